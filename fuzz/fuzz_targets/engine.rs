@@ -0,0 +1,144 @@
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use orderbook::command::{Command, FlushPolicy};
+use orderbook::engine::MatchingEngine;
+use orderbook::order::{Order, PegReference, PostOnly, Side, Trail};
+use orderbook::session::Session;
+use orderbook::MatchingMode;
+
+// A run of the engine: its mode and the commands it processes
+#[derive(Arbitrary, Debug)]
+struct Input {
+    reject: bool,
+    steps: Vec<Step>,
+}
+
+// A command with the order IDs left out, they're numbered as the orders come
+// since the engine expects them to be unique. Prices and quantities are kept
+// small, so that orders often share a level and cross the book
+#[derive(Arbitrary, Debug)]
+enum Step {
+    New {
+        other: bool,
+        user_id: u8,
+        bid: bool,
+        price: u8,
+        quantity: u8,
+        kind: Kind,
+    },
+    Cancel {
+        order_id: u8,
+    },
+    Session {
+        other: bool,
+        session: u8,
+        time: Option<u8>,
+    },
+    Clock {
+        time: u8,
+    },
+    Flush {
+        close: bool,
+    },
+}
+
+// The kind of a new order, on top of its limit price and quantity
+#[derive(Arbitrary, Debug)]
+enum Kind {
+    Limit,
+    Market,
+    Iceberg(u8),
+    PostOnly(bool),
+    Stop(u8),
+    Trailing(u8, bool),
+    Pegged(u8, i8),
+    MinQuantity(u8),
+    AllOrNone,
+}
+
+const SESSIONS: [Session; 7] = [
+    Session::PreOpen,
+    Session::OpeningAuction,
+    Session::Continuous,
+    Session::VolatilityAuction,
+    Session::Halted,
+    Session::ClosingAuction,
+    Session::Closed,
+];
+
+// Build the command of a step, new orders get the next ID
+fn command(step: Step, next_id: &mut usize) -> Command {
+    let symbol = |other: bool| String::from(if other { "AAPL" } else { "IBM" });
+
+    match step {
+        Step::New { other, user_id, bid, price, quantity, kind } => {
+            let side = if bid { Side::Bid } else { Side::Ask };
+            let price = u32::from(price % 16);
+            let order = Order::new(
+                *next_id,
+                usize::from(user_id % 4),
+                side,
+                price,
+                quantity.into(),
+            );
+            *next_id += 1;
+
+            let order = match kind {
+                Kind::Limit => order,
+                Kind::Market => order.with_market(),
+                Kind::Iceberg(display) => order.with_display(display.into()),
+                Kind::PostOnly(false) => order.with_post_only(PostOnly::Reject),
+                Kind::PostOnly(true) => order.with_post_only(PostOnly::Slide),
+                Kind::Stop(stop) => order.with_stop(u32::from(stop % 16)),
+                Kind::Trailing(trail, false) => order.with_trail(Trail::Offset(trail.into())),
+                Kind::Trailing(trail, true) => order.with_trail(Trail::Percent(trail.into())),
+                Kind::Pegged(reference, offset) => {
+                    let reference = match reference % 3 {
+                        0 => PegReference::Primary,
+                        1 => PegReference::Market,
+                        _ => PegReference::Midpoint,
+                    };
+                    order.with_peg(reference, i32::from(offset % 4))
+                }
+                Kind::MinQuantity(min) => order.with_min_quantity(min.into()),
+                Kind::AllOrNone => order.with_all_or_none(),
+            };
+
+            Command::New { symbol: symbol(other), order }
+        }
+        Step::Cancel { order_id } => Command::Cancel { user_id: 1, order_id: order_id.into() },
+        Step::Session { other, session, time } => Command::Session {
+            symbol: symbol(other),
+            session: SESSIONS[usize::from(session) % SESSIONS.len()],
+            time: time.map(u64::from),
+        },
+        Step::Clock { time } => Command::Clock { time: time.into() },
+        Step::Flush { close: false } => Command::Flush { policy: FlushPolicy::Reset },
+        Step::Flush { close: true } => Command::Flush { policy: FlushPolicy::Close },
+    }
+}
+
+// Structured commands go through the engine, which has to keep its books
+// consistent after each of them, see `MatchingEngine::check_invariants`
+fuzz_target!(|input: Input| {
+    let mode = if input.reject {
+        MatchingMode::Reject
+    } else {
+        MatchingMode::Trade
+    };
+    let mut engine = MatchingEngine::new().with_mode(mode);
+    let mut next_id = 1;
+
+    for step in input.steps {
+        let command = command(step, &mut next_id);
+        let description = format!("{:?}", command);
+
+        engine.process(command);
+
+        if let Err(error) = engine.check_invariants() {
+            panic!("{} after {}", error, description);
+        }
+    }
+});
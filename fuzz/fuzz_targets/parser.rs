@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orderbook::parser::{csv_reader, format_command, parse_record, CommandReader};
+
+// Arbitrary bytes go through the command reader, which has to report the
+// records it can't parse rather than panic. Every command it reads has to come
+// back the same once formatted and parsed again, as the journal relies on it
+fuzz_target!(|data: &[u8]| {
+    for command in CommandReader::new("fuzz", data).flatten() {
+        let text = format_command(&command);
+        let record = csv_reader(text.as_bytes())
+            .records()
+            .next()
+            .expect("a formatted command is a record")
+            .expect("a formatted command can be read");
+
+        assert_eq!(parse_record(&record).as_ref(), Ok(&command), "{}", text);
+    }
+});
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use crate::order::Order;
+
+/// Policy used to spread an incoming quantity across the resting orders of a
+/// price level.
+///
+/// With `Fifo` orders are filled in time priority. With `ProRata` the quantity
+/// is spread in proportion to the shown quantity of each order, rounded down,
+/// and shares smaller than `min_allocation` are dropped; when `top_order` is
+/// set the first order of the level is filled before the others. With
+/// `FifoLmm` the orders of the lead market maker `user_id` are filled first,
+/// up to `share` percent of the incoming quantity. Whatever is left after the
+/// priority and pro-rata steps is always allocated in time priority.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Allocation {
+    Fifo,
+    ProRata {
+        top_order: bool,
+        min_allocation: u32,
+    },
+    FifoLmm {
+        user_id: usize,
+        share: u32,
+    },
+}
+
+impl Default for Allocation {
+    fn default() -> Self {
+        Self::Fifo
+    }
+}
+
+impl Allocation {
+    /// Build a `FifoLmm` policy, as long as the share is a percentage.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::allocation::Allocation;
+    ///
+    /// assert!(Allocation::fifo_lmm(7, 40).is_some());
+    /// assert!(Allocation::fifo_lmm(7, 101).is_none());
+    /// ```
+    #[must_use]
+    pub fn fifo_lmm(user_id: usize, share: u32) -> Option<Self> {
+        (share <= 100).then(|| Self::FifoLmm { user_id, share })
+    }
+
+    /// Compute how much of `quantity` goes to each order of the queue, in the
+    /// same sequence. Only the shown quantity of an order can be allocated, and
+    /// orders with a minimum quantity, or all-or-none, get nothing unless their
+    /// minimum is met. This method has *O*(*n*) complexity, where *n* is the
+    /// length of the queue.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::VecDeque;
+    ///
+    /// use orderbook::allocation::Allocation;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let orders = VecDeque::from(vec![
+    ///     Order::new(1, 1, Side::Ask, 10, 100),
+    ///     Order::new(2, 1, Side::Ask, 10, 300),
+    /// ]);
+    /// let pro_rata = Allocation::ProRata { top_order: false, min_allocation: 0 };
+    ///
+    /// assert_eq!(pro_rata.allocate(&orders, 40), vec![10, 30]);
+    /// assert_eq!(Allocation::Fifo.allocate(&orders, 40), vec![40, 0]);
+    /// ```
+    #[must_use]
+    pub fn allocate(&self, orders: &VecDeque<Order>, quantity: u32) -> Vec<u32> {
+        let mut allocations = vec![0; orders.len()];
+        let mut remaining = quantity;
+
+        match *self {
+            Self::Fifo => {}
+            Self::ProRata { top_order, min_allocation } => {
+                if top_order {
+                    if let Some(order) = orders.front() {
+                        Self::give(&mut allocations[0], order, &mut remaining, u32::MAX);
+                    }
+                }
+
+                // Shares are computed on what is still available after the
+                // top order, in 64 bits to avoid overflowing the products
+                let pool = u64::from(remaining);
+                let total: u64 = orders
+                    .iter()
+                    .zip(&allocations)
+                    .map(|(order, allocated)| u64::from(order.quantity - allocated))
+                    .sum();
+
+                for (order, allocated) in orders.iter().zip(allocations.iter_mut()) {
+                    let available = u64::from(order.quantity - *allocated);
+                    let share = (available * pool).checked_div(total).unwrap_or(0) as u32;
+
+                    if share >= min_allocation {
+                        Self::give(allocated, order, &mut remaining, share);
+                    }
+                }
+            }
+            Self::FifoLmm { user_id, share } => {
+                // A share above 100% can't give more than the whole quantity
+                let lmm = u64::from(quantity) * u64::from(share.min(100)) / 100;
+                let mut lmm = u32::try_from(lmm).unwrap_or(quantity);
+
+                for (order, allocated) in orders.iter().zip(allocations.iter_mut()) {
+                    if order.user_id == user_id {
+                        lmm -= Self::give(allocated, order, &mut remaining, lmm);
+                    }
+                }
+            }
+        }
+
+        // Rounding leftovers and plain FIFO go in time priority
+        for (order, allocated) in orders.iter().zip(allocations.iter_mut()) {
+            if remaining == 0 {
+                break;
+            }
+
+            Self::give(allocated, order, &mut remaining, u32::MAX);
+        }
+
+        allocations
+    }
+
+    /// Allocate up to `quantity` more to an order, within its shown quantity
+    /// and the remaining one, and return the allocated amount. Nothing is
+    /// allocated when the total would not meet the minimum of the order.
+    fn give(allocated: &mut u32, order: &Order, remaining: &mut u32, quantity: u32) -> u32 {
+        let given = quantity.min(*remaining).min(order.quantity - *allocated);
+
+        if given == 0 || *allocated + given < order.min_fill() {
+            return 0;
+        }
+
+        *allocated += given;
+        *remaining -= given;
+        given
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::order::Side;
+
+    use super::*;
+
+    fn queue(quantities: &[u32]) -> VecDeque<Order> {
+        quantities
+            .iter()
+            .enumerate()
+            .map(|(id, &quantity)| Order::new(id, id, Side::Ask, 10, quantity))
+            .collect()
+    }
+
+    #[test]
+    fn test_allocate_pro_rata() {
+        let orders = queue(&[10, 20, 70]);
+        let pro_rata = Allocation::ProRata { top_order: false, min_allocation: 0 };
+
+        // Rounding leftovers go to the oldest orders
+        assert_eq!(pro_rata.allocate(&orders, 15), vec![2, 3, 10]);
+        assert_eq!(pro_rata.allocate(&orders, 200), vec![10, 20, 70]);
+    }
+
+    #[test]
+    fn test_allocate_pro_rata_min_allocation() {
+        let orders = queue(&[10, 20, 70]);
+        let pro_rata = Allocation::ProRata { top_order: false, min_allocation: 4 };
+
+        // The smallest shares are dropped and handed out in time priority
+        assert_eq!(pro_rata.allocate(&orders, 15), vec![5, 0, 10]);
+    }
+
+    #[test]
+    fn test_allocate_pro_rata_top_order() {
+        let orders = queue(&[10, 20, 70]);
+        let pro_rata = Allocation::ProRata { top_order: true, min_allocation: 0 };
+
+        assert_eq!(pro_rata.allocate(&orders, 28), vec![10, 4, 14]);
+    }
+
+    #[test]
+    fn test_allocate_pro_rata_min_quantity() {
+        let mut orders = queue(&[10, 20, 70]);
+        orders[2] = orders[2].with_min_quantity(20);
+        let pro_rata = Allocation::ProRata { top_order: false, min_allocation: 0 };
+
+        assert_eq!(pro_rata.allocate(&orders, 15), vec![10, 5, 0]);
+    }
+
+    #[test]
+    fn test_allocate_fifo_lmm() {
+        let orders = queue(&[10, 20, 70]);
+        let lmm = Allocation::FifoLmm { user_id: 2, share: 40 };
+
+        assert_eq!(lmm.allocate(&orders, 50), vec![10, 20, 20]);
+        assert_eq!(Allocation::Fifo.allocate(&orders, 50), vec![10, 20, 20]);
+        assert_eq!(lmm.allocate(&orders, 20), vec![10, 2, 8]);
+
+        let lmm = Allocation::FifoLmm { user_id: 2, share: u32::MAX };
+        assert_eq!(lmm.allocate(&orders, u32::MAX), vec![10, 20, 70]);
+    }
+}
@@ -0,0 +1,180 @@
+use std::cmp::Ordering;
+
+use crate::order::Side;
+
+/// The price an auction would uncross at, along with the volume it would
+/// execute and the quantity left unmatched on the side with a surplus. Both
+/// add up the quantities of many price levels, so they're wider than a single
+/// order quantity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clearing {
+    pub price: u32,
+    pub volume: u64,
+    pub imbalance: u64,
+    pub side: Option<Side>,
+}
+
+/// Compute the clearing price of an auction from the depth of both sides,
+/// given as `(price, quantity)` pairs sorted by ascending price. Every price of
+/// the book is a candidate, the chosen one:
+///
+/// 1. maximises the executed volume;
+/// 2. then minimises the imbalance;
+/// 3. then follows the market pressure, the highest price when every
+///    remaining candidate has a buy surplus and the lowest one when they all
+///    have a sell surplus;
+/// 4. then is the closest to the reference price, the lowest one on ties or
+///    without a reference price.
+///
+/// There's no clearing price when the book isn't crossed. The complexity for
+/// this operation is *O*(*n* log *n*), where *n* is the number of prices.
+///
+/// # Example
+/// ```
+/// use orderbook::auction::clearing_price;
+/// use orderbook::order::Side;
+///
+/// let bids = [(10, 100), (11, 50)];
+/// let asks = [(9, 80), (12, 100)];
+/// let clearing = clearing_price(&bids, &asks, None).unwrap();
+///
+/// assert_eq!((clearing.price, clearing.volume), (10, 80));
+/// assert_eq!((clearing.imbalance, clearing.side), (70, Some(Side::Bid)));
+/// ```
+#[must_use]
+pub fn clearing_price(
+    bids: &[(u32, u32)],
+    asks: &[(u32, u32)],
+    reference: Option<u32>,
+) -> Option<Clearing> {
+    let mut prices: Vec<u32> = bids.iter().chain(asks).map(|&(price, _)| price).collect();
+    prices.sort_unstable();
+    prices.dedup();
+
+    // Sell quantity at or below each price, accumulated upwards
+    let mut supply = Vec::with_capacity(prices.len());
+    let (mut total, mut next) = (0_u64, 0);
+    for &price in &prices {
+        while next < asks.len() && asks[next].0 <= price {
+            total += u64::from(asks[next].1);
+            next += 1;
+        }
+        supply.push(total);
+    }
+
+    // Buy quantity at or above each price, accumulated downwards
+    let mut demand = vec![0; prices.len()];
+    let (mut total, mut next) = (0_u64, bids.len());
+    for (pos, &price) in prices.iter().enumerate().rev() {
+        while next > 0 && bids[next - 1].0 >= price {
+            total += u64::from(bids[next - 1].1);
+            next -= 1;
+        }
+        demand[pos] = total;
+    }
+
+    let candidates: Vec<Clearing> = prices
+        .iter()
+        .zip(supply.iter().zip(&demand))
+        .map(|(&price, (&supply, &demand))| Clearing {
+            price,
+            volume: supply.min(demand),
+            imbalance: supply.max(demand) - supply.min(demand),
+            side: match demand.cmp(&supply) {
+                Ordering::Greater => Some(Side::Bid),
+                Ordering::Less => Some(Side::Ask),
+                Ordering::Equal => None,
+            },
+        })
+        .filter(|clearing| clearing.volume > 0)
+        .collect();
+
+    let volume = candidates.iter().map(|c| c.volume).max()?;
+    let candidates: Vec<Clearing> = candidates
+        .into_iter()
+        .filter(|c| c.volume == volume)
+        .collect();
+
+    let imbalance = candidates.iter().map(|c| c.imbalance).min()?;
+    let candidates: Vec<Clearing> = candidates
+        .into_iter()
+        .filter(|c| c.imbalance == imbalance)
+        .collect();
+
+    // Candidates are sorted by ascending price
+    if candidates.iter().all(|c| c.side == Some(Side::Bid)) {
+        return candidates.last().copied();
+    }
+
+    if candidates.iter().all(|c| c.side == Some(Side::Ask)) {
+        return candidates.first().copied();
+    }
+
+    let reference = reference.unwrap_or(candidates[0].price);
+    candidates
+        .into_iter()
+        .min_by_key(|c| (c.price.max(reference) - c.price.min(reference), c.price))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clearing_price_uncrossed() {
+        assert_eq!(clearing_price(&[(10, 100)], &[(11, 100)], None), None);
+        assert_eq!(clearing_price(&[], &[(11, 100)], Some(11)), None);
+    }
+
+    #[test]
+    fn test_clearing_price_max_volume() {
+        let bids = [(9, 50), (10, 30), (12, 20)];
+        let asks = [(8, 40), (10, 40), (11, 100)];
+        let clearing = clearing_price(&bids, &asks, None).unwrap();
+
+        assert_eq!((clearing.price, clearing.volume), (10, 50));
+        assert_eq!((clearing.imbalance, clearing.side), (30, Some(Side::Ask)));
+    }
+
+    #[test]
+    fn test_clearing_price_market_pressure() {
+        // Every candidate has a buy surplus, the highest price wins
+        let bids = [(12, 100)];
+        let asks = [(10, 50)];
+        let clearing = clearing_price(&bids, &asks, None).unwrap();
+
+        assert_eq!(clearing.price, 12);
+
+        // Every candidate has a sell surplus, the lowest price wins
+        let bids = [(12, 50)];
+        let asks = [(9, 100)];
+        let clearing = clearing_price(&bids, &asks, None).unwrap();
+
+        assert_eq!(clearing.price, 9);
+    }
+
+    #[test]
+    fn test_clearing_price_reference() {
+        let bids = [(12, 100)];
+        let asks = [(10, 100)];
+
+        assert_eq!(clearing_price(&bids, &asks, Some(13)).unwrap().price, 12);
+        assert_eq!(clearing_price(&bids, &asks, Some(11)).unwrap().price, 10);
+        assert_eq!(clearing_price(&bids, &asks, Some(9)).unwrap().price, 10);
+        assert_eq!(clearing_price(&bids, &asks, None).unwrap().price, 10);
+    }
+
+    #[test]
+    fn test_clearing_price_large_quantities() {
+        // The accumulated quantities go past the largest order quantity
+        let bids = [(11, 5), (12, u32::MAX)];
+        let asks = [(10, u32::MAX), (11, 5)];
+        let clearing = clearing_price(&bids, &asks, None).unwrap();
+
+        assert_eq!(
+            (clearing.price, clearing.volume),
+            (11, u64::from(u32::MAX) + 5)
+        );
+        assert_eq!((clearing.imbalance, clearing.side), (0, None));
+    }
+}
@@ -0,0 +1,801 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use crate::command::Command;
+use crate::order::{Order, Side};
+use crate::order_book::{MatchingMode, OrderBook, OrderOutcome, RejectReason};
+use crate::scheduler::Scheduler;
+
+/// A trading strategy under test. It sees every outcome of the book, as the
+/// market data it would receive, and it's told apart from the outcomes of its
+/// own orders, including the ones the book amends, and from its executions.
+/// Each callback can request new orders, amendments and cancels through the
+/// provided actions, which the harness sends to the book right after it, in
+/// order.
+///
+/// Every callback but the market one does nothing by default.
+pub trait Strategy {
+    /// An outcome of someone else's order, or of the book itself, as with
+    /// session transitions and auctions.
+    fn on_market(&mut self, order_book: &OrderBook, outcome: &OrderOutcome, actions: &mut Actions);
+
+    /// An outcome of one of the orders, cancels or amendments of the strategy.
+    fn on_outcome(
+        &mut self,
+        _order_book: &OrderBook,
+        _outcome: &OrderOutcome,
+        _actions: &mut Actions,
+    ) {
+    }
+
+    /// An execution of one of the orders of the strategy, it comes right after
+    /// the outcome that carries it.
+    fn on_fill(&mut self, _order_book: &OrderBook, _fill: &Execution, _actions: &mut Actions) {}
+}
+
+/// What a strategy asks the harness to do once its callback returns.
+#[derive(Clone, Debug, PartialEq)]
+enum Request {
+    Submit(Order),
+    Amend {
+        order_id: usize,
+        price: u32,
+        quantity: u32,
+    },
+    Cancel {
+        order_id: usize,
+    },
+}
+
+/// The requests of a strategy, waiting to be carried out. Orders of the
+/// strategy all belong to the same user, and they're numbered down from the
+/// largest ID, so that they never clash with the orders of the flow.
+#[derive(Debug)]
+pub struct Actions {
+    user_id: usize,
+    next_id: usize,
+    requests: VecDeque<Request>,
+}
+
+impl Actions {
+    /// Submit a new limit order, and return its ID.
+    pub fn submit(&mut self, side: Side, price: u32, quantity: u32) -> usize {
+        self.submit_order(Order::new(0, 0, side, price, quantity))
+    }
+
+    /// Submit a new market order, and return its ID.
+    pub fn market(&mut self, side: Side, quantity: u32) -> usize {
+        self.submit_order(Order::new(0, 0, side, 0, quantity).with_market())
+    }
+
+    /// Submit a new order with its attributes, as an iceberg or a pegged order,
+    /// and return its ID. The IDs of the provided order are replaced by the
+    /// ones of the strategy.
+    pub fn submit_order(&mut self, order: Order) -> usize {
+        let order_id = self.next_id;
+        self.next_id -= 1;
+
+        let order = Order { id: order_id, user_id: self.user_id, ..order };
+        self.requests.push_back(Request::Submit(order));
+
+        order_id
+    }
+
+    /// Change the price and the remaining quantity of a resting order. The
+    /// order is cancelled and submitted again as a plain limit order with the
+    /// same ID, so that it loses its time priority, whatever changed.
+    pub fn amend(&mut self, order_id: usize, price: u32, quantity: u32) {
+        self.requests
+            .push_back(Request::Amend { order_id, price, quantity });
+    }
+
+    /// Cancel a resting order.
+    pub fn cancel(&mut self, order_id: usize) {
+        self.requests.push_back(Request::Cancel { order_id });
+    }
+}
+
+/// An execution of an order of the strategy: passive when the order was
+/// resting on the book, or traded in an auction uncross, aggressive when it
+/// took liquidity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Execution {
+    pub order_id: usize,
+    pub side: Side,
+    pub price: u32,
+    pub quantity: u32,
+    pub passive: bool,
+}
+
+/// Where the orders of the strategy joined the queue of their price level:
+/// how many times they rested on the book, amendments included, the orders
+/// and the shown volume ahead of them in total, and how many of them were then
+/// filled, at least in part.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueueStats {
+    pub rested: usize,
+    pub filled: usize,
+    pub orders_ahead: u64,
+    pub volume_ahead: u64,
+}
+
+impl QueueStats {
+    /// The average shown volume ahead of a resting order when it joined the
+    /// queue, if any order rested.
+    #[must_use]
+    pub fn average_volume_ahead(&self) -> Option<f64> {
+        (self.rested > 0).then(|| self.volume_ahead as f64 / self.rested as f64)
+    }
+
+    /// The share of the resting orders that were filled, if any order rested.
+    #[must_use]
+    pub fn fill_ratio(&self) -> Option<f64> {
+        (self.rested > 0).then(|| self.filled as f64 / self.rested as f64)
+    }
+}
+
+/// The results of a strategy so far: its executions, its position, the cash
+/// it spent or received, and the last trade price of the book, which marks the
+/// position to market.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub fills: Vec<Execution>,
+    pub position: i64,
+    pub cash: i64,
+    pub mark: Option<u32>,
+    pub queue: QueueStats,
+}
+
+impl Report {
+    /// The traded quantity, both sides included.
+    #[must_use]
+    pub fn volume(&self) -> u64 {
+        self.fills.iter().map(|fill| u64::from(fill.quantity)).sum()
+    }
+
+    /// The profit and loss of the strategy: its cash along with the value of
+    /// its position at the mark price.
+    #[must_use]
+    pub fn pnl(&self) -> i64 {
+        self.cash + self.position * i64::from(self.mark.unwrap_or_default())
+    }
+
+    /// Account for an execution.
+    fn record(&mut self, fill: Execution) {
+        let (quantity, price) = (i64::from(fill.quantity), i64::from(fill.price));
+
+        match fill.side {
+            Side::Bid => {
+                self.position += quantity;
+                self.cash -= quantity * price;
+            }
+            Side::Ask => {
+                self.position -= quantity;
+                self.cash += quantity * price;
+            }
+        }
+
+        self.fills.push(fill);
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mark = self.mark.map_or(String::from("-"), |mark| mark.to_string());
+        let decimal =
+            |value: Option<f64>| value.map_or(String::from("-"), |value| format!("{value:.2}"));
+
+        writeln!(f, "fills: {}, volume: {}", self.fills.len(), self.volume())?;
+        writeln!(
+            f,
+            "position: {}, cash: {}, pnl: {} at {}",
+            self.position,
+            self.cash,
+            self.pnl(),
+            mark
+        )?;
+        write!(
+            f,
+            "queue: {} rested, {} filled ({}), {} volume ahead on average",
+            self.queue.rested,
+            self.queue.filled,
+            decimal(self.queue.fill_ratio()),
+            decimal(self.queue.average_volume_ahead())
+        )
+    }
+}
+
+/// Simulated latencies of a user, in units of the clock: on the way to the
+/// book for its orders and cancels, and on the way back for the outcomes it
+/// receives, market data included.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Latency {
+    pub inbound: u64,
+    pub outbound: u64,
+}
+
+impl Latency {
+    #[must_use]
+    pub const fn new(inbound: u64, outbound: u64) -> Self {
+        Self { inbound, outbound }
+    }
+}
+
+/// What travels between the users and the book: commands of the flow and
+/// requests of the strategy on their way in, outcomes along with the
+/// executions they carry on their way back to the strategy.
+enum Event {
+    Command(Command),
+    Request(Request),
+    Outcome(OrderOutcome, Vec<Execution>),
+}
+
+/// Runs a strategy against the book of a symbol, interleaving its orders with
+/// a flow of commands, either historical ones read from a file, once the
+/// records of a `CommandReader` are parsed, or generated ones.
+///
+/// Commands travel to the book, and outcomes back to the strategy, with the
+/// simulated latencies of their users, and a scheduler has them arrive in the
+/// order of their arrival time, then in the order they were sent. Time is
+/// kept by the clock commands of the flow: the other commands are sent at the
+/// time of the last clock, and the events due by each clock arrive before it
+/// moves the book forward. Without latencies, the default, the strategy sees
+/// the outcomes of each command, and its requests are carried out, before the
+/// next command.
+///
+/// Only the commands for the symbol of the book reach it, along with the
+/// cancels of its orders and the clock, while flushes are ignored. The book
+/// the strategy is handed is the one at the time the outcome arrives, which
+/// can be ahead of the outcome when there's latency on the way back.
+///
+/// # Example
+/// ```
+/// use orderbook::backtest::{Actions, Backtest, Latency, Strategy};
+/// use orderbook::generator::Generator;
+/// use orderbook::order::Side;
+/// use orderbook::order_book::{OrderBook, OrderOutcome};
+///
+/// // Buy once, as soon as there's someone to sell
+/// struct BuyOnce(bool);
+///
+/// impl Strategy for BuyOnce {
+///     fn on_market(&mut self, order_book: &OrderBook, _: &OrderOutcome, actions: &mut Actions) {
+///         if !self.0 && order_book.best_ask_price().is_some() {
+///             actions.market(Side::Bid, 1);
+///             self.0 = true;
+///         }
+///     }
+/// }
+///
+/// let mut backtest = Backtest::new("SYM1", BuyOnce(false)).with_latency(Latency::new(2, 1));
+/// backtest.run(Generator::new().with_seed(1).take(100));
+///
+/// assert_eq!(backtest.report().position, 1);
+/// ```
+pub struct Backtest<S> {
+    symbol: String,
+    order_book: OrderBook,
+    strategy: S,
+    actions: Actions,
+    report: Report,
+    queued: HashSet<usize>,
+    latency: Latency,
+    latencies: HashMap<usize, Latency>,
+    scheduler: Scheduler<Event>,
+    now: u64,
+}
+
+impl<S: Strategy> Backtest<S> {
+    /// A harness for the book of the symbol, with the default settings but for
+    /// crossing orders, which trade, where the strategy trades as user zero.
+    #[must_use]
+    pub fn new(symbol: &str, strategy: S) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            order_book: OrderBook::new().with_mode(MatchingMode::Trade),
+            strategy,
+            actions: Actions {
+                user_id: 0,
+                next_id: usize::MAX,
+                requests: VecDeque::new(),
+            },
+            report: Report::default(),
+            queued: HashSet::new(),
+            latency: Latency::default(),
+            latencies: HashMap::new(),
+            scheduler: Scheduler::new(),
+            now: 0,
+        }
+    }
+
+    /// Use the provided book, with its mode, rules and state, instead of an
+    /// empty one.
+    #[must_use]
+    pub fn with_order_book(mut self, order_book: OrderBook) -> Self {
+        self.order_book = order_book;
+        self
+    }
+
+    /// Trade as the provided user, which the flow shouldn't use.
+    #[must_use]
+    pub fn with_user_id(mut self, user_id: usize) -> Self {
+        self.actions.user_id = user_id;
+        self
+    }
+
+    /// Use the provided latencies for every user without their own ones.
+    #[must_use]
+    pub fn with_latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Use the provided latencies for a user, either the strategy or one of
+    /// the flow. Outcomes only travel back to the strategy, the flow doesn't
+    /// listen.
+    #[must_use]
+    pub fn with_user_latency(mut self, user_id: usize, latency: Latency) -> Self {
+        self.latencies.insert(user_id, latency);
+        self
+    }
+
+    #[must_use]
+    pub const fn order_book(&self) -> &OrderBook {
+        &self.order_book
+    }
+
+    #[must_use]
+    pub const fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    #[must_use]
+    pub const fn report(&self) -> &Report {
+        &self.report
+    }
+
+    /// The simulated time, as of the last event.
+    #[must_use]
+    pub const fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Process every command of the flow, let every event still on its way
+    /// arrive, and return the report.
+    pub fn run(&mut self, flow: impl IntoIterator<Item = Command>) -> &Report {
+        for command in flow {
+            self.process(command);
+        }
+        self.finish();
+
+        &self.report
+    }
+
+    /// Send a command of the flow, then let every event due by now arrive.
+    /// Clock commands move time forward, once the events due by then have
+    /// arrived.
+    pub fn process(&mut self, command: Command) {
+        let user_id = match &command {
+            Command::New { order, .. } => Some(order.user_id),
+            Command::Cancel { user_id, .. } => Some(*user_id),
+            _ => None,
+        };
+
+        match command {
+            Command::Clock { time } => {
+                self.arrive(time);
+
+                self.now = self.now.max(time);
+                let outcomes = self.order_book.advance(self.now);
+                self.publish(&outcomes);
+            }
+            command => {
+                let latency = user_id.map_or(0, |user_id| self.latency(user_id).inbound);
+                self.scheduler
+                    .schedule(self.now.saturating_add(latency), Event::Command(command));
+            }
+        }
+
+        self.arrive(self.now);
+    }
+
+    /// Let every event still on its way arrive, whatever its time.
+    pub fn finish(&mut self) {
+        self.arrive(u64::MAX);
+    }
+
+    /// The latencies of a user.
+    fn latency(&self, user_id: usize) -> Latency {
+        self.latencies
+            .get(&user_id)
+            .copied()
+            .unwrap_or(self.latency)
+    }
+
+    /// Let the events due by the provided time arrive in order, along with
+    /// the ones they lead to. The book moves forward to the time of each.
+    fn arrive(&mut self, time: u64) {
+        while let Some((time, event)) = self.scheduler.pop_until(time) {
+            if time > self.now {
+                self.now = time;
+                let outcomes = self.order_book.advance(time);
+                self.publish(&outcomes);
+            }
+
+            match event {
+                Event::Command(command) => {
+                    let outcomes = self.apply(command);
+                    self.publish(&outcomes);
+                }
+                Event::Request(request) => {
+                    let outcomes = self.execute(request);
+                    self.publish(&outcomes);
+                }
+                Event::Outcome(outcome, fills) => self.notify(&outcome, &fills),
+            }
+        }
+    }
+
+    /// Process a command of the flow that reached the book, and return its
+    /// outcomes.
+    fn apply(&mut self, command: Command) -> Vec<OrderOutcome> {
+        let order_book = &mut self.order_book;
+
+        match command {
+            Command::New { symbol, order } if symbol == self.symbol => order_book.submit(order),
+            Command::Cancel { user_id, order_id }
+                if order_book.order_status(order_id).is_some() =>
+            {
+                order_book.cancel_order(user_id, order_id)
+            }
+            Command::Session { symbol, session, time } if symbol == self.symbol => match time {
+                Some(time) => {
+                    order_book.schedule(time, session);
+                    Vec::new()
+                }
+                None => order_book.transition(session),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Carry out a request of the strategy, and return its outcomes.
+    fn execute(&mut self, request: Request) -> Vec<OrderOutcome> {
+        let user_id = self.actions.user_id;
+        let order_book = &mut self.order_book;
+
+        let order_id = match request {
+            Request::Submit(order) => order.id,
+            Request::Cancel { order_id } | Request::Amend { order_id, .. } => order_id,
+        };
+        let order = match (request, order_book.order_status(order_id)) {
+            (Request::Submit(order), _) => order,
+            (Request::Cancel { .. }, Some(_)) => {
+                self.queued.remove(&order_id);
+                return order_book.cancel_order(user_id, order_id);
+            }
+            (Request::Amend { price, quantity, .. }, Some(order)) => {
+                let outcomes = order_book.cancel_order(user_id, order_id);
+
+                // The order stays as it is when it can't be cancelled
+                if order_book.order_status(order_id).is_some() {
+                    return outcomes;
+                }
+
+                let order = Order::new(order_id, user_id, order.side, price, quantity);
+                return [outcomes, self.place(order)].concat();
+            }
+            (_, None) => {
+                let reason = RejectReason::UnknownOrder;
+                return vec![OrderOutcome::Rejected { user_id, order_id, reason }];
+            }
+        };
+
+        self.place(order)
+    }
+
+    /// Submit an order of the strategy, and return its outcomes. Orders that
+    /// rest on the book join the back of their queue.
+    fn place(&mut self, order: Order) -> Vec<OrderOutcome> {
+        let order_id = order.id;
+        let outcomes = self.order_book.submit(order);
+
+        if let Some((orders, volume)) = self.order_book.queue_position(order_id) {
+            let queue = &mut self.report.queue;
+
+            queue.rested += 1;
+            queue.orders_ahead += orders as u64;
+            queue.volume_ahead += u64::from(volume);
+            self.queued.insert(order_id);
+        }
+
+        outcomes
+    }
+
+    /// Account for the executions of the strategy in the outcomes, as they
+    /// happen on the book, and send each outcome back to the strategy along
+    /// with its executions.
+    fn publish(&mut self, outcomes: &[OrderOutcome]) {
+        let time = self
+            .now
+            .saturating_add(self.latency(self.actions.user_id).outbound);
+
+        for outcome in outcomes {
+            let fills = self.fills(outcome);
+
+            for &fill in &fills {
+                if fill.passive && self.queued.remove(&fill.order_id) {
+                    self.report.queue.filled += 1;
+                }
+                self.report.record(fill);
+            }
+            self.report.mark = self.order_book.last_price();
+
+            self.scheduler
+                .schedule(time, Event::Outcome(outcome.clone(), fills));
+        }
+    }
+
+    /// Hand an outcome that arrived over to the strategy, followed by its
+    /// executions, and send the requests of the strategy to the book.
+    fn notify(&mut self, outcome: &OrderOutcome, fills: &[Execution]) {
+        let actions = &mut self.actions;
+
+        if owner(outcome) == Some(actions.user_id) {
+            self.strategy.on_outcome(&self.order_book, outcome, actions);
+        } else {
+            self.strategy.on_market(&self.order_book, outcome, actions);
+        }
+
+        for fill in fills {
+            self.strategy.on_fill(&self.order_book, fill, actions);
+        }
+
+        let time = self
+            .now
+            .saturating_add(self.latency(self.actions.user_id).inbound);
+        while let Some(request) = self.actions.requests.pop_front() {
+            self.scheduler.schedule(time, Event::Request(request));
+        }
+    }
+
+    /// The executions of the strategy carried by an outcome, a strategy
+    /// trading with itself executes on both sides.
+    fn fills(&self, outcome: &OrderOutcome) -> Vec<Execution> {
+        let user_id = self.actions.user_id;
+        let aggressor = match outcome {
+            OrderOutcome::Traded { order_id, .. } => Some(*order_id),
+            _ => None,
+        };
+        let mut fills = Vec::new();
+
+        for trade in outcome.trades() {
+            let sides = [
+                (trade.user_id_buy, trade.order_id_buy, Side::Bid),
+                (trade.user_id_sell, trade.order_id_sell, Side::Ask),
+            ];
+
+            for &(owner, order_id, side) in sides.iter() {
+                if owner == user_id {
+                    fills.push(Execution {
+                        order_id,
+                        side,
+                        price: trade.price,
+                        quantity: trade.quantity,
+                        passive: aggressor != Some(order_id),
+                    });
+                }
+            }
+        }
+
+        fills
+    }
+}
+
+/// The user whose order, cancel or amendment an outcome is about, if any.
+fn owner(outcome: &OrderOutcome) -> Option<usize> {
+    match outcome {
+        OrderOutcome::Rejected { user_id, .. }
+        | OrderOutcome::Created { user_id, .. }
+        | OrderOutcome::Cancelled { user_id, .. }
+        | OrderOutcome::TopOfBook { user_id, .. }
+        | OrderOutcome::Traded { user_id, .. }
+        | OrderOutcome::Triggered { user_id, .. }
+        | OrderOutcome::Amended { user_id, .. } => Some(*user_id),
+        OrderOutcome::Transitioned { .. }
+        | OrderOutcome::Indicative { .. }
+        | OrderOutcome::Uncrossed { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quote one lot on each side around the book, then requote whenever a
+    // quote is filled or left behind
+    #[derive(Default)]
+    struct Quoter {
+        quotes: Vec<usize>,
+        outcomes: usize,
+        fills: usize,
+    }
+
+    impl Strategy for Quoter {
+        fn on_market(&mut self, order_book: &OrderBook, _: &OrderOutcome, actions: &mut Actions) {
+            let (bid, ask) = match (order_book.best_bid_price(), order_book.best_ask_price()) {
+                (Some(bid), Some(ask)) if ask - bid > 2 => (bid + 1, ask - 1),
+                _ => return,
+            };
+
+            if self.quotes.is_empty() {
+                self.quotes.push(actions.submit(Side::Bid, bid, 10));
+                self.quotes.push(actions.submit(Side::Ask, ask, 10));
+            } else {
+                actions.amend(self.quotes[0], bid, 10);
+                actions.amend(self.quotes[1], ask, 10);
+            }
+        }
+
+        fn on_outcome(&mut self, _: &OrderBook, _: &OrderOutcome, _: &mut Actions) {
+            self.outcomes += 1;
+        }
+
+        fn on_fill(&mut self, _: &OrderBook, _: &Execution, _: &mut Actions) {
+            self.fills += 1;
+        }
+    }
+
+    fn new_order(id: usize, side: Side, price: u32, quantity: u32) -> Command {
+        let order = Order::new(id, 1, side, price, quantity);
+        Command::New { symbol: String::from("IBM"), order }
+    }
+
+    #[test]
+    fn test_quotes_and_fills() {
+        let mut backtest = Backtest::new("IBM", Quoter::default());
+
+        backtest.process(new_order(1, Side::Bid, 10, 100));
+        backtest.process(new_order(2, Side::Ask, 20, 100));
+        let (bid, ask) = (backtest.strategy().quotes[0], backtest.strategy().quotes[1]);
+
+        assert_eq!(backtest.order_book().order_status(bid).unwrap().price, 11);
+        assert_eq!(backtest.order_book().order_status(ask).unwrap().price, 19);
+        assert_eq!(backtest.strategy().outcomes, 2);
+
+        // Someone sells into the bid, which is requoted along with the ask
+        backtest.process(new_order(3, Side::Ask, 11, 4));
+        let report = backtest.report();
+
+        assert_eq!(
+            report.fills,
+            vec![Execution {
+                order_id: bid,
+                side: Side::Bid,
+                price: 11,
+                quantity: 4,
+                passive: true
+            }]
+        );
+        assert_eq!(
+            (report.position, report.cash, report.mark),
+            (4, -44, Some(11))
+        );
+        assert_eq!(report.pnl(), 0);
+        assert_eq!(backtest.strategy().fills, 1);
+        assert_eq!(
+            backtest.order_book().order_status(bid).unwrap().quantity,
+            10
+        );
+
+        // The requoted orders rested at the front of their queue
+        assert_eq!(report.queue.rested, 4);
+        assert_eq!(report.queue.filled, 1);
+        assert_eq!(report.queue.average_volume_ahead(), Some(0.0));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut backtest = Backtest::new("IBM", Quoter::default())
+            .with_order_book(OrderBook::new().with_mode(MatchingMode::Trade))
+            .with_user_id(7);
+
+        backtest.process(new_order(1, Side::Bid, 10, 100));
+        backtest.process(new_order(2, Side::Ask, 13, 100));
+        // Sells 10 to the quoted bid at 11, then the ask at 12 is taken
+        backtest.process(new_order(3, Side::Ask, 11, 10));
+        backtest.process(new_order(4, Side::Bid, 12, 10));
+
+        let report = backtest.report();
+        assert_eq!(report.position, 0);
+        assert_eq!(report.cash, 10);
+        assert_eq!(report.pnl(), 10);
+        assert!(report.fills.iter().all(|fill| fill.passive));
+
+        // The unknown and filled orders of the strategy can't be cancelled
+        backtest.actions.cancel(42);
+        let request = backtest.actions.requests.pop_front().unwrap();
+
+        assert_eq!(
+            backtest.execute(request),
+            vec![OrderOutcome::Rejected {
+                user_id: 7,
+                order_id: 42,
+                reason: RejectReason::UnknownOrder,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_strategy_latency() {
+        let mut backtest =
+            Backtest::new("IBM", Quoter::default()).with_user_latency(0, Latency::new(2, 1));
+
+        backtest.process(new_order(1, Side::Bid, 10, 100));
+        backtest.process(new_order(2, Side::Ask, 20, 100));
+        backtest.process(Command::Clock { time: 1 });
+
+        // The strategy has just seen the book, its quotes are on their way
+        let bid = backtest.strategy().quotes[0];
+        assert_eq!(backtest.order_book().order_status(bid), None);
+
+        backtest.process(Command::Clock { time: 3 });
+        assert_eq!(backtest.order_book().order_status(bid).unwrap().price, 11);
+        assert_eq!(backtest.strategy().outcomes, 0);
+
+        // Both quotes were submitted, then amended as the second outcome came
+        backtest.process(Command::Clock { time: 4 });
+        assert_eq!(backtest.strategy().outcomes, 6);
+
+        // Executions are accounted for right away, the strategy learns later
+        backtest.process(new_order(3, Side::Ask, 11, 4));
+        assert_eq!(backtest.report().position, 4);
+        assert_eq!(backtest.strategy().fills, 0);
+
+        backtest.process(Command::Clock { time: 5 });
+        assert_eq!(backtest.strategy().fills, 1);
+        assert_eq!(backtest.now(), 5);
+    }
+
+    #[test]
+    fn test_flow_latency() {
+        let mut backtest = Backtest::new("IBM", Quoter::default())
+            .with_latency(Latency::new(1, 0))
+            .with_user_latency(1, Latency::new(3, 0));
+        let symbol = String::from("IBM");
+
+        // The sell is sent first, but it arrives last
+        let order = Order::new(1, 1, Side::Ask, 10, 100);
+        backtest.process(Command::New { symbol: symbol.clone(), order });
+        let order = Order::new(2, 2, Side::Bid, 10, 100);
+        backtest.process(Command::New { symbol, order });
+        backtest.process(Command::Clock { time: 2 });
+
+        assert_eq!(backtest.order_book().best_bid_price(), Some(10));
+        assert_eq!(backtest.order_book().best_ask_price(), None);
+
+        backtest.finish();
+        assert_eq!(backtest.order_book().last_price(), Some(10));
+        assert_eq!(backtest.order_book().best_bid_price(), None);
+        assert_eq!(backtest.now(), 3);
+    }
+
+    #[test]
+    fn test_report_display() {
+        let mut report = Report::default();
+        report.record(Execution {
+            order_id: 1,
+            side: Side::Ask,
+            price: 10,
+            quantity: 5,
+            passive: false,
+        });
+        report.mark = Some(12);
+
+        assert_eq!(
+            report.to_string(),
+            "fills: 1, volume: 5\nposition: -5, cash: 50, pnl: -10 at 12\n\
+             queue: 0 rested, 0 filled (-), - volume ahead on average"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+/// A price band around a reference price, as a percentage of the reference
+/// price either way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub percent: u32,
+}
+
+impl Band {
+    #[must_use]
+    pub const fn new(percent: u32) -> Self {
+        Self { percent }
+    }
+
+    /// The lowest and highest prices of the band around the provided reference
+    /// price, both included. The distance is rounded down.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::band::Band;
+    ///
+    /// assert_eq!(Band::new(10).bounds(105), (95, 115));
+    /// ```
+    #[must_use]
+    pub fn bounds(&self, reference: u32) -> (u32, u32) {
+        let distance = (u64::from(reference) * u64::from(self.percent) / 100) as u32;
+
+        (
+            reference.saturating_sub(distance),
+            reference.saturating_add(distance),
+        )
+    }
+
+    /// Whether the price is within the band around the reference price.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::band::Band;
+    ///
+    /// let band = Band::new(5);
+    ///
+    /// assert!(band.contains(100, 105));
+    /// assert!(!band.contains(100, 94));
+    /// ```
+    #[must_use]
+    pub fn contains(&self, reference: u32, price: u32) -> bool {
+        let (low, high) = self.bounds(reference);
+        (low..=high).contains(&price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds() {
+        assert_eq!(Band::new(0).bounds(10), (10, 10));
+        assert_eq!(Band::new(50).bounds(3), (2, 4));
+        assert_eq!(Band::new(200).bounds(10), (0, 30));
+    }
+}
@@ -1,17 +1,83 @@
+use std::collections::BTreeMap;
+
 use rb_tree::RBMap;
 
-use crate::{order::Order, price_level::PriceLevel};
+use crate::{
+    allocation::Allocation,
+    order::{Order, Side},
+    price_level::{Fill, PriceLevel},
+};
+
+/// The map a book side stores its price levels in. Both of them give the same
+/// results, so that one can be checked against the other.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    RedBlackTree,
+    BTree,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::RedBlackTree
+    }
+}
+
+/// The price levels of a book side, in the map of its backend.
+enum Prices {
+    RedBlackTree(RBMap<u32, PriceLevel>),
+    BTree(BTreeMap<u32, PriceLevel>),
+}
 
 /// A single side of an order book, it can represent either the Ask or Bid side
-/// and stores price levels in a map-indexable red-black tree.
+/// and stores price levels in a map-indexable red-black tree, or a B-tree.
 pub struct BookSide {
-    prices: RBMap<u32, PriceLevel>,
+    prices: Prices,
 }
 
 impl BookSide {
     #[must_use]
     pub fn new() -> Self {
-        Self { prices: RBMap::new() }
+        Self { prices: Prices::RedBlackTree(RBMap::new()) }
+    }
+
+    /// Store the price levels in the map of the provided backend, the ones
+    /// already there are moved over. The complexity for this operation is
+    /// *O*(*n* log *n*), where *n* is the size of the tree.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::book_side::{Backend, BookSide};
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut bookside = BookSide::new();
+    /// bookside.append(Order::new(1, 1, Side::Ask, 10, 100));
+    ///
+    /// let bookside = bookside.with_backend(Backend::BTree);
+    ///
+    /// assert_eq!(bookside.backend(), Backend::BTree);
+    /// assert_eq!(bookside.get_price_volume(10).unwrap(), 100);
+    /// ```
+    #[must_use]
+    pub fn with_backend(self, backend: Backend) -> Self {
+        let mut prices = match backend {
+            Backend::RedBlackTree => Prices::RedBlackTree(RBMap::new()),
+            Backend::BTree => Prices::BTree(BTreeMap::new()),
+        };
+
+        for price_level in self.prices.into_values() {
+            prices.insert(price_level.price, price_level);
+        }
+
+        Self { prices }
+    }
+
+    /// Get the backend the price levels are stored in.
+    #[must_use]
+    pub const fn backend(&self) -> Backend {
+        match self.prices {
+            Prices::RedBlackTree(_) => Backend::RedBlackTree,
+            Prices::BTree(_) => Backend::BTree,
+        }
     }
 
     /// Append an order to the corresponding price level, and returns its
@@ -61,14 +127,7 @@ impl BookSide {
     /// ```
     pub fn remove(&mut self, order: Order) -> Option<Order> {
         // Searching a red-black tree is O(log n)
-        let price_level = self.prices.get_mut(&order.price);
-
-        if price_level == None {
-            return None;
-        }
-
-        assert_ne!(price_level, None);
-        let price_level = price_level.unwrap();
+        let price_level = self.prices.get_mut(&order.price)?;
         // Removing from a queue is O(m)
         let removed = price_level.remove(order);
 
@@ -79,12 +138,14 @@ impl BookSide {
         removed
     }
 
-    /// Trade an order from the corresponding price level, and returns it. The
+    /// Trade a quantity against the price level at the given price following
+    /// the provided allocation policy, and returns the resulting fills. The
     /// complexity for this operation is *O*(log *n* + *m*), where *n* is the
     /// size of the tree and *m* is the length of the price level.
     ///
     /// # Example
     /// ```
+    /// use orderbook::allocation::Allocation;
     /// use orderbook::book_side::BookSide;
     /// use orderbook::order::{Order, Side};
     ///
@@ -92,18 +153,18 @@ impl BookSide {
     /// let order = Order::new(1, 1, Side::Ask, 10, 100);
     ///
     /// bookside.append(order);
-    /// bookside.trade(10, 100);
+    /// bookside.trade(10, 100, Allocation::Fifo);
     ///
     /// assert_eq!(bookside.max(), None);
     /// ```
-    pub fn trade(&mut self, price: u32, quantity: u32) -> Option<Order> {
-        let mut outcome = None;
+    pub fn trade(&mut self, price: u32, quantity: u32, allocation: Allocation) -> Vec<Fill> {
+        let mut outcome = Vec::new();
 
         // Search for a matching price level
         // Searching a red-black tree is O(log n)
         if let Some(price_level) = self.prices.get_mut(&price) {
             // Price level found, attempt to trade on it
-            outcome = price_level.trade(quantity);
+            outcome = price_level.trade(quantity, allocation);
 
             if price_level.is_empty() {
                 self.prices.remove(&price);
@@ -113,6 +174,112 @@ impl BookSide {
         outcome
     }
 
+    /// Take the price level matching the provided price out of the side, and
+    /// return it. The complexity for this operation is *O*(log *n*), where *n*
+    /// is the size of the tree.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::book_side::BookSide;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut bookside = BookSide::new();
+    /// bookside.append(Order::new(1, 1, Side::Ask, 10, 100));
+    /// bookside.append(Order::new(2, 1, Side::Ask, 11, 100));
+    ///
+    /// let price_level = bookside.take(10).unwrap();
+    /// assert_eq!(bookside.min().unwrap().price, 11);
+    ///
+    /// bookside.put(price_level);
+    /// assert_eq!(bookside.min().unwrap().price, 10);
+    /// ```
+    pub fn take(&mut self, price: u32) -> Option<PriceLevel> {
+        self.prices.remove(&price)
+    }
+
+    /// Put a price level taken out of the side back in, with its queue as it
+    /// was. The complexity for this operation is *O*(log *n*), where *n* is
+    /// the size of the tree.
+    pub fn put(&mut self, price_level: PriceLevel) {
+        self.prices.insert(price_level.price, price_level);
+    }
+
+    /// Return the prices of every level in ascending order. The complexity for
+    /// this operation is *O*(*n*), where *n* is the size of the tree.
+    #[must_use]
+    pub fn prices(&self) -> Vec<u32> {
+        self.prices.values().map(|level| level.price).collect()
+    }
+
+    /// Return every order in ascending price order, and then in time priority.
+    /// Appending them to an empty side in this sequence rebuilds the same
+    /// queues. The complexity for this operation is *O*(*n* + *k*), where *n*
+    /// is the size of the tree and *k* is the number of orders.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::book_side::BookSide;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut bookside = BookSide::new();
+    ///
+    /// bookside.append(Order::new(1, 1, Side::Ask, 11, 100));
+    /// bookside.append(Order::new(2, 1, Side::Ask, 10, 50));
+    /// bookside.append(Order::new(3, 1, Side::Ask, 11, 50));
+    ///
+    /// let ids: Vec<_> = bookside.orders().iter().map(|order| order.id).collect();
+    /// assert_eq!(ids, vec![2, 1, 3]);
+    /// ```
+    #[must_use]
+    pub fn orders(&self) -> Vec<Order> {
+        self.prices
+            .values()
+            .flat_map(PriceLevel::iter)
+            .copied()
+            .collect()
+    }
+
+    /// Return the price of every level along with its whole open quantity,
+    /// hidden quantity included, in ascending price order. The complexity for
+    /// this operation is *O*(*n* + *k*), where *n* is the size of the tree and
+    /// *k* is the number of orders.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::book_side::BookSide;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut bookside = BookSide::new();
+    ///
+    /// bookside.append(Order::new(1, 1, Side::Ask, 11, 100).with_display(10));
+    /// bookside.append(Order::new(2, 1, Side::Ask, 10, 50));
+    ///
+    /// assert_eq!(bookside.depth(), vec![(10, 50), (11, 100)]);
+    /// ```
+    #[must_use]
+    pub fn depth(&self) -> Vec<(u32, u32)> {
+        self.prices
+            .values()
+            .map(|level| (level.price, level.iter().map(Order::leaves).sum()))
+            .collect()
+    }
+
+    /// Return the price level matching the provided price. The complexity for
+    /// this operation is *O*(log *n*), where *n* is the size of the tree.
+    #[must_use]
+    pub fn get_level(&self, price: u32) -> Option<&PriceLevel> {
+        self.prices.get(&price)
+    }
+
+    /// Return the order with the provided ID from the price level matching the
+    /// provided price. The complexity for this operation is
+    /// *O*(log *n* + *m*), where *n* is the size of the tree and *m* is the
+    /// length of the price level.
+    #[must_use]
+    pub fn get(&self, price: u32, order_id: usize) -> Option<&Order> {
+        self.prices.get(&price)?.get(order_id)
+    }
+
     /// Return the volume of the price level matching the provided price. The
     /// complexity for this operation is *O*(log *n*), where *n* is the size of
     /// the tree.
@@ -121,19 +288,151 @@ impl BookSide {
         self.prices.get(&price).map(|pl| pl.volume)
     }
 
+    /// Check that every price level is consistent: it is stored under its
+    /// price, it isn't empty, its orders are open and belong to the provided
+    /// side at its price, and its volume is their shown quantity. The
+    /// complexity for this operation is *O*(*n* log *n* + *k*), where *n* is
+    /// the size of the tree and *k* is the number of orders.
+    ///
+    /// # Errors
+    /// Return a description of the first inconsistency found.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::book_side::BookSide;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut bookside = BookSide::new();
+    /// bookside.append(Order::new(1, 1, Side::Ask, 10, 100));
+    ///
+    /// assert!(bookside.check_invariants(Side::Ask).is_ok());
+    /// assert!(bookside.check_invariants(Side::Bid).is_err());
+    /// ```
+    pub fn check_invariants(&self, side: Side) -> Result<(), String> {
+        for level in self.prices.values() {
+            let price = level.price;
+
+            if !self
+                .get_level(price)
+                .map_or(false, |stored| std::ptr::eq(stored, level))
+            {
+                return Err(format!("Level {price} isn't stored under its price"));
+            }
+            if level.is_empty() {
+                return Err(format!("Level {price} is empty"));
+            }
+
+            for order in level.iter() {
+                if order.side != side || order.price != price {
+                    return Err(format!(
+                        "Order {} doesn't belong to level {price}",
+                        order.id
+                    ));
+                }
+                if order.leaves() == 0 {
+                    return Err(format!(
+                        "Order {} is filled but still on level {price}",
+                        order.id
+                    ));
+                }
+            }
+
+            let volume: u32 = level.iter().map(|order| order.quantity).sum();
+            if volume != level.volume {
+                return Err(format!(
+                    "Level {price} has volume {}, its orders show {volume}",
+                    level.volume
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Return the smallest price level sorted by price if present. The
-    /// complexity for this operation is *O*(1).
+    /// complexity for this operation is *O*(1) with a red-black tree, and
+    /// *O*(log *n*) with a B-tree, where *n* is the size of the tree.
     #[must_use]
     pub fn min(&self) -> Option<&PriceLevel> {
-        self.prices.peek()
+        match &self.prices {
+            Prices::RedBlackTree(prices) => prices.peek(),
+            Prices::BTree(prices) => prices.values().next(),
+        }
     }
 
     /// Return the biggest price level sorted by price if present. The
-    /// complexity for this operation is *O*(log *n*), where *n* is the length
-    /// of the price level.
+    /// complexity for this operation is *O*(log *n*), where *n* is the size of
+    /// the tree.
     #[must_use]
     pub fn max(&self) -> Option<&PriceLevel> {
-        self.prices.peek_back()
+        match &self.prices {
+            Prices::RedBlackTree(prices) => prices.peek_back(),
+            Prices::BTree(prices) => prices.values().next_back(),
+        }
+    }
+}
+
+impl Prices {
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        match self {
+            Self::RedBlackTree(prices) => prices.len(),
+            Self::BTree(prices) => prices.len(),
+        }
+    }
+
+    fn get(&self, price: &u32) -> Option<&PriceLevel> {
+        match self {
+            Self::RedBlackTree(prices) => prices.get(price),
+            Self::BTree(prices) => prices.get(price),
+        }
+    }
+
+    fn get_mut(&mut self, price: &u32) -> Option<&mut PriceLevel> {
+        match self {
+            Self::RedBlackTree(prices) => prices.get_mut(price),
+            Self::BTree(prices) => prices.get_mut(price),
+        }
+    }
+
+    fn insert(&mut self, price: u32, price_level: PriceLevel) {
+        match self {
+            Self::RedBlackTree(prices) => {
+                prices.insert(price, price_level);
+            }
+            Self::BTree(prices) => {
+                prices.insert(price, price_level);
+            }
+        }
+    }
+
+    fn remove(&mut self, price: &u32) -> Option<PriceLevel> {
+        match self {
+            Self::RedBlackTree(prices) => prices.remove(price),
+            Self::BTree(prices) => prices.remove(price),
+        }
+    }
+
+    /// Iterate over the price levels in ascending price order.
+    fn values(&self) -> Box<dyn Iterator<Item = &PriceLevel> + '_> {
+        match self {
+            Self::RedBlackTree(prices) => Box::new(prices.values()),
+            Self::BTree(prices) => Box::new(prices.values()),
+        }
+    }
+
+    /// Take the price levels out in ascending price order.
+    fn into_values(self) -> Vec<PriceLevel> {
+        match self {
+            Self::RedBlackTree(prices) => prices.into_iter().map(|(_, level)| level).collect(),
+            Self::BTree(prices) => prices.into_values().collect(),
+        }
+    }
+}
+
+impl Default for BookSide {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -244,10 +543,47 @@ mod test {
         let order = Order::new(1, 1, Side::Ask, 1, 1);
 
         side.append(order);
-        let outcome = side.trade(1, 1);
+        let outcome = side.trade(1, 1, Allocation::Fifo);
 
         assert_eq!(side.prices.get(&1), None);
         assert_eq!(side.prices.len(), 0);
-        assert_eq!(outcome.unwrap(), order);
+        assert_eq!(outcome[0].order, order);
+    }
+
+    #[test]
+    fn test_prices() {
+        let mut side = BookSide::new();
+
+        side.append(Order::new(1, 1, Side::Ask, 3, 1));
+        side.append(Order::new(2, 1, Side::Ask, 1, 1));
+        side.append(Order::new(3, 1, Side::Ask, 2, 1));
+
+        assert_eq!(side.prices(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_backends() {
+        let orders = [
+            Order::new(1, 1, Side::Ask, 3, 10),
+            Order::new(2, 1, Side::Ask, 1, 20),
+            Order::new(3, 1, Side::Ask, 2, 30),
+            Order::new(4, 1, Side::Ask, 1, 40),
+        ];
+        let mut red_black = BookSide::new();
+        let mut b_tree = BookSide::new().with_backend(Backend::BTree);
+
+        for side in [&mut red_black, &mut b_tree].iter_mut() {
+            for &order in orders.iter() {
+                side.append(order);
+            }
+            side.remove(orders[2]);
+            side.trade(1, 25, Allocation::Fifo);
+        }
+
+        assert_eq!(b_tree.backend(), Backend::BTree);
+        assert_eq!(b_tree.orders(), red_black.orders());
+        assert_eq!(b_tree.depth(), vec![(1, 35), (3, 10)]);
+        assert_eq!(b_tree.min(), red_black.min());
+        assert_eq!(b_tree.max(), red_black.max());
     }
 }
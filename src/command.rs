@@ -0,0 +1,47 @@
+use crate::order::Order;
+use crate::session::Session;
+
+/// Representation of the commands a matching engine processes, as read from
+/// the input file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    // New orders carry the symbol of the book they're submitted to
+    New {
+        symbol: String,
+        order: Order,
+    },
+    // Cancels only need the order IDs, the engine knows the book of each order
+    Cancel {
+        user_id: usize,
+        order_id: usize,
+    },
+    // Session changes happen right away, or at the provided time
+    Session {
+        symbol: String,
+        session: Session,
+        time: Option<u64>,
+    },
+    // The clock moves every book to the provided time
+    Clock {
+        time: u64,
+    },
+    // Flushes act on every book
+    Flush {
+        policy: FlushPolicy,
+    },
+}
+
+/// What a flush does to the order books: either drop them right away, or close
+/// them as at the end of the day, uncrossing their closing auctions, before
+/// dropping them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlushPolicy {
+    Reset,
+    Close,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self::Reset
+    }
+}
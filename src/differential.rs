@@ -0,0 +1,196 @@
+use std::fmt;
+use std::io::Read;
+
+use crate::command::Command;
+use crate::engine::{Event, MatchingEngine};
+use crate::parser::{format_command, format_outcome, CommandReader, RecordError};
+use crate::scenario::Report;
+
+/// The first command two engines disagree on, along with the events each of
+/// them gave for it. The line is the one of the command in the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub line: u64,
+    pub command: Command,
+    pub baseline: Vec<Event>,
+    pub candidate: Vec<Event>,
+}
+
+impl fmt::Display for Divergence {
+    /// The command as a record, followed by the difference between the output
+    /// lines of the baseline and the ones of the candidate.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let report = Report {
+            name: String::new(),
+            expected: output(&self.baseline),
+            actual: output(&self.candidate),
+        };
+
+        write!(f, "{}: {}", self.line, format_command(&self.command))?;
+        for line in report.diff() {
+            write!(f, "\n    {line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How two engines compare over the same commands: how many of them they
+/// processed, the first one they disagree on if any, and whether their books
+/// ended up the same. Engines stop at the first divergence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub commands: u64,
+    pub divergence: Option<Divergence>,
+    pub same_books: bool,
+}
+
+impl Comparison {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.divergence.is_none() && self.same_books
+    }
+}
+
+/// Run every command of the input through both engines, and compare their
+/// events command by command, then their books once the input is over. The
+/// engines are expected to differ in the way they work rather than in what
+/// they do, such as the backend of their book sides, so that a refactor can be
+/// checked against real order flow.
+///
+/// # Errors
+/// Return the first record of the input that can't be parsed.
+///
+/// # Example
+/// ```
+/// use orderbook::book_side::Backend;
+/// use orderbook::differential::compare;
+/// use orderbook::engine::MatchingEngine;
+/// use orderbook::parser::CommandReader;
+///
+/// let input = "N, 1, IBM, 10, 100, B, 1\nN, 2, IBM, 10, 50, S, 2\n";
+/// let reader = CommandReader::new("input.csv", input.as_bytes());
+/// let comparison = compare(
+///     reader,
+///     &mut MatchingEngine::new(),
+///     &mut MatchingEngine::new().with_backend(Backend::BTree),
+/// )
+/// .unwrap();
+///
+/// assert_eq!(comparison.commands, 2);
+/// assert!(comparison.passed());
+/// ```
+pub fn compare<R: Read>(
+    mut reader: CommandReader<R>,
+    baseline: &mut MatchingEngine,
+    candidate: &mut MatchingEngine,
+) -> Result<Comparison, RecordError> {
+    let mut commands = 0;
+
+    while let Some(command) = reader.next() {
+        let command = command?;
+        commands += 1;
+
+        let events = (
+            baseline.process(command.clone()),
+            candidate.process(command.clone()),
+        );
+
+        if events.0 != events.1 {
+            let divergence = Divergence {
+                line: reader.line(),
+                command,
+                baseline: events.0,
+                candidate: events.1,
+            };
+
+            return Ok(Comparison {
+                commands,
+                divergence: Some(divergence),
+                same_books: false,
+            });
+        }
+    }
+
+    Ok(Comparison {
+        commands,
+        divergence: None,
+        same_books: baseline.snapshot(0) == candidate.snapshot(0),
+    })
+}
+
+/// The output lines of some events.
+fn output(events: &[Event]) -> Vec<String> {
+    events
+        .iter()
+        .flat_map(|event| format_outcome(&event.outcome))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::book_side::Backend;
+    use crate::order::{Order, Side};
+    use crate::MatchingMode;
+
+    use super::*;
+
+    const INPUT: &str = "\
+# Both engines rest the first order
+N, 1, IBM, 10, 100, B, 1
+N, 2, IBM, 12, 100, S, 2
+N, 2, IBM, 10, 40, S, 3
+C, 1, 1
+";
+
+    fn run(baseline: MatchingEngine, candidate: MatchingEngine) -> Comparison {
+        let reader = CommandReader::new("input.csv", INPUT.as_bytes());
+
+        compare(reader, &mut { baseline }, &mut { candidate }).unwrap()
+    }
+
+    #[test]
+    fn test_compare_backends() {
+        let comparison = run(
+            MatchingEngine::new().with_backend(Backend::BTree),
+            MatchingEngine::new(),
+        );
+
+        assert_eq!(comparison.commands, 4);
+        assert!(comparison.passed());
+    }
+
+    #[test]
+    fn test_compare_divergence() {
+        let comparison = run(
+            MatchingEngine::new().with_mode(MatchingMode::Trade),
+            MatchingEngine::new(),
+        );
+        let divergence = comparison.divergence.unwrap();
+
+        assert_eq!((comparison.commands, divergence.line), (3, 4));
+        assert_eq!(
+            divergence.to_string(),
+            "4: N, 2, IBM, 10, 40, S, 3\n    \
+             - A, 2, 3\n    \
+             - T, 1, 1, 2, 3, 10, 40\n    \
+             - B, B, 10, 60\n    \
+             + R, 2, 3"
+        );
+    }
+
+    #[test]
+    fn test_compare_books() {
+        let reader = CommandReader::new("input.csv", "C, 1, 1\n".as_bytes());
+        let mut baseline = MatchingEngine::new();
+        baseline.process(Command::New {
+            symbol: String::from("IBM"),
+            order: Order::new(2, 2, Side::Ask, 12, 100),
+        });
+
+        let comparison = compare(reader, &mut baseline, &mut MatchingEngine::new()).unwrap();
+
+        assert!(comparison.divergence.is_none());
+        assert!(!comparison.passed());
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::book_side::Backend;
+use crate::command::{Command, FlushPolicy};
+use crate::instrument::Registry;
+use crate::order_book::{MatchingMode, OrderBook, OrderOutcome, RejectReason};
+use crate::session::Session;
+use crate::snapshot::Snapshot;
+
+/// An outcome of a command, along with the symbol of the book it comes from.
+/// The symbol is empty for cancels of unknown orders, which have no book.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub symbol: String,
+    pub outcome: OrderOutcome,
+}
+
+/// Routes the commands to the order books of their symbols, and keeps track of
+/// the book each open order lives in so that cancels can find it. Books are
+/// sorted by symbol, so that commands touching every book produce their events
+/// in a stable order.
+///
+/// Without an instrument registry a book is created for each new symbol,
+/// otherwise each registered instrument gets its book right away and orders
+/// for any other symbol are rejected.
+#[derive(Default)]
+pub struct MatchingEngine {
+    mode: MatchingMode,
+    backend: Backend,
+    registry: Option<Registry>,
+    order_books: BTreeMap<String, OrderBook>,
+    order_symbols: HashMap<usize, String>,
+}
+
+impl MatchingEngine {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            mode: MatchingMode::Reject,
+            backend: Backend::RedBlackTree,
+            registry: None,
+            order_books: BTreeMap::new(),
+            order_symbols: HashMap::new(),
+        }
+    }
+
+    /// Use the provided matching mode for every order book.
+    #[must_use]
+    pub fn with_mode(mut self, mode: MatchingMode) -> Self {
+        self.mode = mode;
+        self.order_books = self.open_books();
+        self
+    }
+
+    /// Store the price levels of every order book in the provided backend.
+    #[must_use]
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self.order_books = self.open_books();
+        self
+    }
+
+    /// Only trade the instruments of the provided registry, each of them gets
+    /// its order book right away.
+    #[must_use]
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = Some(registry);
+        self.order_books = self.open_books();
+        self
+    }
+
+    /// Build the empty order books the engine starts from, one for each
+    /// instrument of the registry if any.
+    fn open_books(&self) -> BTreeMap<String, OrderBook> {
+        let books = self
+            .registry
+            .as_ref()
+            .map(Registry::books)
+            .unwrap_or_default();
+
+        books
+            .into_iter()
+            .map(|(symbol, order_book)| (symbol, self.set_up(order_book)))
+            .collect()
+    }
+
+    /// Set up a new order book with the mode and backend of the engine.
+    fn set_up(&self, order_book: OrderBook) -> OrderBook {
+        order_book.with_mode(self.mode).with_backend(self.backend)
+    }
+
+    /// Get the order book of a symbol, if it has one.
+    #[must_use]
+    pub fn order_book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.order_books.get(symbol)
+    }
+
+    /// Get the number of orders tracked by the engine, those resting on a book
+    /// or waiting for their trigger.
+    #[must_use]
+    pub fn open_orders(&self) -> usize {
+        self.order_symbols.len()
+    }
+
+    /// Check that every order book is consistent, see
+    /// `OrderBook::check_invariants`, and that the engine tracks exactly the
+    /// orders of the books, each in the book of its symbol.
+    ///
+    /// # Errors
+    /// Return a description of the first inconsistency found, along with the
+    /// symbol of its book.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut open_orders = 0;
+
+        for (symbol, order_book) in &self.order_books {
+            order_book
+                .check_invariants()
+                .map_err(|error| format!("{symbol}: {error}"))?;
+            open_orders += order_book.snapshot().orders.len();
+        }
+
+        for (order_id, symbol) in &self.order_symbols {
+            let order_book = self.order_books.get(symbol);
+
+            if order_book
+                .and_then(|book| book.order_status(*order_id))
+                .is_none()
+            {
+                return Err(format!(
+                    "{symbol}: Order {order_id} is tracked but not on the book"
+                ));
+            }
+        }
+
+        if open_orders != self.order_symbols.len() {
+            return Err(format!(
+                "The books hold {} orders, the engine tracks {}",
+                open_orders,
+                self.order_symbols.len()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Take a snapshot of every order book, the provided sequence number is
+    /// the one of the last journal entry the engine has processed.
+    #[must_use]
+    pub fn snapshot(&self, seq: u64) -> Snapshot {
+        let books = self
+            .order_books
+            .iter()
+            .map(|(symbol, order_book)| (symbol.clone(), order_book.snapshot()))
+            .collect();
+
+        Snapshot { seq, books }
+    }
+
+    /// Replace the state of the engine with the one of a snapshot, as if it
+    /// had processed the same commands. The books are set up by the engine,
+    /// so it should have the same mode and instruments as the one that took
+    /// the snapshot, books of symbols outside the registry are dropped.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.order_books = self.open_books();
+        self.order_symbols.clear();
+
+        for (symbol, book) in &snapshot.books {
+            if let Some(order_book) = self.get_order_book_mut(symbol) {
+                order_book.restore(book);
+
+                for order in &book.orders {
+                    self.order_symbols.insert(order.id, symbol.clone());
+                }
+            }
+        }
+    }
+
+    /// Process a command, and return its events. Orders that leave their book,
+    /// because they're rejected, filled or cancelled, are no longer tracked.
+    ///
+    /// Cancels for orders the engine doesn't know are rejected, and so are
+    /// orders for symbols outside the registry, while their session commands
+    /// are ignored. Flushing the engine forgets every order and starts again
+    /// from empty books.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::command::Command;
+    /// use orderbook::engine::MatchingEngine;
+    /// use orderbook::order::{Order, Side};
+    /// use orderbook::order_book::MatchingMode;
+    ///
+    /// let mut engine = MatchingEngine::new().with_mode(MatchingMode::Trade);
+    /// let symbol = String::from("IBM");
+    ///
+    /// let order = Order::new(1, 1, Side::Ask, 10, 100);
+    /// engine.process(Command::New { symbol: symbol.clone(), order });
+    /// assert_eq!(engine.open_orders(), 1);
+    ///
+    /// let order = Order::new(2, 2, Side::Bid, 10, 100);
+    /// let events = engine.process(Command::New { symbol, order });
+    ///
+    /// assert_eq!(events[0].outcome.trades().len(), 1);
+    /// assert_eq!(engine.open_orders(), 0);
+    /// ```
+    pub fn process(&mut self, command: Command) -> Vec<Event> {
+        match command {
+            Command::New { symbol, order } => {
+                let (user_id, order_id) = (order.user_id, order.id);
+
+                match self.get_order_book_mut(&symbol) {
+                    Some(order_book) => {
+                        let outcomes = order_book.submit(order);
+                        self.order_symbols.insert(order_id, symbol.clone());
+                        self.track(&symbol, outcomes)
+                    }
+                    None => {
+                        let reason = RejectReason::UnknownSymbol;
+                        let outcome = OrderOutcome::Rejected { user_id, order_id, reason };
+                        vec![Event { symbol, outcome }]
+                    }
+                }
+            }
+            Command::Cancel { user_id, order_id } => match self.order_symbols.get(&order_id) {
+                Some(symbol) => {
+                    let symbol = symbol.clone();
+                    let order_book = self.order_books.get_mut(&symbol).unwrap();
+                    let outcomes = order_book.cancel_order(user_id, order_id);
+                    self.track(&symbol, outcomes)
+                }
+                None => {
+                    let reason = RejectReason::UnknownOrder;
+                    let outcome = OrderOutcome::Rejected { user_id, order_id, reason };
+                    vec![Event { symbol: String::new(), outcome }]
+                }
+            },
+            Command::Session { symbol, session, time } => {
+                // Unknown symbols have no session to move
+                let outcomes = match (self.get_order_book_mut(&symbol), time) {
+                    (Some(order_book), Some(time)) => {
+                        order_book.schedule(time, session);
+                        Vec::new()
+                    }
+                    (Some(order_book), None) => order_book.transition(session),
+                    (None, _) => Vec::new(),
+                };
+
+                self.track(&symbol, outcomes)
+            }
+            Command::Clock { time } => {
+                let symbols: Vec<String> = self.order_books.keys().cloned().collect();
+                let mut events = Vec::new();
+
+                for symbol in symbols {
+                    let outcomes = self.order_books.get_mut(&symbol).unwrap().advance(time);
+                    events.extend(self.track(&symbol, outcomes));
+                }
+
+                events
+            }
+            Command::Flush { policy } => {
+                let mut events = Vec::new();
+
+                if let FlushPolicy::Close = policy {
+                    for (symbol, order_book) in &mut self.order_books {
+                        events.extend(
+                            order_book
+                                .transition(Session::Closed)
+                                .into_iter()
+                                .map(|outcome| Event { symbol: symbol.clone(), outcome }),
+                        );
+                    }
+                }
+
+                self.order_books = self.open_books();
+                self.order_symbols.clear();
+                events
+            }
+        }
+    }
+
+    /// Get the order book of a symbol. Without an instrument registry a book is
+    /// created for each new symbol, otherwise only registered symbols have one.
+    fn get_order_book_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        match self.registry {
+            Some(_) => self.order_books.get_mut(symbol),
+            None => {
+                if !self.order_books.contains_key(symbol) {
+                    let order_book = self.set_up(OrderBook::new());
+                    self.order_books.insert(symbol.to_string(), order_book);
+                }
+
+                self.order_books.get_mut(symbol)
+            }
+        }
+    }
+
+    /// Stop tracking the orders that left the book of a symbol according to
+    /// its outcomes, and return them as events. The complexity for this
+    /// operation is *O*(*k* (log *n* + *m*)), where *k* is the number of
+    /// orders in the outcomes, *n* is the size of the tree and *m* is the
+    /// length of the price level.
+    fn track(&mut self, symbol: &str, outcomes: Vec<OrderOutcome>) -> Vec<Event> {
+        // Symbols outside the registry have no book, nor orders
+        let order_book = match self.order_books.get(symbol) {
+            Some(order_book) => order_book,
+            None => return Vec::new(),
+        };
+
+        for outcome in &outcomes {
+            let mut order_ids = Vec::new();
+
+            match outcome {
+                OrderOutcome::Rejected { order_id, .. }
+                | OrderOutcome::Created { order_id, .. }
+                | OrderOutcome::Cancelled { order_id, .. }
+                | OrderOutcome::TopOfBook { order_id, .. }
+                | OrderOutcome::Triggered { order_id, .. }
+                | OrderOutcome::Traded { order_id, .. } => order_ids.push(*order_id),
+                _ => {}
+            }
+
+            for trade in outcome.trades() {
+                order_ids.extend([trade.order_id_buy, trade.order_id_sell].iter());
+            }
+
+            for order_id in order_ids {
+                if order_book.order_status(order_id).is_none() {
+                    self.order_symbols.remove(&order_id);
+                }
+            }
+        }
+
+        outcomes
+            .into_iter()
+            .map(|outcome| Event { symbol: symbol.to_string(), outcome })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrument::Instrument;
+    use crate::order::{Order, Side};
+
+    use super::*;
+
+    fn new(symbol: &str, order: Order) -> Command {
+        Command::New { symbol: symbol.to_string(), order }
+    }
+
+    #[test]
+    fn test_process_routing() {
+        let mut engine = MatchingEngine::new().with_mode(MatchingMode::Trade);
+
+        engine.process(new("IBM", Order::new(1, 1, Side::Ask, 10, 100)));
+        engine.process(new("VAL", Order::new(2, 1, Side::Ask, 20, 100)));
+        engine.process(new(
+            "VAL",
+            Order::new(3, 1, Side::Ask, 0, 100)
+                .with_market()
+                .with_stop(25),
+        ));
+
+        assert_eq!(engine.order_book("IBM").unwrap().best_ask_price(), Some(10));
+        assert_eq!(engine.order_book("VAL").unwrap().best_ask_price(), Some(20));
+        assert_eq!(engine.open_orders(), 3);
+
+        // Cancels find the book of the order, and forget it
+        let events = engine.process(Command::Cancel { user_id: 1, order_id: 2 });
+
+        assert_eq!(events[0].symbol, "VAL");
+        assert_eq!(engine.order_book("VAL").unwrap().best_ask_price(), None);
+        assert_eq!(engine.open_orders(), 2);
+
+        let events = engine.process(Command::Cancel { user_id: 1, order_id: 2 });
+        assert_eq!(
+            events[0].outcome,
+            OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 2,
+                reason: RejectReason::UnknownOrder
+            }
+        );
+
+        // Rejected orders are never tracked
+        engine.process(new(
+            "IBM",
+            Order::new(4, 2, Side::Bid, 0, 500).with_market(),
+        ));
+        assert_eq!(engine.open_orders(), 1);
+
+        engine.process(Command::Flush { policy: FlushPolicy::Reset });
+        assert_eq!(engine.open_orders(), 0);
+        assert!(engine.order_book("IBM").is_none());
+    }
+
+    #[test]
+    fn test_process_registry() {
+        let mut registry = Registry::new();
+        registry.insert(Instrument::new("IBM"));
+        let mut engine = MatchingEngine::new().with_registry(registry);
+
+        let events = engine.process(new("VAL", Order::new(1, 1, Side::Ask, 10, 100)));
+        let reason = RejectReason::UnknownSymbol;
+
+        assert_eq!(
+            events[0].outcome,
+            OrderOutcome::Rejected { user_id: 1, order_id: 1, reason }
+        );
+        assert!(engine.order_book("VAL").is_none());
+
+        // Registered books survive a flush, empty
+        engine.process(new("IBM", Order::new(2, 1, Side::Ask, 10, 100)));
+        let events = engine.process(Command::Flush { policy: FlushPolicy::Close });
+
+        assert_eq!(events[0].symbol, "IBM");
+        assert_eq!(engine.order_book("IBM").unwrap().best_ask_price(), None);
+    }
+
+    #[test]
+    fn test_process_mode() {
+        let mut engine = MatchingEngine::new().with_mode(MatchingMode::Reject);
+
+        engine.process(new("IBM", Order::new(1, 1, Side::Ask, 10, 100)));
+        let events = engine.process(new("IBM", Order::new(2, 2, Side::Bid, 10, 100)));
+        let reason = RejectReason::Crossing;
+
+        assert_eq!(
+            events[0].outcome,
+            OrderOutcome::Rejected { user_id: 2, order_id: 2, reason }
+        );
+        assert_eq!(engine.open_orders(), 1);
+
+        // Books opened by a flush keep the mode
+        engine.process(Command::Flush { policy: FlushPolicy::Reset });
+        engine.process(new("IBM", Order::new(3, 1, Side::Ask, 10, 100)));
+        let events = engine.process(new("IBM", Order::new(4, 2, Side::Bid, 10, 100)));
+
+        assert!(events[0].outcome.trades().is_empty());
+    }
+
+    #[test]
+    fn test_process_backend() {
+        let mut engine = MatchingEngine::new().with_backend(Backend::BTree);
+
+        engine.process(new("IBM", Order::new(1, 1, Side::Ask, 10, 100)));
+        assert_eq!(engine.order_book("IBM").unwrap().backend(), Backend::BTree);
+
+        // Books restored from a snapshot keep the backend
+        let snapshot = engine.snapshot(1);
+        let mut restored = MatchingEngine::new().with_backend(Backend::BTree);
+        restored.restore(&snapshot);
+
+        assert_eq!(
+            restored.order_book("IBM").unwrap().backend(),
+            Backend::BTree
+        );
+        assert_eq!(restored.snapshot(1), snapshot);
+    }
+}
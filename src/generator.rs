@@ -0,0 +1,340 @@
+use crate::command::Command;
+use crate::order::{Order, Side};
+
+/// Price every symbol starts from, before its random walk.
+const START_PRICE: u32 = 1000;
+
+/// How many ticks away from the current price passive orders are quoted, and
+/// how far through it marketable ones reach.
+const DEPTH: u32 = 5;
+
+/// The largest quantity of a generated order.
+const MAX_QUANTITY: u32 = 100;
+
+/// Generates a synthetic flow of commands, as an endless iterator, for
+/// benchmarks, demos and soak tests. The flow is fully determined by the seed
+/// and the settings, so that a run can be reproduced.
+///
+/// Events arrive at the provided rate, on average, per unit of the engine
+/// clock, with exponentially distributed gaps, and a clock command is issued
+/// whenever time moves on. Each event is either a cancel, for one of the
+/// passive orders sent so far, or a new order for a random symbol and user.
+/// Before every new order the price of its symbol takes a random step, within
+/// the volatility either way: passive orders are quoted a few ticks away from
+/// that price, while marketable ones reach through it to trade against them.
+///
+/// Passive orders can still trade, or be rejected in reject mode, once the
+/// price has moved past them, and cancels can come after their order is gone,
+/// as they would from real users.
+///
+/// # Example
+/// ```
+/// use orderbook::command::Command;
+/// use orderbook::generator::Generator;
+///
+/// let commands: Vec<Command> = Generator::new().with_seed(7).take(100).collect();
+///
+/// assert_eq!(Generator::new().with_seed(7).take(100).collect::<Vec<_>>(), commands);
+/// assert_ne!(Generator::new().with_seed(8).take(100).collect::<Vec<_>>(), commands);
+/// ```
+pub struct Generator {
+    symbols: Vec<String>,
+    prices: Vec<u32>,
+    users: usize,
+    rate: f64,
+    volatility: u32,
+    cancel_ratio: f64,
+    marketable_ratio: f64,
+    rng: Rng,
+    time: f64,
+    clock: u64,
+    next_id: usize,
+    passive: Vec<(usize, usize)>,
+    pending: Option<Command>,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Generator {
+    /// A generator for a single symbol and ten users, one event per unit of
+    /// time, three cancels out of ten events and one marketable order out of
+    /// ten new ones, seeded with zero.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            symbols: Vec::new(),
+            prices: Vec::new(),
+            users: 10,
+            rate: 1.0,
+            volatility: 1,
+            cancel_ratio: 0.3,
+            marketable_ratio: 0.1,
+            rng: Rng::new(0),
+            time: 0.0,
+            clock: 0,
+            next_id: 1,
+            passive: Vec::new(),
+            pending: None,
+        }
+        .with_symbols(1)
+    }
+
+    /// Use the provided seed for the random choices, starting over.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// Trade the provided number of symbols, at least one, named from `SYM1`
+    /// onwards.
+    #[must_use]
+    pub fn with_symbols(mut self, symbols: usize) -> Self {
+        self.symbols = (1..=symbols.max(1))
+            .map(|index| format!("SYM{index}"))
+            .collect();
+        self.prices = vec![START_PRICE; self.symbols.len()];
+        self
+    }
+
+    /// Send orders from the provided number of users, at least one, with IDs
+    /// from one onwards.
+    #[must_use]
+    pub fn with_users(mut self, users: usize) -> Self {
+        self.users = users.max(1);
+        self
+    }
+
+    /// Issue the provided number of events per unit of time on average, which
+    /// is ignored unless it's positive.
+    #[must_use]
+    pub fn with_rate(mut self, rate: f64) -> Self {
+        if rate > 0.0 && rate.is_finite() {
+            self.rate = rate;
+        }
+        self
+    }
+
+    /// Move the price of a symbol by up to the provided number of ticks either
+    /// way before each of its orders, zero keeps it still.
+    #[must_use]
+    pub fn with_volatility(mut self, volatility: u32) -> Self {
+        self.volatility = volatility;
+        self
+    }
+
+    /// Make the provided share of the events cancels, from zero to one.
+    #[must_use]
+    pub fn with_cancel_ratio(mut self, ratio: f64) -> Self {
+        self.cancel_ratio = clamp_ratio(ratio);
+        self
+    }
+
+    /// Make the provided share of the new orders marketable, from zero to one.
+    #[must_use]
+    pub fn with_marketable_ratio(mut self, ratio: f64) -> Self {
+        self.marketable_ratio = clamp_ratio(ratio);
+        self
+    }
+
+    /// Draw the next event: a cancel when there's a passive order to cancel
+    /// and the cancel ratio allows it, a new order otherwise.
+    fn event(&mut self) -> Command {
+        if !self.passive.is_empty() && self.rng.uniform() < self.cancel_ratio {
+            let index = self.rng.below(self.passive.len() as u64) as usize;
+            let (user_id, order_id) = self.passive.swap_remove(index);
+
+            return Command::Cancel { user_id, order_id };
+        }
+
+        let index = self.rng.below(self.symbols.len() as u64) as usize;
+        let step = self.rng.below(2 * u64::from(self.volatility) + 1) as i64;
+        let price = i64::from(self.prices[index]) + step - i64::from(self.volatility);
+        // Prices stay far enough from the bounds to quote on both sides
+        let price = price.clamp(i64::from(DEPTH + 1), i64::from(u32::MAX - DEPTH)) as u32;
+        self.prices[index] = price;
+
+        let side = if self.rng.below(2) == 0 {
+            Side::Bid
+        } else {
+            Side::Ask
+        };
+        let user_id = self.rng.below(self.users as u64) as usize + 1;
+        let quantity = self.rng.below(u64::from(MAX_QUANTITY)) as u32 + 1;
+        let marketable = self.rng.uniform() < self.marketable_ratio;
+        let distance = if marketable {
+            DEPTH
+        } else {
+            self.rng.below(u64::from(DEPTH)) as u32 + 1
+        };
+
+        let price = match (side, marketable) {
+            (Side::Bid, true) | (Side::Ask, false) => price + distance,
+            (Side::Bid, false) | (Side::Ask, true) => price - distance,
+        };
+        let order = Order::new(self.next_id, user_id, side, price, quantity);
+        self.next_id += 1;
+
+        if !marketable {
+            self.passive.push((user_id, order.id));
+        }
+
+        Command::New { symbol: self.symbols[index].clone(), order }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Command;
+
+    /// Return the next command, the clock comes before the event that moves
+    /// it forward.
+    fn next(&mut self) -> Option<Command> {
+        if let Some(command) = self.pending.take() {
+            return Some(command);
+        }
+
+        // Gaps between events are exponentially distributed
+        self.time -= (1.0 - self.rng.uniform()).ln() / self.rate;
+        let command = self.event();
+
+        if self.time as u64 > self.clock {
+            self.clock = self.time as u64;
+            self.pending = Some(command);
+
+            return Some(Command::Clock { time: self.clock });
+        }
+
+        Some(command)
+    }
+}
+
+/// Keep a ratio from zero to one, out of range ones get the closest bound
+/// and those that aren't a number count as zero.
+fn clamp_ratio(ratio: f64) -> f64 {
+    if ratio.is_nan() {
+        0.0
+    } else {
+        ratio.clamp(0.0, 1.0)
+    }
+}
+
+/// The SplitMix64 generator: small, fast and good enough for a synthetic flow,
+/// and it gives the same numbers on every platform.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from zero, included, to one, excluded.
+    fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number from zero, included, to the provided bound, excluded.
+    fn below(&mut self, bound: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(bound)) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::MatchingEngine;
+    use crate::order_book::MatchingMode;
+
+    #[test]
+    fn test_rates_and_ratios() {
+        let commands: Vec<Command> = Generator::new()
+            .with_seed(42)
+            .with_rate(4.0)
+            .with_cancel_ratio(0.0)
+            .take(1000)
+            .collect();
+
+        let clocks: Vec<u64> = commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::Clock { time } => Some(*time),
+                _ => None,
+            })
+            .collect();
+        let orders = commands.len() - clocks.len();
+
+        // Time only moves forward, about a quarter as fast as the orders come
+        assert!(clocks.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((200..300).contains(&clocks[clocks.len() - 1]));
+        assert!(commands
+            .iter()
+            .all(|command| !matches!(command, Command::Cancel { .. })));
+
+        // Only new orders are left, each with its own ID
+        let ids: Vec<usize> = commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::New { order, .. } => Some(order.id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, (1..=orders).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_symbols_and_users() {
+        let generator = Generator::new()
+            .with_seed(1)
+            .with_symbols(3)
+            .with_users(2)
+            .with_volatility(0)
+            .with_cancel_ratio(0.0)
+            .with_marketable_ratio(0.0);
+
+        for command in generator.take(500) {
+            if let Command::New { symbol, order } = command {
+                assert!(["SYM1", "SYM2", "SYM3"].contains(&symbol.as_str()));
+                assert!((1..=2).contains(&order.user_id));
+
+                // Passive orders are quoted around a price that doesn't move
+                match order.side {
+                    Side::Bid => assert!((995..1000).contains(&order.price)),
+                    Side::Ask => assert!((1001..=1005).contains(&order.price)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_flow_trades() {
+        let mut engine = MatchingEngine::new().with_mode(MatchingMode::Trade);
+        let (mut trades, mut cancels) = (0, 0);
+
+        for command in Generator::new().with_seed(3).with_volatility(2).take(2000) {
+            let cancel = matches!(command, Command::Cancel { .. });
+
+            for event in engine.process(command) {
+                trades += event.outcome.trades().len();
+                cancels += usize::from(cancel);
+            }
+        }
+
+        assert!(trades > 0);
+        assert!(cancels > 0);
+        assert_eq!(engine.check_invariants(), Ok(()));
+    }
+}
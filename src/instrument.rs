@@ -0,0 +1,513 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Read;
+
+use csv::StringRecord;
+
+use crate::allocation::Allocation;
+use crate::band::Band;
+use crate::order::Order;
+use crate::order_book::{OrderBook, RejectReason};
+use crate::parser;
+use crate::session::Session;
+
+/// Trading rules of an instrument, checked on every incoming order before it
+/// reaches the book. Prices must be a multiple of the tick size, which can be
+/// tiered by price, while quantities must be a multiple of the lot size and
+/// within the minimum and maximum order quantity. Limit orders can also be
+/// capped by their notional value, the price times the quantity.
+///
+/// The default rules accept any price and any quantity but zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstrumentRules {
+    ticks: Vec<(u32, u32)>,
+    lot: u32,
+    min_quantity: u32,
+    max_quantity: Option<u32>,
+    max_notional: Option<u64>,
+}
+
+impl InstrumentRules {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            ticks: vec![(0, 1)],
+            lot: 1,
+            min_quantity: 1,
+            max_quantity: None,
+            max_notional: None,
+        }
+    }
+
+    /// Use the provided tick size for every price, replacing any tier.
+    #[must_use]
+    pub fn with_tick(mut self, tick: u32) -> Self {
+        self.ticks = vec![(0, tick.max(1))];
+        self
+    }
+
+    /// Use the provided tick size from the provided price upwards, until the
+    /// next tier. The complexity for this operation is *O*(*t*), where *t* is
+    /// the number of tiers.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::instrument::InstrumentRules;
+    ///
+    /// let rules = InstrumentRules::new().with_tick_tier(100, 5).with_tick_tier(1000, 10);
+    ///
+    /// assert_eq!(rules.tick_at(99), 1);
+    /// assert_eq!(rules.tick_at(100), 5);
+    /// assert_eq!(rules.tick_at(1500), 10);
+    /// ```
+    #[must_use]
+    pub fn with_tick_tier(mut self, from: u32, tick: u32) -> Self {
+        let pos = self.ticks.partition_point(|&(start, _)| start < from);
+
+        match self.ticks.get_mut(pos) {
+            Some(tier) if tier.0 == from => tier.1 = tick.max(1),
+            _ => self.ticks.insert(pos, (from, tick.max(1))),
+        }
+
+        self
+    }
+
+    /// Only accept quantities that are a multiple of the provided lot size.
+    #[must_use]
+    pub const fn with_lot(mut self, lot: u32) -> Self {
+        self.lot = if lot == 0 { 1 } else { lot };
+        self
+    }
+
+    /// Only accept orders for at least the provided quantity.
+    #[must_use]
+    pub const fn with_min_quantity(mut self, min_quantity: u32) -> Self {
+        self.min_quantity = min_quantity;
+        self
+    }
+
+    /// Only accept orders for at most the provided quantity.
+    #[must_use]
+    pub const fn with_max_quantity(mut self, max_quantity: u32) -> Self {
+        self.max_quantity = Some(max_quantity);
+        self
+    }
+
+    /// Only accept limit orders whose price times quantity is at most the
+    /// provided notional value.
+    #[must_use]
+    pub const fn with_max_notional(mut self, max_notional: u64) -> Self {
+        self.max_notional = Some(max_notional);
+        self
+    }
+
+    /// Get the lot size.
+    #[must_use]
+    pub const fn lot(&self) -> u32 {
+        self.lot
+    }
+
+    /// Get the tick size in force at the provided price. The complexity for
+    /// this operation is *O*(log *t*), where *t* is the number of tiers.
+    #[must_use]
+    pub fn tick_at(&self, price: u32) -> u32 {
+        let pos = self.ticks.partition_point(|&(start, _)| start <= price);
+        self.ticks[pos.saturating_sub(1)].1
+    }
+
+    /// Whether the price is a multiple of the tick size in force at it.
+    #[must_use]
+    pub fn is_on_tick(&self, price: u32) -> bool {
+        price % self.tick_at(price) == 0
+    }
+
+    /// Get the highest price on the tick grid at or below the provided one.
+    #[must_use]
+    pub fn round_down(&self, price: u32) -> u32 {
+        price - price % self.tick_at(price)
+    }
+
+    /// Get the lowest price on the tick grid at or above the provided one, if
+    /// there's room for it.
+    #[must_use]
+    pub fn round_up(&self, price: u32) -> Option<u32> {
+        let tick = self.tick_at(price);
+
+        match price % tick {
+            0 => Some(price),
+            rest => price.checked_add(tick - rest),
+        }
+    }
+
+    /// Check an incoming order against the rules, and return the reason of
+    /// the first violation. Limit and stop prices must be on the tick grid,
+    /// while pegged orders get their price from the book. The whole quantity
+    /// and the display size of iceberg orders must be in lots.
+    ///
+    /// # Errors
+    /// Return the reason the order must be rejected for.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::instrument::InstrumentRules;
+    /// use orderbook::order::{Order, Side};
+    /// use orderbook::order_book::RejectReason;
+    ///
+    /// let rules = InstrumentRules::new().with_tick(5).with_lot(100);
+    ///
+    /// assert_eq!(rules.check(&Order::new(1, 1, Side::Bid, 15, 200)), Ok(()));
+    /// assert_eq!(rules.check(&Order::new(2, 1, Side::Bid, 12, 200)), Err(RejectReason::TickSize));
+    /// assert_eq!(rules.check(&Order::new(3, 1, Side::Bid, 15, 250)), Err(RejectReason::LotSize));
+    /// ```
+    pub fn check(&self, order: &Order) -> Result<(), RejectReason> {
+        let quantity = order.leaves();
+        let limit = Some(order.price).filter(|_| !order.is_market() && order.peg.is_none());
+
+        if limit
+            .into_iter()
+            .chain(order.stop)
+            .any(|price| !self.is_on_tick(price))
+        {
+            return Err(RejectReason::TickSize);
+        }
+
+        if quantity < self.min_quantity || quantity == 0 {
+            return Err(RejectReason::QuantityTooLow);
+        }
+
+        if self.max_quantity.map_or(false, |max| quantity > max) {
+            return Err(RejectReason::QuantityTooHigh);
+        }
+
+        let lots = |quantity: u32| quantity % self.lot == 0;
+        if !lots(quantity) || order.display.map_or(false, |display| !lots(display)) {
+            return Err(RejectReason::LotSize);
+        }
+
+        let notional = limit.map(|price| u64::from(price) * u64::from(quantity));
+        if notional
+            .zip(self.max_notional)
+            .map_or(false, |(n, max)| n > max)
+        {
+            return Err(RejectReason::NotionalTooHigh);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for InstrumentRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reference data of a tradable instrument: its trading rules, the number of
+/// decimals of its prices, the matching policy, the price bands and the
+/// trading day of its order book.
+///
+/// Prices are always integers, `decimals` tells how many of their digits are
+/// fractional, for display purposes only.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Instrument {
+    pub symbol: String,
+    pub rules: InstrumentRules,
+    pub decimals: u32,
+    pub allocation: Allocation,
+    pub static_band: Option<Band>,
+    pub reference_price: Option<u32>,
+    pub dynamic_band: Option<(Band, u64)>,
+    pub session: Session,
+    pub schedule: Vec<(u64, Session)>,
+}
+
+impl Instrument {
+    #[must_use]
+    pub fn new(symbol: &str) -> Self {
+        Self { symbol: symbol.to_string(), ..Self::default() }
+    }
+
+    /// Build an empty order book following the reference data, with its
+    /// trading day already scheduled.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::instrument::Instrument;
+    /// use orderbook::session::Session;
+    ///
+    /// let mut instrument = Instrument::new("IBM");
+    /// instrument.session = Session::PreOpen;
+    /// instrument.schedule.push((930, Session::Continuous));
+    ///
+    /// let mut order_book = instrument.book();
+    /// order_book.advance(930);
+    ///
+    /// assert_eq!(order_book.session(), Session::Continuous);
+    /// ```
+    #[must_use]
+    pub fn book(&self) -> OrderBook {
+        let mut order_book = OrderBook::new()
+            .with_rules(self.rules.clone())
+            .with_allocation(self.allocation)
+            .with_session(self.session);
+
+        if let Some(band) = self.static_band {
+            order_book = order_book.with_static_band(band);
+        }
+
+        if let Some(price) = self.reference_price {
+            order_book = order_book.with_reference_price(price);
+        }
+
+        if let Some((band, reopen_after)) = self.dynamic_band {
+            order_book = order_book.with_dynamic_band(band, reopen_after);
+        }
+
+        for &(time, session) in &self.schedule {
+            order_book.schedule(time, session);
+        }
+
+        order_book
+    }
+}
+
+/// The instruments that can be traded, by symbol.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Registry {
+    instruments: BTreeMap<String, Instrument>,
+}
+
+impl Registry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self { instruments: BTreeMap::new() }
+    }
+
+    /// Add an instrument, replacing the one with the same symbol if any.
+    pub fn insert(&mut self, instrument: Instrument) {
+        self.instruments
+            .insert(instrument.symbol.clone(), instrument);
+    }
+
+    /// Get the instrument with the provided symbol, if it can be traded.
+    #[must_use]
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        self.instruments.get(symbol)
+    }
+
+    /// Iterate over the instruments, sorted by symbol.
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments.values()
+    }
+
+    /// Build an empty order book for each instrument, by symbol.
+    #[must_use]
+    pub fn books(&self) -> BTreeMap<String, OrderBook> {
+        self.iter()
+            .map(|instrument| (instrument.symbol.clone(), instrument.book()))
+            .collect()
+    }
+
+    /// Load the instruments from a CSV source, one per line with the symbol,
+    /// the tick size, the lot size and the price decimals, followed by the
+    /// optional `key=value` attributes are:
+    ///
+    /// - `tier=price:tick` a tick size from the price upwards, repeatable;
+    /// - `min`, `max` and `notional` the quantity and notional limits;
+    /// - `allocation` one of `fifo`, `pro-rata[:min]`, `pro-rata-top[:min]`
+    ///   and `lmm:user:share`;
+    /// - `static=percent` and `reference=price` the static band;
+    /// - `dynamic=percent:duration` the dynamic band and the duration of its
+    ///   volatility auctions;
+    /// - `start=session` the initial session;
+    /// - `schedule=time:session` a transition of the trading day, repeatable.
+    ///
+    /// Lines starting with `#` are comments.
+    ///
+    /// # Errors
+    /// Return an error for unreadable sources and malformed lines.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::instrument::Registry;
+    /// use orderbook::session::Session;
+    ///
+    /// let csv = "IBM, 5, 100, 2, tier=1000:10, start=pre-open, schedule=930:continuous";
+    /// let registry = Registry::from_reader(csv.as_bytes()).unwrap();
+    /// let instrument = registry.get("IBM").unwrap();
+    ///
+    /// assert_eq!(instrument.rules.tick_at(1500), 10);
+    /// assert_eq!(instrument.schedule, vec![(930, Session::Continuous)]);
+    /// assert!(registry.get("AAPL").is_none());
+    /// ```
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut reader = parser::csv_reader(reader);
+        let mut registry = Self::new();
+
+        for record in reader.records() {
+            registry.insert(Self::parse_instrument(&record?)?);
+        }
+
+        Ok(registry)
+    }
+
+    /// Build an instrument from a CSV record, see `from_reader`.
+    fn parse_instrument(record: &StringRecord) -> Result<Instrument, Box<dyn Error + Send + Sync>> {
+        let field = |pos, name| record.get(pos).ok_or(format!("Missing instrument {name}"));
+        let mut instrument = Instrument::new(field(0, "symbol")?);
+
+        instrument.rules = InstrumentRules::new()
+            .with_tick(field(1, "tick")?.parse()?)
+            .with_lot(field(2, "lot")?.parse()?);
+        instrument.decimals = field(3, "decimals")?.parse()?;
+
+        // A price has at most 10 digits
+        if instrument.decimals > 9 {
+            return Err(format!("Too many decimals {}", instrument.decimals).into());
+        }
+
+        for attribute in record.iter().skip(4) {
+            let (key, value) = attribute.split_once('=').ok_or("Missing attribute value")?;
+            let (key, value) = (key.trim(), value.trim());
+            let rules = instrument.rules.clone();
+
+            match key {
+                "tier" => {
+                    let (from, tick) = value.split_once(':').ok_or("Missing tier tick")?;
+                    instrument.rules = rules.with_tick_tier(from.parse()?, tick.parse()?);
+                }
+                "min" => instrument.rules = rules.with_min_quantity(value.parse()?),
+                "max" => instrument.rules = rules.with_max_quantity(value.parse()?),
+                "notional" => instrument.rules = rules.with_max_notional(value.parse()?),
+                "allocation" => instrument.allocation = Self::parse_allocation(value)?,
+                "static" => instrument.static_band = Some(Band::new(value.parse()?)),
+                "reference" => instrument.reference_price = Some(value.parse()?),
+                "dynamic" => {
+                    let (percent, duration) = value.split_once(':').ok_or("Missing duration")?;
+                    instrument.dynamic_band =
+                        Some((Band::new(percent.parse()?), duration.parse()?));
+                }
+                "start" => instrument.session = value.parse()?,
+                "schedule" => {
+                    let (time, session) = value.split_once(':').ok_or("Missing session")?;
+                    instrument.schedule.push((time.parse()?, session.parse()?));
+                }
+                _ => return Err(format!("Unknown attribute {key}").into()),
+            }
+        }
+
+        Ok(instrument)
+    }
+
+    /// Converts an allocation policy to its in-memory representation.
+    fn parse_allocation(value: &str) -> Result<Allocation, Box<dyn Error + Send + Sync>> {
+        let mut parts = value.split(':');
+        let min_allocation = |part: Option<&str>| part.map_or(Ok(0), str::parse);
+
+        let allocation = match parts.next() {
+            Some("fifo") => Allocation::Fifo,
+            Some("pro-rata") => Allocation::ProRata {
+                top_order: false,
+                min_allocation: min_allocation(parts.next())?,
+            },
+            Some("pro-rata-top") => Allocation::ProRata {
+                top_order: true,
+                min_allocation: min_allocation(parts.next())?,
+            },
+            Some("lmm") => {
+                let user_id = parts.next().ok_or("Missing lead market maker")?.parse()?;
+                let share = parts.next().ok_or("Missing lead market maker share")?;
+
+                Allocation::fifo_lmm(user_id, share.parse()?)
+                    .ok_or(format!("Lead market maker share above 100 {share}"))?
+            }
+            _ => return Err(format!("Unknown allocation {value}").into()),
+        };
+
+        Ok(allocation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::order::Side;
+
+    use super::*;
+
+    #[test]
+    fn test_tick_tiers() {
+        let rules = InstrumentRules::new()
+            .with_tick_tier(1000, 10)
+            .with_tick_tier(100, 5)
+            .with_tick_tier(100, 2);
+
+        assert_eq!(rules.tick_at(0), 1);
+        assert_eq!(rules.tick_at(101), 2);
+        assert_eq!(rules.tick_at(1000), 10);
+        assert_eq!(rules.round_down(1005), 1000);
+        assert_eq!(rules.round_up(997), Some(998));
+        assert_eq!(rules.round_up(999), Some(1000));
+        assert!(!rules.is_on_tick(1005));
+    }
+
+    #[test]
+    fn test_registry_from_reader() {
+        let csv = "# symbol, tick, lot, decimals
+            IBM, 1, 1, 0
+            VAL, 5, 10, 2, allocation=lmm:7:40, static=10, dynamic=5:30, max=1000";
+        let registry = Registry::from_reader(csv.as_bytes()).unwrap();
+        let symbols: Vec<&str> = registry.iter().map(|i| i.symbol.as_str()).collect();
+
+        assert_eq!(symbols, vec!["IBM", "VAL"]);
+
+        let val = registry.get("VAL").unwrap();
+        assert_eq!(
+            val.allocation,
+            Allocation::FifoLmm { user_id: 7, share: 40 }
+        );
+        assert_eq!(val.dynamic_band, Some((Band::new(5), 30)));
+        assert_eq!(val.rules.lot(), 10);
+        assert_eq!(
+            val.rules.check(&Order::new(1, 1, Side::Bid, 10, 2000)),
+            Err(RejectReason::QuantityTooHigh)
+        );
+
+        assert!(Registry::from_reader("IBM, 1".as_bytes()).is_err());
+        assert!(Registry::from_reader("IBM, 1, 1, 0, color=red".as_bytes()).is_err());
+        assert!(Registry::from_reader("IBM, 1, 1, 10".as_bytes()).is_err());
+        assert!(Registry::from_reader("IBM, 1, 1, 0, allocation=lmm:7:101".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let rules = InstrumentRules::new()
+            .with_lot(10)
+            .with_min_quantity(20)
+            .with_max_quantity(1000)
+            .with_max_notional(5000);
+        let order = |price, quantity| Order::new(1, 1, Side::Ask, price, quantity);
+
+        assert_eq!(
+            rules.check(&order(10, 0)),
+            Err(RejectReason::QuantityTooLow)
+        );
+        assert_eq!(
+            rules.check(&order(10, 10)),
+            Err(RejectReason::QuantityTooLow)
+        );
+        assert_eq!(
+            rules.check(&order(1, 1010)),
+            Err(RejectReason::QuantityTooHigh)
+        );
+        assert_eq!(
+            rules.check(&order(10, 600)),
+            Err(RejectReason::NotionalTooHigh)
+        );
+        assert_eq!(rules.check(&order(10, 500)), Ok(()));
+
+        // Market orders have no notional, icebergs show whole lots
+        assert_eq!(rules.check(&order(0, 1000)), Ok(()));
+        let iceberg = order(10, 100).with_display(15);
+        assert_eq!(rules.check(&iceberg), Err(RejectReason::LotSize));
+    }
+}
@@ -0,0 +1,352 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Lines, Write};
+
+use crate::command::Command;
+use crate::engine::{Event, MatchingEngine};
+use crate::parser::{csv_reader, format_command, parse_record, ParseError};
+
+/// Why a journal can't be read back: the source failed, a line isn't an
+/// entry, an entry was altered or is out of sequence, its command can't be
+/// parsed anymore, or the journal ends before an entry it should hold. Lines
+/// are numbered from one.
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    Malformed {
+        line: u64,
+    },
+    Checksum {
+        line: u64,
+        seq: u64,
+    },
+    Sequence {
+        line: u64,
+        expected: u64,
+        found: u64,
+    },
+    Parse {
+        line: u64,
+        error: ParseError,
+    },
+    Truncated {
+        seq: u64,
+    },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Cannot read the journal: {error}"),
+            Self::Malformed { line } => write!(f, "{line}: Malformed entry"),
+            Self::Checksum { line, seq } => write!(f, "{line}: Wrong checksum for entry {seq}"),
+            Self::Sequence { line, expected, found } => {
+                write!(f, "{line}: Expected entry {expected}, found {found}")
+            }
+            Self::Parse { line, error } => write!(f, "{line}: {error}"),
+            Self::Truncated { seq } => write!(f, "Missing entry {seq}"),
+        }
+    }
+}
+
+impl Error for JournalError {}
+
+impl From<io::Error> for JournalError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// An entry of the journal: a command along with its sequence number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub seq: u64,
+    pub command: Command,
+}
+
+/// Appends commands to a journal, one line each, before they're processed.
+/// Each line holds the sequence number of the entry, starting from one, its
+/// checksum and the command as a record of the input file:
+///
+/// `seq, checksum, command`
+///
+/// The checksum is the 64 bits FNV-1a hash of the line without it, as
+/// `seq, command`, written in hexadecimal.
+pub struct JournalWriter<W> {
+    writer: W,
+    seq: u64,
+}
+
+impl<W: Write> JournalWriter<W> {
+    /// Start a new journal on the provided writer.
+    pub fn new(writer: W) -> Self {
+        Self { writer, seq: 1 }
+    }
+
+    /// Number the entries from the provided sequence number, so that a
+    /// journal can be resumed where it ended.
+    #[must_use]
+    pub fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// The sequence number of the next entry.
+    #[must_use]
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Get the writer back.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Append a command and flush the writer, so that the entry is persisted
+    /// by the time the command is processed. Return the sequence number of
+    /// the entry.
+    ///
+    /// # Errors
+    /// Return the error of the writer, in that case the entry may be partially
+    /// written and the journal shouldn't be appended to anymore.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::command::Command;
+    /// use orderbook::journal::JournalWriter;
+    ///
+    /// let mut journal = JournalWriter::new(Vec::new());
+    /// journal.append(&Command::Clock { time: 930 }).unwrap();
+    ///
+    /// assert_eq!(journal.seq(), 2);
+    /// ```
+    pub fn append(&mut self, command: &Command) -> io::Result<u64> {
+        let seq = self.seq;
+        let command = format_command(command);
+        let checksum = checksum(seq, &command);
+
+        writeln!(self.writer, "{seq}, {checksum:016x}, {command}")?;
+        self.writer.flush()?;
+
+        self.seq += 1;
+        Ok(seq)
+    }
+}
+
+/// Iterates over the entries of a journal, checking that each of them is
+/// intact and follows the previous one. Reading goes on after an error, it's
+/// up to the caller to stop.
+pub struct JournalReader<R> {
+    lines: Lines<R>,
+    line: u64,
+    seq: u64,
+}
+
+impl<R: BufRead> JournalReader<R> {
+    /// Read the entries of the provided source, the first one is expected to
+    /// be number one.
+    pub fn new(reader: R) -> Self {
+        Self { lines: reader.lines(), line: 0, seq: 1 }
+    }
+
+    /// The sequence number the next entry is expected to have, once the
+    /// journal is over it's the one to resume from.
+    #[must_use]
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Check the line of an entry, and parse its command.
+    fn parse_entry(&self, text: &str) -> Result<Entry, JournalError> {
+        let line = self.line;
+        let malformed = || JournalError::Malformed { line };
+
+        let (seq, rest) = text.split_once(',').ok_or_else(malformed)?;
+        let (sum, command) = rest.split_once(',').ok_or_else(malformed)?;
+        let command = command.trim();
+
+        let seq: u64 = seq.trim().parse().map_err(|_| malformed())?;
+        let sum = u64::from_str_radix(sum.trim(), 16).map_err(|_| malformed())?;
+
+        if sum != checksum(seq, command) {
+            return Err(JournalError::Checksum { line, seq });
+        }
+        if seq != self.seq {
+            return Err(JournalError::Sequence { line, expected: self.seq, found: seq });
+        }
+
+        let record = csv_reader(command.as_bytes())
+            .records()
+            .next()
+            .and_then(Result::ok)
+            .ok_or_else(malformed)?;
+        let command = parse_record(&record).map_err(|error| JournalError::Parse { line, error })?;
+
+        Ok(Entry { seq, command })
+    }
+}
+
+impl<R: BufRead> Iterator for JournalReader<R> {
+    type Item = Result<Entry, JournalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let text = match self.lines.next()? {
+            Ok(text) => text,
+            Err(error) => return Some(Err(error.into())),
+        };
+        self.line += 1;
+
+        let entry = self.parse_entry(&text);
+        if entry.is_ok() {
+            self.seq += 1;
+        }
+
+        Some(entry)
+    }
+}
+
+/// Replay a journal through the provided engine, and return the events of
+/// its commands in order. Replaying into a new engine, with the same mode and
+/// instruments as the one that processed the commands, gives the same events.
+///
+/// # Errors
+/// Return the first entry that can't be read back, without processing any
+/// further.
+///
+/// # Example
+/// ```
+/// use orderbook::command::Command;
+/// use orderbook::engine::MatchingEngine;
+/// use orderbook::journal::{replay, JournalWriter};
+/// use orderbook::order::{Order, Side};
+///
+/// let command = Command::New {
+///     symbol: String::from("IBM"),
+///     order: Order::new(1, 1, Side::Bid, 10, 100),
+/// };
+///
+/// let mut engine = MatchingEngine::new();
+/// let mut journal = JournalWriter::new(Vec::new());
+/// journal.append(&command).unwrap();
+/// let events = engine.process(command);
+///
+/// let journal = journal.into_inner();
+/// let replayed = replay(journal.as_slice(), &mut MatchingEngine::new()).unwrap();
+///
+/// assert_eq!(replayed, events);
+/// ```
+pub fn replay<R: BufRead>(
+    reader: R,
+    engine: &mut MatchingEngine,
+) -> Result<Vec<Event>, JournalError> {
+    let mut events = Vec::new();
+
+    for entry in JournalReader::new(reader) {
+        events.extend(engine.process(entry?.command));
+    }
+
+    Ok(events)
+}
+
+/// The checksum of an entry, the hash of its line without the checksum.
+fn checksum(seq: u64, command: &str) -> u64 {
+    fnv1a(format!("{seq}, {command}").as_bytes())
+}
+
+/// The 64 bits FNV-1a hash of some bytes.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::order::{Order, Side};
+
+    use super::*;
+
+    fn journal(commands: &[Command]) -> String {
+        let mut journal = JournalWriter::new(Vec::new());
+
+        for command in commands {
+            journal.append(command).unwrap();
+        }
+
+        String::from_utf8(journal.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let commands = vec![
+            Command::New {
+                symbol: String::from("IBM"),
+                order: Order::new(1, 1, Side::Bid, 10, 100).with_display(10),
+            },
+            Command::Cancel { user_id: 1, order_id: 1 },
+            Command::Clock { time: 930 },
+        ];
+        let text = journal(&commands);
+
+        assert!(text.starts_with("1, "));
+        assert!(text.ends_with(", K, 930\n"));
+
+        let mut reader = JournalReader::new(text.as_bytes());
+        let entries: Vec<Entry> = reader.by_ref().map(Result::unwrap).collect();
+        let seqs: Vec<u64> = entries.iter().map(|entry| entry.seq).collect();
+
+        assert_eq!(seqs, vec![1, 2, 3]);
+        assert_eq!(entries[0].command, commands[0]);
+        let seq = reader.seq();
+        assert_eq!(seq, 4);
+
+        // Resumed journals go on with the sequence
+        let mut journal = JournalWriter::new(text.into_bytes()).with_seq(seq);
+        journal.append(&Command::Clock { time: 1000 }).unwrap();
+        let text = String::from_utf8(journal.into_inner()).unwrap();
+
+        assert_eq!(
+            JournalReader::new(text.as_bytes())
+                .filter(Result::is_ok)
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_journal_errors() {
+        let text = journal(&[
+            Command::Clock { time: 930 },
+            Command::Clock { time: 1000 },
+            Command::Clock { time: 1100 },
+        ]);
+        let lines: Vec<&str> = text.lines().collect();
+
+        // Altered entries fail their checksum
+        let altered = text.replace("K, 1000", "K, 1001");
+        let error = JournalReader::new(altered.as_bytes())
+            .find_map(Result::err)
+            .unwrap();
+
+        assert!(matches!(error, JournalError::Checksum { line: 2, seq: 2 }));
+
+        // Missing entries break the sequence
+        let missing = format!("{}\n{}\n", lines[0], lines[2]);
+        let error = JournalReader::new(missing.as_bytes())
+            .find_map(Result::err)
+            .unwrap();
+
+        assert!(matches!(
+            error,
+            JournalError::Sequence { line: 2, expected: 2, found: 3 }
+        ));
+
+        // Torn entries can't be read
+        let torn = &lines[0][..10];
+        let error = JournalReader::new(torn.as_bytes())
+            .find_map(Result::err)
+            .unwrap();
+
+        assert_eq!(error.to_string(), "1: Malformed entry");
+    }
+}
@@ -1,6 +1,23 @@
+pub mod allocation;
+pub mod auction;
+pub mod backtest;
+pub mod band;
 pub mod book_side;
+pub mod command;
+pub mod differential;
+pub mod engine;
+pub mod generator;
+pub mod instrument;
+pub mod journal;
 pub mod order;
 pub mod order_book;
+pub mod parser;
 pub mod price_level;
+pub mod reference;
+pub mod scenario;
+pub mod scheduler;
+pub mod session;
+pub mod snapshot;
+pub mod trigger_book;
 
 pub use order_book::*;
@@ -1,33 +1,155 @@
 use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::process;
+use std::str::FromStr;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
-use std::{collections::HashMap, fs::File};
-
-use csv::{ReaderBuilder, StringRecord, Trim};
-use orderbook::OrderOutcome;
-use orderbook::{order::Side, OrderBook};
-
-/// Representation of the three commands that can be read from the input file
-enum Command {
-    New {
-        user_id: usize,
-        symbol: String,
-        price: u32,
-        quantity: u32,
-        side: Side,
-        order_id: usize,
-    },
-    Cancel {
-        order_id: usize,
-    },
-    Flush,
+
+use orderbook::book_side::Backend;
+use orderbook::differential::compare;
+use orderbook::engine::{Event, MatchingEngine};
+use orderbook::generator::Generator;
+use orderbook::instrument::Registry;
+use orderbook::journal::{JournalReader, JournalWriter};
+use orderbook::parser::{
+    format_command, format_event_json, format_outcome, CommandReader, ErrorPolicy,
+};
+use orderbook::scenario;
+use orderbook::snapshot::{recover, Snapshot};
+use orderbook::{MatchingMode, OrderOutcome};
+
+const USAGE: &str = "Usage: orderbook [COMMAND] [OPTIONS]
+
+Commands:
+    run                       process the commands of the input (default)
+    scenarios                 run each scenario of the input, and compare its
+                              output with the one of the expected file
+    verify                    replay the journal into new books, and compare
+                              their output with the expected file, if any
+    diff                      run the input through two engines, and report the
+                              first outcome they disagree on
+    generate                  write a synthetic flow of commands, the same for
+                              the same seed and settings
+
+Options:
+    -i, --input <PATH>        read the commands from a file, `-` for stdin (default)
+    -x, --expected <PATH>     read the expected output from a file
+    -o, --output <PATH>       write the outcomes to a file, `-` for stdout (default)
+    -j, --journal <PATH>      append the commands to a journal before processing them
+    -s, --snapshot <PATH>     recover from a snapshot along with the journal, and
+                              write a new one once the input is over
+    -m, --mode <MODE>         `reject` crossing orders (default) or `trade` them
+    -b, --backend <BACKEND>   store price levels in a `rb-tree` (default) or `b-tree`
+        --versus <SETTINGS>   compare with the engine where some options are
+                              changed, as in `backend=b-tree,mode=trade`
+                              (default: the other backend)
+    -f, --format <FORMAT>     write the outcomes as `csv` (default) or `json` lines
+    -e, --errors <POLICY>     `abort` (default), `skip` or `reject` malformed records
+        --instruments <PATH>  only trade the instruments listed in the file
+    -q, --quiet               only write the statistics once the input is over
+    -n, --count <COUNT>       generate that many commands (default: 1000)
+        --seed <SEED>         seed of the generated flow (default: 0)
+        --symbols <COUNT>     generate orders for that many symbols (default: 1)
+        --users <COUNT>       generate orders from that many users (default: 10)
+        --rate <RATE>         generate that many events per unit of time on
+                              average (default: 1)
+        --volatility <TICKS>  move prices by up to that many ticks either way
+                              before each order (default: 1)
+        --cancels <RATIO>     share of the generated events that are cancels
+                              (default: 0.3)
+        --marketable <RATIO>  share of the generated orders that are marketable
+                              (default: 0.1)
+    -h, --help                print this message";
+
+// What the program does with its input
+#[derive(Clone, Copy, PartialEq)]
+enum Subcommand {
+    Run,
+    Scenarios,
+    Verify,
+    Diff,
+    Generate,
+}
+
+// An option changed for the engine the diff compares with
+enum Setting {
+    Mode(MatchingMode),
+    Backend(Backend),
+    Instruments(String),
+}
+
+// Format of the outcomes written to the output
+#[derive(Clone, Copy)]
+enum Format {
+    Csv,
+    Json,
+}
+
+// Options of the program, as read from the command line
+struct Options {
+    command: Subcommand,
+    input: Option<String>,
+    expected: Option<String>,
+    output: Option<String>,
+    journal: Option<String>,
+    snapshot: Option<String>,
+    mode: MatchingMode,
+    backend: Backend,
+    versus: Vec<Setting>,
+    format: Format,
+    errors: ErrorPolicy,
+    instruments: Option<String>,
+    quiet: bool,
+    count: usize,
+    generator: Generator,
+}
+
+// Statistics on the outcomes, written instead of them in quiet mode
+#[derive(Default)]
+struct Stats {
+    acks: usize,
+    rejects: usize,
+    trades: usize,
+    volume: u64,
 }
 
 fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     // Specify the writer channel type
-    type WriterTarget = Option<OrderOutcome>;
+    type WriterTarget = Vec<Event>;
     type WriterChannel = (Sender<WriterTarget>, Receiver<WriterTarget>);
 
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    match options.command {
+        Subcommand::Run => {}
+        Subcommand::Generate => return Ok(generate(options)?),
+        Subcommand::Scenarios | Subcommand::Verify | Subcommand::Diff => {
+            let passed = match options.command {
+                Subcommand::Scenarios => run_scenarios(&options)?,
+                Subcommand::Verify => verify(&options)?,
+                _ => diff(&options)?,
+            };
+
+            if !passed {
+                process::exit(1);
+            }
+
+            return Ok(());
+        }
+    }
+
     // Get two communication channels (reader<->worker)
     let (reader_to_worker, from_reader) = mpsc::channel();
     let (to_reader, reader_from_worker) = mpsc::channel();
@@ -36,159 +158,593 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let (writer_to_worker, from_writer) = mpsc::channel();
     let (to_writer, writer_from_worker): WriterChannel = mpsc::channel();
 
-    // Get the CSV reader
-    let file_path = "files/input_file.csv";
-    let file = File::open(file_path)?;
-    let mut reader = ReaderBuilder::new()
-        .trim(Trim::All)
-        .flexible(true)
-        .has_headers(false)
-        .comment(Some(b'#'))
-        .from_reader(file);
-
-    // Spawn the reader thread
+    // Get the CSV reader, from a file or stdin
+    let (file_path, input) = open_input(&options)?;
+    let reader = CommandReader::new(file_path, input);
+
+    let mut output = open_output(&options)?;
+    let registry = load_registry(options.instruments.as_deref())?;
+    let mut engine = new_engine(options.mode, options.backend, registry.as_ref());
+    let mut journal = open_journal(&options, &mut engine)?;
+
+    // Spawn the reader thread, it stops as soon as the worker does
     let reader_thread = thread::spawn(move || {
-        for result in reader.records() {
-            let record = result.expect("Broken record");
-            reader_to_worker.send(parse_record(&record)).unwrap();
-            reader_from_worker.recv().unwrap();
+        for command in reader {
+            if reader_to_worker.send(command).is_err() || reader_from_worker.recv().is_err() {
+                break;
+            }
         }
     });
 
     // Spawn the writer thread
-    let writer_thread = thread::spawn(move || {
+    let (format, quiet) = (options.format, options.quiet);
+    let writer_thread = thread::spawn(move || -> io::Result<()> {
+        let mut stats = Stats::default();
+        let mut result = Ok(());
+
         // Start the worker
         writer_to_worker.send(()).unwrap();
 
-        // Keep reading messages from the worker thread until it shuts down
-        while let Ok(outcome) = writer_from_worker.recv() {
+        // Keep reading messages from the worker thread until it shuts down,
+        // once a write fails the messages are only acknowledged
+        while let Ok(events) = writer_from_worker.recv() {
             writer_to_worker.send(()).unwrap();
 
-            if outcome == None {
-                // Last command was a flush
-                continue;
+            // A flush has no outcome
+            for event in &events {
+                stats.count(&event.outcome);
+
+                if result.is_ok() && !quiet {
+                    result = write_event(&mut output, event, format);
+                }
             }
+        }
 
-            print_outcome(&outcome.unwrap());
+        result?;
+        if quiet {
+            stats.write(&mut output, format)?;
         }
-    });
 
-    // Build the order books collection
-    let mut order_books = HashMap::new();
+        output.flush()
+    });
 
-    // Build a map to keep track of the symbol for each order
-    let mut order_symbols = HashMap::new();
+    // Records that can't be parsed are reported, then handled by the policy
+    let policy = options.errors;
+    let mut aborted = false;
+    let mut unjournaled = None;
 
     // The main thread will act as the worker thread and
     // compute commands received from the reader
     while let Ok(command) = from_reader.recv() {
-        to_reader.send(()).unwrap();
+        if let Err(error) = &command {
+            eprintln!("{error}");
 
-        // Perform the action required by the command
-        let outcome = match command? {
-            Command::Flush => {
-                order_books = HashMap::new();
-                None
+            // Stop right away, without reading any further
+            if policy == ErrorPolicy::Abort {
+                aborted = true;
+                break;
             }
-            Command::New { user_id, order_id, side, price, quantity, symbol } => {
-                let symbol_clone = symbol.clone();
-                let order_book = order_books.entry(symbol).or_insert_with(OrderBook::new);
-                order_symbols.insert(order_id, symbol_clone);
-                Some(order_book.submit_order(side, price, quantity, user_id, order_id))
-            }
-            Command::Cancel { order_id, .. } => {
-                let symbol = order_symbols.get(&order_id).unwrap();
-                let order_book = order_books.get_mut(symbol).unwrap();
-                Some(order_book.cancel_order(order_id))
+        }
+
+        to_reader.send(()).unwrap();
+
+        let events = match command {
+            // Persist the command, then perform the action it requires
+            Ok(command) => {
+                if let Some(journal) = &mut journal {
+                    if let Err(error) = journal.append(&command) {
+                        unjournaled = Some(error);
+                        break;
+                    }
+                }
+
+                engine.process(command)
             }
+            Err(error) if policy == ErrorPolicy::Reject => error
+                .outcome()
+                .map(|outcome| vec![Event { symbol: String::new(), outcome }])
+                .unwrap_or_default(),
+            Err(_) => Vec::new(),
         };
 
         from_writer.recv().unwrap();
-        to_writer.send(outcome).unwrap();
+        to_writer.send(events).unwrap();
     }
 
     // Ensure that all the threads have ended
-    drop(to_writer);
-    writer_thread.join().unwrap();
+    drop((to_reader, from_reader, to_writer));
+    let written = writer_thread.join().unwrap();
     reader_thread.join().unwrap();
 
+    // A closed pipe only means that the rest of the output isn't wanted
+    match written {
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe => return Err(error.into()),
+        _ => {}
+    }
+
+    // The books are consistent with the journal, whatever stopped the input
+    if let (Some(journal), Some(path)) = (&journal, &options.snapshot) {
+        write_snapshot(path, &engine.snapshot(journal.seq() - 1))?;
+    }
+
+    // Commands can't be processed unless they're in the journal
+    if let Some(error) = unjournaled {
+        return Err(format!("Cannot append to the journal: {error}").into());
+    }
+
+    if aborted {
+        process::exit(1);
+    }
+
     Ok(())
 }
 
-/// Parse a CSV string record to build an in-memory command representation.
-fn parse_record(record: &StringRecord) -> Result<Command, Box<dyn Error + Send + Sync>> {
-    let command = match record.get(0).unwrap() {
-        "N" => Command::New {
-            user_id: record.get(1).unwrap().parse()?,
-            symbol: record.get(2).unwrap().to_string(),
-            price: record.get(3).unwrap().parse()?,
-            quantity: record.get(4).unwrap().parse()?,
-            side: parse_side_from_csv(record.get(5).unwrap()),
-            order_id: record.get(6).unwrap().parse()?,
-        },
-        "C" => Command::Cancel { order_id: record.get(2).unwrap().parse()? },
-        _ => Command::Flush,
+// Open the journal for appending, if there's one. An existing journal must be
+// intact, the books recover their state from it, starting from the snapshot
+// when there's one, and its sequence goes on from the last entry
+fn open_journal(
+    options: &Options,
+    engine: &mut MatchingEngine,
+) -> Result<Option<JournalWriter<BufWriter<File>>>, Box<dyn Error + Send + Sync>> {
+    let path = match &options.journal {
+        Some(path) => path,
+        None => return Ok(None),
     };
 
-    Ok(command)
-}
-
-/// Converts a side character to its in-memory Side representation.
-fn parse_side_from_csv(csv_side: &str) -> Side {
-    if csv_side == "B" {
-        Side::Bid
-    } else {
-        Side::Ask
-    }
-}
-
-/// Converts a Side enum to a side character for output purposes.
-fn parse_side_to_csv(side: Side) -> &'static str {
-    if side == Side::Bid {
-        "B"
-    } else {
-        "S"
-    }
-}
-
-/// Write the completion message to stdout. The message depends on the outcome.
-fn print_outcome(outcome: &OrderOutcome) {
-    match outcome {
-        OrderOutcome::Created { user_id, order_id } => {
-            println!("A, {user_id}, {order_id}");
-        }
-        OrderOutcome::TopOfBook { user_id, order_id, side, top_price, volume } => {
-            println!("A, {user_id}, {order_id}");
-
-            let side = parse_side_to_csv(*side);
-            let top_price = top_price.map_or_else(|| String::from("-"), |price| price.to_string());
-            let volume = volume.map_or_else(|| String::from("-"), |price| price.to_string());
-            println!("B, {side}, {top_price}, {volume}");
-        }
-        OrderOutcome::Rejected { user_id, order_id } => {
-            println!("R, {user_id}, {order_id}");
-        }
-        OrderOutcome::Traded {
-            user_id,
-            order_id,
-            user_id_buy,
-            order_id_buy,
-            user_id_sell,
-            order_id_sell,
-            price,
-            quantity,
-            side,
-            top_price,
-            volume,
-        } => {
-            println!("A, {user_id}, {order_id}");
-            println!("T, {user_id_buy}, {order_id_buy}, {user_id_sell}, {order_id_sell}, {price}, {quantity}");
-            if let Some(side) = side {
-                let side = parse_side_to_csv(*side);
-                let top_price = top_price.map_or(String::from("-"), |p| p.to_string());
-                let volume = volume.map_or(String::from("-"), |v| v.to_string());
-                println!("B, {side}, {top_price}, {volume}");
+    let mut seq = 1;
+    if let Ok(file) = File::open(path) {
+        let snapshot = match options.snapshot.as_deref().map(File::open) {
+            Some(Ok(file)) => Snapshot::read(BufReader::new(file)).map_err(|error| {
+                format!(
+                    "{}:{}",
+                    options.snapshot.as_deref().unwrap_or_default(),
+                    error
+                )
+            })?,
+            Some(Err(error)) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => engine.snapshot(0),
+        };
+        let mut reader = JournalReader::new(BufReader::new(file));
+
+        recover(&snapshot, &mut reader, engine).map_err(|error| format!("{path}:{error}"))?;
+        seq = reader.seq();
+    }
+
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Some(JournalWriter::new(BufWriter::new(file)).with_seq(seq)))
+}
+
+// Write the snapshot next to its path first, then move it over, so that the
+// previous one stays whole until the new one is
+fn write_snapshot(path: &str, snapshot: &Snapshot) -> io::Result<()> {
+    let temp = format!("{path}.tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+
+    snapshot.write(&mut writer)?;
+    writer.into_inner()?.sync_all()?;
+
+    fs::rename(temp, path)
+}
+
+// Get the input, from a file or stdin, along with its name
+fn open_input(options: &Options) -> io::Result<(&str, Box<dyn Read + Send>)> {
+    Ok(match options.input.as_deref() {
+        None | Some("-") => ("stdin", Box::new(io::stdin())),
+        Some(path) => (path, Box::new(File::open(path)?)),
+    })
+}
+
+// Get the writer, to a file or stdout
+fn open_output(options: &Options) -> io::Result<Box<dyn Write + Send>> {
+    Ok(match options.output.as_deref() {
+        None | Some("-") => Box::new(BufWriter::new(io::stdout())),
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+    })
+}
+
+// Load the instrument registry, if there's one
+fn load_registry(path: Option<&str>) -> Result<Option<Registry>, Box<dyn Error + Send + Sync>> {
+    match path {
+        Some(path) => Ok(Some(Registry::from_reader(File::open(path)?)?)),
+        None => Ok(None),
+    }
+}
+
+// Only the symbols of the instrument registry can be traded, when there's
+// one, otherwise a book is created for each new symbol
+fn new_engine(mode: MatchingMode, backend: Backend, registry: Option<&Registry>) -> MatchingEngine {
+    let engine = MatchingEngine::new().with_mode(mode).with_backend(backend);
+
+    match registry {
+        Some(registry) => engine.with_registry(registry.clone()),
+        None => engine,
+    }
+}
+
+// Run each scenario of the input through a new engine and write its report,
+// followed by the totals, then tell whether they all passed
+fn run_scenarios(options: &Options) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let (file_path, mut input) = (String::from("stdin"), String::new());
+    let file_path = match options.input.as_deref() {
+        None | Some("-") => {
+            io::stdin().read_to_string(&mut input)?;
+            file_path
+        }
+        Some(path) => {
+            input = fs::read_to_string(path)?;
+            path.to_string()
+        }
+    };
+    let expected = fs::read_to_string(options.expected.as_deref().unwrap_or_default())?;
+
+    let registry = load_registry(options.instruments.as_deref())?;
+    let reports = scenario::run_scenarios(&file_path, &input, &expected, || {
+        new_engine(options.mode, options.backend, registry.as_ref())
+    })?;
+    let passed = reports.iter().filter(|report| report.passed()).count();
+
+    let mut output = open_output(options)?;
+    for report in &reports {
+        writeln!(output, "{report}")?;
+    }
+    writeln!(
+        output,
+        "\n{} passed, {} failed",
+        passed,
+        reports.len() - passed
+    )?;
+    output.flush()?;
+
+    Ok(passed == reports.len())
+}
+
+// Replay the journal into a new engine, write how many entries it holds and,
+// when there's an expected output, compare it byte for byte with the replayed
+// one written the same way, then tell whether they're the same
+fn verify(options: &Options) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let path = options.journal.as_deref().unwrap_or_default();
+    let registry = load_registry(options.instruments.as_deref())?;
+    let mut engine = new_engine(options.mode, options.backend, registry.as_ref());
+    let mut reader = JournalReader::new(BufReader::new(File::open(path)?));
+
+    let mut replayed = Vec::new();
+    let mut stats = Stats::default();
+
+    for entry in &mut reader {
+        let entry = entry.map_err(|error| format!("{path}:{error}"))?;
+
+        for event in engine.process(entry.command) {
+            stats.count(&event.outcome);
+
+            if !options.quiet {
+                write_event(&mut replayed, &event, options.format)?;
             }
         }
+    }
+
+    if options.quiet {
+        stats.write(&mut replayed, options.format)?;
+    }
+
+    let mut output = open_output(options)?;
+    writeln!(output, "{}: {} entries", path, reader.seq() - 1)?;
+
+    let passed = match &options.expected {
+        Some(expected_path) => {
+            let expected = fs::read(expected_path)?;
+
+            match first_difference(&expected, &replayed) {
+                None => {
+                    writeln!(output, "{expected_path}: same output")?;
+                    true
+                }
+                Some((line, expected, replayed)) => {
+                    writeln!(output, "{expected_path}:{line}: different output")?;
+                    writeln!(output, "    - {expected}\n    + {replayed}")?;
+                    false
+                }
+            }
+        }
+        None => true,
     };
+
+    output.flush()?;
+    Ok(passed)
+}
+
+// Run the input through the engine of the options and through the one where
+// the settings to compare with are changed, write how they compare, then tell
+// whether they agree all along
+fn diff(options: &Options) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let (mut mode, mut backend, mut instruments) = (
+        options.mode,
+        options.backend,
+        options.instruments.as_deref(),
+    );
+
+    for setting in &options.versus {
+        match setting {
+            Setting::Mode(value) => mode = *value,
+            Setting::Backend(value) => backend = *value,
+            Setting::Instruments(path) => instruments = Some(path),
+        }
+    }
+
+    let registry = load_registry(options.instruments.as_deref())?;
+    let mut baseline = new_engine(options.mode, options.backend, registry.as_ref());
+    let mut candidate = new_engine(mode, backend, load_registry(instruments)?.as_ref());
+
+    let (file_path, input) = open_input(options)?;
+    let comparison = compare(
+        CommandReader::new(file_path, input),
+        &mut baseline,
+        &mut candidate,
+    )?;
+
+    let mut output = open_output(options)?;
+    match &comparison.divergence {
+        Some(divergence) => writeln!(output, "{file_path}:{divergence}")?,
+        None if comparison.same_books => writeln!(
+            output,
+            "{}: {} commands, same outcomes and books",
+            file_path, comparison.commands
+        )?,
+        None => writeln!(
+            output,
+            "{}: {} commands, same outcomes, different books",
+            file_path, comparison.commands
+        )?,
+    }
+
+    output.flush()?;
+    Ok(comparison.passed())
+}
+
+// Write the commands of the generated flow to the output, as records of the
+// input file
+fn generate(options: Options) -> io::Result<()> {
+    let mut output = open_output(&options)?;
+
+    for command in options.generator.take(options.count) {
+        writeln!(output, "{}", format_command(&command))?;
+    }
+
+    output.flush()
+}
+
+// Find the first line where two outputs differ, along with both versions of
+// it, lines past the end of an output are shown as such
+fn first_difference(expected: &[u8], actual: &[u8]) -> Option<(usize, String, String)> {
+    if expected == actual {
+        return None;
+    }
+
+    let mut expected_lines = expected.split(|&byte| byte == b'\n');
+    let mut actual_lines = actual.split(|&byte| byte == b'\n');
+    let show = |line: Option<&[u8]>| {
+        line.map_or(String::from("<end of output>"), |line| {
+            String::from_utf8_lossy(line).into_owned()
+        })
+    };
+
+    for number in 1.. {
+        let (expected, actual) = (expected_lines.next(), actual_lines.next());
+
+        if expected != actual {
+            return Some((number, show(expected), show(actual)));
+        }
+    }
+
+    None
+}
+
+// Read the options from the command line arguments, there are none when the
+// help is requested
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        command: Subcommand::Run,
+        input: None,
+        expected: None,
+        output: None,
+        journal: None,
+        snapshot: None,
+        mode: MatchingMode::Reject,
+        backend: Backend::RedBlackTree,
+        versus: Vec::new(),
+        format: Format::Csv,
+        errors: ErrorPolicy::Abort,
+        instruments: None,
+        quiet: false,
+        count: 1000,
+        generator: Generator::new(),
+    };
+
+    // The subcommand can only come first
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some("run") => {
+            args.next();
+        }
+        Some("scenarios") => {
+            options.command = Subcommand::Scenarios;
+            args.next();
+        }
+        Some("verify") => {
+            options.command = Subcommand::Verify;
+            args.next();
+        }
+        Some("diff") => {
+            options.command = Subcommand::Diff;
+            args.next();
+        }
+        Some("generate") => {
+            options.command = Subcommand::Generate;
+            args.next();
+        }
+        _ => {}
+    }
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {arg}"));
+
+        match arg.as_str() {
+            "-i" | "--input" => options.input = Some(value()?),
+            "-x" | "--expected" => options.expected = Some(value()?),
+            "-o" | "--output" => options.output = Some(value()?),
+            "-j" | "--journal" => options.journal = Some(value()?),
+            "-s" | "--snapshot" => options.snapshot = Some(value()?),
+            "-m" | "--mode" => options.mode = parse_mode(&value()?)?,
+            "-b" | "--backend" => options.backend = parse_backend(&value()?)?,
+            "--versus" => {
+                for setting in value()?.split(',') {
+                    let (key, value) = setting
+                        .split_once('=')
+                        .ok_or(format!("Malformed setting {setting}"))?;
+
+                    options.versus.push(match key.trim() {
+                        "mode" => Setting::Mode(parse_mode(value.trim())?),
+                        "backend" => Setting::Backend(parse_backend(value.trim())?),
+                        "instruments" => Setting::Instruments(value.trim().to_string()),
+                        key => return Err(format!("Unknown setting {key}")),
+                    });
+                }
+            }
+            "-f" | "--format" => {
+                options.format = match value()?.as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    format => return Err(format!("Unknown format {format}")),
+                }
+            }
+            "-e" | "--errors" => {
+                options.errors = match value()?.as_str() {
+                    "abort" => ErrorPolicy::Abort,
+                    "skip" => ErrorPolicy::Skip,
+                    "reject" => ErrorPolicy::Reject,
+                    policy => return Err(format!("Unknown error policy {policy}")),
+                }
+            }
+            "--instruments" => options.instruments = Some(value()?),
+            "-q" | "--quiet" => options.quiet = true,
+            "-n" | "--count" => options.count = parse_number(&arg, &value()?)?,
+            "--seed" | "--symbols" | "--users" | "--rate" | "--volatility" | "--cancels"
+            | "--marketable" => {
+                let value = value()?;
+                let generator = mem::take(&mut options.generator);
+
+                options.generator = match arg.as_str() {
+                    "--seed" => generator.with_seed(parse_number(&arg, &value)?),
+                    "--symbols" => generator.with_symbols(parse_number(&arg, &value)?),
+                    "--users" => generator.with_users(parse_number(&arg, &value)?),
+                    "--volatility" => generator.with_volatility(parse_number(&arg, &value)?),
+                    "--rate" => match parse_number(&arg, &value)? {
+                        rate if rate > 0.0 && f64::is_finite(rate) => generator.with_rate(rate),
+                        _ => return Err(format!("Invalid value {value} for {arg}")),
+                    },
+                    "--cancels" => generator.with_cancel_ratio(parse_ratio(&arg, &value)?),
+                    _ => generator.with_marketable_ratio(parse_ratio(&arg, &value)?),
+                };
+            }
+            "-h" | "--help" => return Ok(None),
+            _ => return Err(format!("Unknown argument {arg}")),
+        }
+    }
+
+    if options.command == Subcommand::Scenarios && options.expected.is_none() {
+        return Err(String::from("Missing the expected output of the scenarios"));
+    }
+    if options.command == Subcommand::Verify && options.journal.is_none() {
+        return Err(String::from("Missing the journal to verify"));
+    }
+    if options.command == Subcommand::Diff && options.versus.is_empty() {
+        options.versus.push(Setting::Backend(match options.backend {
+            Backend::RedBlackTree => Backend::BTree,
+            Backend::BTree => Backend::RedBlackTree,
+        }));
+    }
+    if options.snapshot.is_some() && options.journal.is_none() {
+        return Err(String::from("Missing the journal of the snapshot"));
+    }
+
+    Ok(Some(options))
+}
+
+// Read a number given to an option
+fn parse_number<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {value} for {arg}"))
+}
+
+// Read a ratio given to an option, from zero to one
+fn parse_ratio(arg: &str, value: &str) -> Result<f64, String> {
+    match parse_number(arg, value)? {
+        ratio if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!("Invalid value {value} for {arg}")),
+    }
+}
+
+// Read a matching mode from its name
+fn parse_mode(mode: &str) -> Result<MatchingMode, String> {
+    match mode {
+        "trade" => Ok(MatchingMode::Trade),
+        "reject" => Ok(MatchingMode::Reject),
+        mode => Err(format!("Unknown mode {mode}")),
+    }
+}
+
+// Read a book side backend from its name
+fn parse_backend(backend: &str) -> Result<Backend, String> {
+    match backend {
+        "rb-tree" => Ok(Backend::RedBlackTree),
+        "b-tree" => Ok(Backend::BTree),
+        backend => Err(format!("Unknown backend {backend}")),
+    }
+}
+
+// Write an event to the output in the provided format
+fn write_event(output: &mut impl Write, event: &Event, format: Format) -> io::Result<()> {
+    match format {
+        Format::Csv => {
+            for line in format_outcome(&event.outcome) {
+                writeln!(output, "{line}")?;
+            }
+            Ok(())
+        }
+        Format::Json => writeln!(output, "{}", format_event_json(event)),
+    }
+}
+
+impl Stats {
+    // Account for an outcome, both orders and cancels are acknowledged while
+    // the cancels the book makes on its own aren't
+    fn count(&mut self, outcome: &OrderOutcome) {
+        match outcome {
+            OrderOutcome::Created { .. }
+            | OrderOutcome::TopOfBook { .. }
+            | OrderOutcome::Traded { .. } => self.acks += 1,
+            OrderOutcome::Rejected { .. } => self.rejects += 1,
+            _ => {}
+        }
+
+        let trades = outcome.trades();
+        self.trades += trades.len();
+        self.volume += trades
+            .iter()
+            .map(|trade| u64::from(trade.quantity))
+            .sum::<u64>();
+    }
+
+    // Write the statistics to the output in the provided format
+    fn write(&self, output: &mut impl Write, format: Format) -> io::Result<()> {
+        let Self { acks, rejects, trades, volume } = self;
+
+        match format {
+            Format::Csv => writeln!(output, "Q, {acks}, {rejects}, {trades}, {volume}"),
+            Format::Json => writeln!(
+                output,
+                r#"{{"event":"stats","acks":{acks},"rejects":{rejects},"trades":{trades},"volume":{volume}}}"#
+            ),
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::ops::Not;
 
 /// Two possible sides of an order book, an ask indicates a sell order and a bid
@@ -29,8 +30,50 @@ impl Not for Side {
     }
 }
 
+/// Behaviour of a post-only order that would take liquidity on arrival, it can
+/// either be rejected or repriced one tick behind the opposite best price.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostOnly {
+    Reject,
+    Slide,
+}
+
+/// Distance kept by a trailing stop from the last trade price, either as a
+/// fixed amount or as a percentage of the price.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trail {
+    Offset(u32),
+    Percent(u32),
+}
+
+/// Book price followed by a pegged order: the best price on its own side
+/// (primary), the best price on the opposite side (market) or the midpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PegReference {
+    Primary,
+    Market,
+    Midpoint,
+}
+
+/// Peg instructions of an order, the price is the reference one plus a signed
+/// offset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Peg {
+    pub reference: PegReference,
+    pub offset: i32,
+}
+
 /// The order is the smallest part of the program, it is constructed by the
 /// order book on each append operation.
+///
+/// The `quantity` is what the order currently shows on the book, iceberg
+/// orders keep the rest of their size in `hidden` and reveal it one `display`
+/// slice at a time. Post-only orders are never matched on arrival. Market
+/// orders ignore their price, and a stop price makes a conditional order that
+/// waits for a trade at or through it before entering the book, trailing stops
+/// move their stop price along with the market. Pegged orders take their price
+/// from the book and follow it. Finally, minimum quantity and all-or-none
+/// orders only trade when enough of their quantity can be executed at once.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Order {
     pub id: usize,
@@ -38,14 +81,254 @@ pub struct Order {
     pub side: Side,
     pub price: u32,
     pub quantity: u32,
+    pub hidden: u32,
+    pub display: Option<u32>,
+    pub post_only: Option<PostOnly>,
+    pub stop: Option<u32>,
+    pub trail: Option<Trail>,
+    pub peg: Option<Peg>,
+    pub min_quantity: Option<u32>,
+    pub all_or_none: bool,
+    pub market: bool,
 }
 
 impl Order {
-    // The constructor is the only explicitly implemented method for the `Order`
-    // type.
+    // The constructor builds a plain limit order, the other kinds of order are
+    // obtained through the `with_*` methods.
     #[must_use]
     pub const fn new(id: usize, user_id: usize, side: Side, price: u32, quantity: u32) -> Self {
-        Self { id, user_id, side, price, quantity }
+        Self {
+            id,
+            user_id,
+            side,
+            price,
+            quantity,
+            hidden: 0,
+            display: None,
+            post_only: None,
+            stop: None,
+            trail: None,
+            peg: None,
+            min_quantity: None,
+            all_or_none: false,
+            market: false,
+        }
+    }
+
+    /// Turn the order into an iceberg order that only shows `display` units of
+    /// its quantity at a time. A display size that is zero or not smaller than
+    /// the quantity leaves the order untouched.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let order = Order::new(1, 1, Side::Ask, 10, 100).with_display(30);
+    ///
+    /// assert_eq!(order.quantity, 30);
+    /// assert_eq!(order.hidden, 70);
+    /// assert_eq!(order.leaves(), 100);
+    /// ```
+    #[must_use]
+    pub const fn with_display(mut self, display: u32) -> Self {
+        let leaves = self.leaves();
+
+        if display > 0 && display < leaves {
+            self.quantity = display;
+            self.hidden = leaves - display;
+            self.display = Some(display);
+        }
+
+        self
+    }
+
+    /// Make sure the order never takes liquidity, the provided behaviour is
+    /// applied when the order would cross the book.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, PostOnly, Side};
+    ///
+    /// let order = Order::new(1, 1, Side::Ask, 10, 100).with_post_only(PostOnly::Slide);
+    ///
+    /// assert_eq!(order.post_only, Some(PostOnly::Slide));
+    /// ```
+    #[must_use]
+    pub const fn with_post_only(mut self, post_only: PostOnly) -> Self {
+        self.post_only = Some(post_only);
+        self
+    }
+
+    /// Make the order conditional on a trade printing at or through the stop
+    /// price: at or above it for buy orders, at or below it for sell orders.
+    /// Stop-market orders are market orders, stop-limit orders have a price.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let stop_market = Order::new(1, 1, Side::Bid, 0, 100).with_market().with_stop(12);
+    ///
+    /// assert!(stop_market.is_market());
+    /// assert_eq!(stop_market.stop, Some(12));
+    /// ```
+    #[must_use]
+    pub const fn with_stop(mut self, stop: u32) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Make the order a trailing stop, its stop price follows the trades in the
+    /// client's favour keeping the provided distance. Without an explicit stop
+    /// price the initial one is computed from the last trade price.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, Side, Trail};
+    ///
+    /// let order = Order::new(1, 1, Side::Ask, 0, 100).with_trail(Trail::Percent(10));
+    ///
+    /// assert_eq!(order.trail_stop(50), 45);
+    /// ```
+    #[must_use]
+    pub const fn with_trail(mut self, trail: Trail) -> Self {
+        self.trail = Some(trail);
+        self
+    }
+
+    /// The stop price a trailing stop would have with the provided last trade
+    /// price: below it for sell orders and above it for buy orders. Orders
+    /// without a trail are kept at the trade price, and stop prices beyond the
+    /// range of a price saturate at its bounds.
+    #[must_use]
+    pub fn trail_stop(&self, price: u32) -> u32 {
+        let distance = match self.trail {
+            Some(Trail::Offset(offset)) => offset,
+            Some(Trail::Percent(percent)) => {
+                let distance = u64::from(price) * u64::from(percent) / 100;
+                u32::try_from(distance).unwrap_or(u32::MAX)
+            }
+            None => 0,
+        };
+
+        if self.side == Side::Ask {
+            price.saturating_sub(distance)
+        } else {
+            price.saturating_add(distance)
+        }
+    }
+
+    /// Peg the order price to a reference price of the book, plus an offset.
+    /// The provided price is replaced by the book when the order is submitted.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, PegReference, Side};
+    ///
+    /// let order = Order::new(1, 1, Side::Bid, 0, 100).with_peg(PegReference::Primary, -1);
+    ///
+    /// assert_eq!(order.peg.unwrap().offset, -1);
+    /// ```
+    #[must_use]
+    pub const fn with_peg(mut self, reference: PegReference, offset: i32) -> Self {
+        self.peg = Some(Peg { reference, offset });
+        self
+    }
+
+    /// Only trade the order when at least `min_quantity` of it can be executed,
+    /// or all of it when less than that is left.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let order = Order::new(1, 1, Side::Bid, 10, 100).with_min_quantity(30);
+    ///
+    /// assert_eq!(order.min_fill(), 30);
+    /// ```
+    #[must_use]
+    pub const fn with_min_quantity(mut self, min_quantity: u32) -> Self {
+        self.min_quantity = Some(min_quantity);
+        self
+    }
+
+    /// Only trade the order when all of it can be executed at once.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let order = Order::new(1, 1, Side::Bid, 10, 100).with_all_or_none();
+    ///
+    /// assert_eq!(order.min_fill(), 100);
+    /// ```
+    #[must_use]
+    pub const fn with_all_or_none(mut self) -> Self {
+        self.all_or_none = true;
+        self
+    }
+
+    /// The smallest quantity the order accepts to trade at once, zero when
+    /// there's no constraint.
+    #[must_use]
+    pub fn min_fill(&self) -> u32 {
+        if self.all_or_none {
+            self.leaves()
+        } else {
+            self.min_quantity.map_or(0, |min| min.min(self.leaves()))
+        }
+    }
+
+    /// Turn the order into a market order, which has no limit price: it
+    /// trades at any price and never rests on the book, its own price is
+    /// ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let order = Order::new(1, 1, Side::Bid, 0, 100);
+    /// assert!(!order.is_market());
+    ///
+    /// assert!(order.with_market().is_market());
+    /// ```
+    #[must_use]
+    pub const fn with_market(mut self) -> Self {
+        self.market = true;
+        self
+    }
+
+    /// Whether the order is a market order, see `with_market`.
+    #[must_use]
+    pub const fn is_market(&self) -> bool {
+        self.market
+    }
+
+    /// The total quantity still open on the order, both shown and hidden.
+    #[must_use]
+    pub const fn leaves(&self) -> u32 {
+        self.quantity + self.hidden
+    }
+
+    /// Execute part of the order, the shown quantity is consumed first and the
+    /// hidden one afterwards.
+    pub fn fill(&mut self, quantity: u32) {
+        let shown = quantity.min(self.quantity);
+        self.quantity -= shown;
+        self.hidden -= quantity - shown;
+    }
+
+    /// Show a new slice of an iceberg order once the previous one has been
+    /// consumed, returns whether a new slice has been revealed.
+    pub fn replenish(&mut self) -> bool {
+        match self.display {
+            Some(display) if self.quantity == 0 && self.hidden > 0 => {
+                self.quantity = display.min(self.hidden);
+                self.hidden -= self.quantity;
+                true
+            }
+            _ => false,
+        }
     }
 }
 
@@ -61,11 +344,69 @@ mod tests {
         let quantity = 1;
         let price = 10;
 
-        let order = Order { id, user_id, side, price, quantity };
+        let order = Order::new(id, user_id, side, price, quantity);
 
         assert_eq!(order.id, id);
         assert_eq!(order.side, side);
         assert_eq!(order.price, price);
         assert_eq!(order.quantity, quantity);
+        assert_eq!(order.hidden, 0);
+        assert_eq!(order.display, None);
+        assert_eq!(order.post_only, None);
+        assert_eq!(order.stop, None);
+        assert_eq!(order.trail, None);
+        assert_eq!(order.peg, None);
+        assert_eq!(order.min_fill(), 0);
+        assert!(!order.is_market());
+    }
+
+    #[test]
+    fn test_min_fill() {
+        let mut order = Order::new(1, 1, Side::Bid, 10, 100).with_min_quantity(30);
+
+        order.fill(80);
+        assert_eq!(order.min_fill(), 20);
+
+        let mut order = Order::new(2, 1, Side::Bid, 10, 100).with_all_or_none();
+
+        order.fill(60);
+        assert_eq!(order.min_fill(), 40);
+    }
+
+    #[test]
+    fn test_trail_stop() {
+        let sell = Order::new(1, 1, Side::Ask, 0, 1).with_trail(Trail::Offset(3));
+        let buy = Order::new(2, 1, Side::Bid, 0, 1).with_trail(Trail::Percent(50));
+
+        assert_eq!(sell.trail_stop(10), 7);
+        assert_eq!(sell.trail_stop(2), 0);
+        assert_eq!(buy.trail_stop(10), 15);
+
+        // Distances wider than a price saturate instead of wrapping around
+        let buy = Order::new(3, 1, Side::Bid, 0, 1).with_trail(Trail::Percent(u32::MAX));
+        let sell = Order::new(4, 1, Side::Ask, 0, 1).with_trail(Trail::Percent(300));
+
+        assert_eq!(buy.trail_stop(u32::MAX), u32::MAX);
+        assert_eq!(buy.trail_stop(200), u32::MAX);
+        assert_eq!(sell.trail_stop(u32::MAX), 0);
+    }
+
+    #[test]
+    fn test_fill_and_replenish() {
+        let mut order = Order::new(1, 1, Side::Ask, 10, 100).with_display(40);
+
+        order.fill(40);
+        assert_eq!(order.quantity, 0);
+        assert!(order.replenish());
+        assert_eq!((order.quantity, order.hidden), (40, 20));
+
+        order.fill(50);
+        assert_eq!((order.quantity, order.hidden), (0, 10));
+        assert!(order.replenish());
+        assert_eq!((order.quantity, order.hidden), (10, 0));
+
+        order.fill(10);
+        assert!(!order.replenish());
+        assert_eq!(order.leaves(), 0);
     }
 }
@@ -1,32 +1,168 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::mem;
 
 use crate::{
-    book_side::BookSide,
-    order::{Order, Side},
+    allocation::Allocation,
+    auction::{self, Clearing},
+    band::Band,
+    book_side::{Backend, BookSide},
+    instrument::InstrumentRules,
+    order::{Order, PegReference, PostOnly, Side},
+    price_level::{Fill, PriceLevel},
+    session::Session,
+    snapshot::BookSnapshot,
+    trigger_book::TriggerBook,
 };
 
 /// The main interface for the program, the order book holds the two book sides
-/// and a map to keep track of each order ID.
+/// with their pending conditional orders, a map to keep track of each order ID
+/// and the state of the trading day.
 pub struct OrderBook {
     orders: HashMap<usize, Order>,
+    mode: MatchingMode,
+    allocation: Allocation,
+    rules: InstrumentRules,
+    // The session, its scheduled transitions and the clock, with the last
+    // published indicative clearing during auctions
+    session: Session,
+    schedule: VecDeque<(u64, Session)>,
+    now: u64,
+    indicative: Option<Clearing>,
+    // Breaching the dynamic band interrupts continuous trading for a while
+    static_band: Option<Band>,
+    reference_price: Option<u32>,
+    dynamic_band: Option<Band>,
+    reopen_after: u64,
+    interrupted: bool,
+    // The remainder cancelled while matching, reported after the trades
+    dropped: Option<OrderOutcome>,
+    // Resting pegged orders in arrival order, and the references they were
+    // last priced with
+    pegged: Vec<usize>,
+    peg_references: (Option<u32>, Option<u32>),
     asks: BookSide,
     bids: BookSide,
+    buy_stops: TriggerBook,
+    sell_stops: TriggerBook,
+    last_price: Option<u32>,
+}
+
+/// How incoming orders that cross the book are handled during continuous
+/// trading: they're either rejected as if they were all post-only orders,
+/// which is how the book has always worked by default, or they trade against
+/// the opposite side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchingMode {
+    Reject,
+    Trade,
+}
+
+impl Default for MatchingMode {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// Why an order, or a cancel, has been rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RejectReason {
+    /// The order would cross the book in reject mode.
+    Crossing,
+    /// A market order found nothing to trade against.
+    NoLiquidity,
+    /// A pegged order or a trailing stop found no reference price.
+    NoReference,
+    /// The order combines instructions that can't work together.
+    Unsupported,
+    /// The operation isn't allowed in the current session.
+    Session,
+    /// The limit price is outside the static or the dynamic band.
+    PriceBand,
+    /// The price is off the tick grid of the instrument.
+    TickSize,
+    /// The quantity isn't a multiple of the lot size.
+    LotSize,
+    /// The quantity is below the instrument minimum.
+    QuantityTooLow,
+    /// The quantity is above the instrument maximum.
+    QuantityTooHigh,
+    /// The notional value is above the instrument maximum.
+    NotionalTooHigh,
+    /// The symbol isn't in the instrument registry.
+    UnknownSymbol,
+    /// The cancelled order isn't in the book.
+    UnknownOrder,
+    /// The record of the input file can't be parsed.
+    Malformed,
+}
+
+impl RejectReason {
+    /// The name of the reason in the output files.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Crossing => "crossing",
+            Self::NoLiquidity => "no-liquidity",
+            Self::NoReference => "no-reference",
+            Self::Unsupported => "unsupported",
+            Self::Session => "session",
+            Self::PriceBand => "price-band",
+            Self::TickSize => "tick-size",
+            Self::LotSize => "lot-size",
+            Self::QuantityTooLow => "quantity-too-low",
+            Self::QuantityTooHigh => "quantity-too-high",
+            Self::NotionalTooHigh => "notional-too-high",
+            Self::UnknownSymbol => "unknown-symbol",
+            Self::UnknownOrder => "unknown-order",
+            Self::Malformed => "malformed",
+        }
+    }
+}
+
+/// A single execution between an incoming order and a resting one, the price
+/// is always the one of the resting order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Trade {
+    pub user_id_buy: usize,
+    pub order_id_buy: usize,
+    pub user_id_sell: usize,
+    pub order_id_sell: usize,
+    pub price: u32,
+    pub quantity: u32,
+}
+
+/// A change in the top of the book for one side, the price and the volume are
+/// unavailable when the side has been emptied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Top {
+    pub side: Side,
+    pub price: Option<u32>,
+    pub volume: Option<u32>,
 }
 
 // Possible outcomes for an order execution, these outcomes holds every
 // information needed for producing the final output.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum OrderOutcome {
-    // Rejected orders require both IDs of the input order
+    // Rejected orders require both IDs of the input order, along with the reason
     Rejected {
         user_id: usize,
         order_id: usize,
+        reason: RejectReason,
     },
     // Appended orders require both IDs of the input order
     Created {
         user_id: usize,
         order_id: usize,
     },
+    // Orders the book cancels on its own, remainders that can't rest or
+    // orders left crossing after an auction, carry the quantity they had left
+    Cancelled {
+        user_id: usize,
+        order_id: usize,
+        quantity: u32,
+    },
     // When the top of the book changes the top price and the volume could be unavailable due to
     // the missing price level
     TopOfBook {
@@ -36,45 +172,458 @@ pub enum OrderOutcome {
         top_price: Option<u32>,
         volume: Option<u32>,
     },
-    // Traded orders need to collect IDs for the buy and sell side, keeping track of which are the
-    // input ID saves a few lines of code
+    // Traded orders carry every execution in the order they happened, followed by the top of the
+    // book changes for either side
     Traded {
         user_id: usize,
         order_id: usize,
-        user_id_buy: usize,
-        order_id_buy: usize,
-        user_id_sell: usize,
-        order_id_sell: usize,
+        trades: Vec<Trade>,
+        tops: Vec<Top>,
+    },
+    // Triggered conditional orders are reported right before the outcome of their activation
+    Triggered {
+        user_id: usize,
+        order_id: usize,
+    },
+    // Orders amended by the book itself carry their new limit and stop prices
+    Amended {
+        user_id: usize,
+        order_id: usize,
         price: u32,
-        quantity: u32,
-        side: Option<Side>,
-        top_price: Option<u32>,
-        volume: Option<u32>,
+        stop: Option<u32>,
+    },
+    // Session transitions carry both the previous and the new session
+    Transitioned {
+        from: Session,
+        to: Session,
+    },
+    // During the call phase of an auction the indicative clearing is published whenever it
+    // changes, it is unavailable while the book isn't crossed
+    Indicative {
+        clearing: Option<Clearing>,
+    },
+    // The uncross of an auction carries every execution at the clearing price, followed by the
+    // top of the book changes for either side
+    Uncrossed {
+        price: u32,
+        trades: Vec<Trade>,
+        tops: Vec<Top>,
     },
 }
 
+impl OrderOutcome {
+    /// The executions carried by the outcome, if any.
+    #[must_use]
+    pub fn trades(&self) -> &[Trade] {
+        match self {
+            Self::Traded { trades, .. } | Self::Uncrossed { trades, .. } => trades,
+            _ => &[],
+        }
+    }
+}
+
 impl OrderBook {
     #[must_use]
     pub fn new() -> Self {
         Self {
             orders: HashMap::new(),
+            mode: MatchingMode::Reject,
+            allocation: Allocation::Fifo,
+            rules: InstrumentRules::new(),
+            session: Session::Continuous,
+            schedule: VecDeque::new(),
+            now: 0,
+            indicative: None,
+            static_band: None,
+            reference_price: None,
+            dynamic_band: None,
+            reopen_after: 0,
+            interrupted: false,
+            dropped: None,
+            pegged: Vec::new(),
+            peg_references: (None, None),
             asks: BookSide::new(),
             bids: BookSide::new(),
+            buy_stops: TriggerBook::new(),
+            sell_stops: TriggerBook::new(),
+            last_price: None,
         }
     }
 
+    /// Use the provided matching mode, instead of rejecting crossing orders.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::{MatchingMode, OrderBook, OrderOutcome, RejectReason};
+    /// use orderbook::order::Side;
+    ///
+    /// let mut order_book = OrderBook::new();
+    /// order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+    ///
+    /// let reason = RejectReason::Crossing;
+    /// assert_eq!(
+    ///     order_book.submit_order(Side::Bid, 10, 100, 2, 2),
+    ///     vec![OrderOutcome::Rejected { user_id: 2, order_id: 2, reason }]
+    /// );
+    ///
+    /// let mut order_book = order_book.with_mode(MatchingMode::Trade);
+    /// let outcome = order_book.submit_order(Side::Bid, 10, 100, 2, 3);
+    ///
+    /// assert!(matches!(outcome[0], OrderOutcome::Traded { .. }));
+    /// assert_eq!(outcome[0].trades().len(), 1);
+    /// ```
+    #[must_use]
+    pub fn with_mode(mut self, mode: MatchingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Get the matching mode of the book.
+    #[must_use]
+    pub const fn mode(&self) -> MatchingMode {
+        self.mode
+    }
+
+    /// Store the price levels of both sides in the provided backend, instead
+    /// of red-black trees.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::book_side::Backend;
+    /// use orderbook::order_book::OrderBook;
+    ///
+    /// let order_book = OrderBook::new().with_backend(Backend::BTree);
+    ///
+    /// assert_eq!(order_book.backend(), Backend::BTree);
+    /// ```
+    #[must_use]
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.asks = self.asks.with_backend(backend);
+        self.bids = self.bids.with_backend(backend);
+        self
+    }
+
+    /// Get the backend the price levels are stored in.
+    #[must_use]
+    pub const fn backend(&self) -> Backend {
+        self.asks.backend()
+    }
+
+    /// Use the provided allocation policy to spread the fills within each price
+    /// level, instead of the default time priority.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::allocation::Allocation;
+    /// use orderbook::order_book::OrderBook;
+    ///
+    /// let pro_rata = Allocation::ProRata { top_order: false, min_allocation: 1 };
+    /// let order_book = OrderBook::new().with_allocation(pro_rata);
+    ///
+    /// assert_eq!(order_book.allocation(), pro_rata);
+    /// ```
+    #[must_use]
+    pub fn with_allocation(mut self, allocation: Allocation) -> Self {
+        self.allocation = allocation;
+        self
+    }
+
+    /// Get the allocation policy used within each price level.
+    #[must_use]
+    pub const fn allocation(&self) -> Allocation {
+        self.allocation
+    }
+
+    /// Check the incoming orders against the provided instrument rules, instead
+    /// of accepting any price and any quantity but zero. Slid and pegged
+    /// orders follow the tick grid as well.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::instrument::InstrumentRules;
+    /// use orderbook::order_book::{OrderBook, OrderOutcome, RejectReason};
+    /// use orderbook::order::Side;
+    ///
+    /// let mut order_book = OrderBook::new().with_rules(InstrumentRules::new().with_lot(100));
+    /// let outcomes = order_book.submit_order(Side::Bid, 10, 150, 1, 1);
+    ///
+    /// let reason = RejectReason::LotSize;
+    /// assert_eq!(outcomes[0], OrderOutcome::Rejected { user_id: 1, order_id: 1, reason });
+    /// ```
+    #[must_use]
+    pub fn with_rules(mut self, rules: InstrumentRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Get the rules incoming orders are checked against.
+    #[must_use]
+    pub const fn rules(&self) -> &InstrumentRules {
+        &self.rules
+    }
+
+    /// Start the book in the provided session, instead of continuous trading.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::OrderBook;
+    /// use orderbook::session::Session;
+    ///
+    /// let order_book = OrderBook::new().with_session(Session::Closed);
+    ///
+    /// assert_eq!(order_book.session(), Session::Closed);
+    /// ```
+    #[must_use]
+    pub fn with_session(mut self, session: Session) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Get the current trading session of the book.
+    #[must_use]
+    pub const fn session(&self) -> Session {
+        self.session
+    }
+
+    /// Reject the limit prices outside the provided band around the reference
+    /// price, whatever the session. Without a reference price, see
+    /// `with_reference_price`, the band applies from the first close.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::band::Band;
+    /// use orderbook::order_book::{OrderBook, OrderOutcome, RejectReason};
+    /// use orderbook::order::Side;
+    ///
+    /// let mut order_book = OrderBook::new()
+    ///     .with_static_band(Band::new(10))
+    ///     .with_reference_price(100);
+    ///
+    /// let outcomes = order_book.submit_order(Side::Ask, 111, 100, 1, 1);
+    /// let reason = RejectReason::PriceBand;
+    /// assert_eq!(outcomes[0], OrderOutcome::Rejected { user_id: 1, order_id: 1, reason });
+    /// ```
+    #[must_use]
+    pub fn with_static_band(mut self, band: Band) -> Self {
+        self.static_band = Some(band);
+        self
+    }
+
+    /// Use the provided reference price for the static band, until the book
+    /// closes and the last trade price becomes the new reference.
+    #[must_use]
+    pub fn with_reference_price(mut self, price: u32) -> Self {
+        self.reference_price = Some(price);
+        self
+    }
+
+    /// Get the reference price of the static band, the previous close once the
+    /// book has been closed.
+    #[must_use]
+    pub const fn reference_price(&self) -> Option<u32> {
+        self.reference_price
+    }
+
+    /// Keep continuous trading within the provided band around the last trade
+    /// price. Limit prices outside of it are rejected, while a trade that would
+    /// print outside of it interrupts continuous trading with a volatility
+    /// auction, which reopens the book once the clock is advanced by
+    /// `reopen_after`.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::band::Band;
+    /// use orderbook::order_book::{MatchingMode, OrderBook};
+    /// use orderbook::order::{Order, Side};
+    /// use orderbook::session::Session;
+    ///
+    /// let mut order_book = OrderBook::new()
+    ///     .with_mode(MatchingMode::Trade)
+    ///     .with_dynamic_band(Band::new(10), 5);
+    /// order_book.submit_order(Side::Ask, 100, 100, 1, 1);
+    /// order_book.submit_order(Side::Ask, 120, 100, 1, 2);
+    /// order_book.submit_order(Side::Bid, 100, 50, 2, 101);
+    ///
+    /// // The second ask is outside the band around the last trade price
+    /// order_book.submit(Order::new(102, 2, Side::Bid, 0, 100).with_market());
+    /// assert_eq!(order_book.session(), Session::VolatilityAuction);
+    ///
+    /// order_book.advance(5);
+    /// assert_eq!(order_book.session(), Session::Continuous);
+    /// ```
+    #[must_use]
+    pub fn with_dynamic_band(mut self, band: Band, reopen_after: u64) -> Self {
+        self.dynamic_band = Some(band);
+        self.reopen_after = reopen_after;
+        self
+    }
+
     /// Get the best price for the ask side. This operation can be performed in
     /// *O*(1).
     #[must_use]
     pub fn best_ask_price(&self) -> Option<u32> {
-        self.asks.min().map_or(None, |bap| Some(bap.price))
+        self.asks.min().map(|bap| bap.price)
     }
 
     /// Get the best price for the bid side. This operation can be performed in
     /// *O*(log *n*) where *n* is the size of the tree.
     #[must_use]
     pub fn best_bid_price(&self) -> Option<u32> {
-        self.bids.max().map_or(None, |bbp| Some(bbp.price))
+        self.bids.max().map(|bbp| bbp.price)
+    }
+
+    /// Get the price of the last trade, if any. This operation can be performed
+    /// in *O*(1).
+    #[must_use]
+    pub const fn last_price(&self) -> Option<u32> {
+        self.last_price
+    }
+
+    /// Take a snapshot of the state of the book, its configuration aside: the
+    /// mode, allocation, rules and bands. The complexity for this operation is
+    /// *O*(*n* + *k* log *k*), where *n* is the size of the trees and *k* is
+    /// the number of orders.
+    #[must_use]
+    pub fn snapshot(&self) -> BookSnapshot {
+        let mut orders: Vec<Order> = self.orders.values().copied().collect();
+        orders.sort_by_key(|order| order.id);
+
+        BookSnapshot {
+            session: self.session,
+            schedule: self.schedule.iter().copied().collect(),
+            now: self.now,
+            indicative: self.indicative,
+            reference_price: self.reference_price,
+            last_price: self.last_price,
+            peg_references: self.peg_references,
+            pegged: self.pegged.clone(),
+            orders,
+            asks: self.asks.orders(),
+            bids: self.bids.orders(),
+            buy_stops: self.buy_stops.entries(),
+            sell_stops: self.sell_stops.entries(),
+        }
+    }
+
+    /// Replace the state of the book with the one of a snapshot, keeping the
+    /// configuration. Orders are appended in the sequence of the snapshot, so
+    /// that each of them gets its time priority back. The complexity for this
+    /// operation is *O*(*k* log *n*), where *n* is the size of the trees and
+    /// *k* is the number of orders.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::{MatchingMode, OrderBook};
+    /// use orderbook::order::Side;
+    ///
+    /// let mut order_book = OrderBook::new();
+    /// order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+    /// order_book.submit_order(Side::Ask, 10, 100, 2, 2);
+    ///
+    /// let mut restored = OrderBook::new().with_mode(MatchingMode::Trade);
+    /// restored.restore(&order_book.snapshot());
+    /// restored.submit_order(Side::Bid, 10, 100, 3, 3);
+    ///
+    /// assert_eq!(restored.order_status(1), None);
+    /// assert_eq!(restored.snapshot().asks, vec![order_book.order_status(2).unwrap()]);
+    /// ```
+    pub fn restore(&mut self, snapshot: &BookSnapshot) {
+        self.orders = snapshot
+            .orders
+            .iter()
+            .map(|&order| (order.id, order))
+            .collect();
+        self.session = snapshot.session;
+        self.schedule = snapshot.schedule.iter().copied().collect();
+        self.now = snapshot.now;
+        self.indicative = snapshot.indicative;
+        self.reference_price = snapshot.reference_price;
+        self.last_price = snapshot.last_price;
+        self.interrupted = false;
+        self.dropped = None;
+        self.pegged = snapshot.pegged.clone();
+        self.peg_references = snapshot.peg_references;
+
+        let backend = self.backend();
+        self.asks = BookSide::new().with_backend(backend);
+        self.bids = BookSide::new().with_backend(backend);
+        for &order in snapshot.asks.iter().chain(&snapshot.bids) {
+            self.get_side_mut(order.side).append(order);
+        }
+
+        self.buy_stops = TriggerBook::new();
+        self.sell_stops = TriggerBook::new();
+        for &(stop, order) in &snapshot.buy_stops {
+            self.buy_stops.append(stop, order);
+        }
+        for &(stop, order) in &snapshot.sell_stops {
+            self.sell_stops.append(stop, order);
+        }
+    }
+
+    /// Get the current state of an order, whether it is resting on the book or
+    /// waiting for its trigger. The complexity for this operation is
+    /// *O*(log *n* + *m*), where *n* is the size of the tree and *m* is the
+    /// length of the price level.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::{MatchingMode, OrderBook};
+    /// use orderbook::order::Side;
+    ///
+    /// let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+    /// order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+    /// order_book.submit_order(Side::Bid, 10, 40, 2, 2);
+    ///
+    /// assert_eq!(order_book.order_status(1).unwrap().quantity, 60);
+    /// assert_eq!(order_book.order_status(2), None);
+    /// ```
+    #[must_use]
+    pub fn order_status(&self, order_id: usize) -> Option<Order> {
+        let order = self.orders.get(&order_id)?;
+
+        if order.stop.is_some() {
+            // Pending orders are kept up to date in the index
+            return Some(*order);
+        }
+
+        self.get_side(order.side)
+            .get(order.price, order_id)
+            .copied()
+    }
+
+    /// Get the place of a resting order in the queue of its price level: the
+    /// number of orders ahead of it, and their shown volume. Orders waiting
+    /// for their trigger aren't queued yet. The complexity for this operation
+    /// is *O*(log *n* + *m*), where *n* is the size of the tree and *m* is the
+    /// length of the price level.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::OrderBook;
+    /// use orderbook::order::Side;
+    ///
+    /// let mut order_book = OrderBook::new();
+    /// order_book.submit_order(Side::Bid, 10, 100, 1, 1);
+    /// order_book.submit_order(Side::Bid, 10, 40, 2, 2);
+    /// order_book.submit_order(Side::Bid, 10, 50, 3, 3);
+    ///
+    /// assert_eq!(order_book.queue_position(1), Some((0, 0)));
+    /// assert_eq!(order_book.queue_position(3), Some((2, 140)));
+    /// assert_eq!(order_book.queue_position(4), None);
+    /// ```
+    #[must_use]
+    pub fn queue_position(&self, order_id: usize) -> Option<(usize, u32)> {
+        let order = self
+            .orders
+            .get(&order_id)
+            .filter(|order| order.stop.is_none())?;
+        let level = self.get_side(order.side).get_level(order.price)?;
+        let ahead = level.iter().take_while(|order| order.id != order_id);
+
+        Some(ahead.fold((0, 0), |(orders, volume), order| {
+            (orders + 1, volume + order.quantity)
+        }))
     }
 
     /// Get the best price for the specified side. This operation can be
@@ -107,6 +656,16 @@ impl OrderBook {
         }
     }
 
+    /// Provide a mutable reference for the pending conditional orders of the
+    /// specified side.
+    fn get_stops_mut(&mut self, side: Side) -> &mut TriggerBook {
+        if side == Side::Ask {
+            &mut self.sell_stops
+        } else {
+            &mut self.buy_stops
+        }
+    }
+
     /// Append an order to the corresponding book side, and returns its current
     /// price and volume. The complexity for this operation is *O*(log *n*),
     /// where *n* is the size of the book side tree.
@@ -116,21 +675,140 @@ impl OrderBook {
         // Append into book side is O(log n)
         self.get_side_mut(order.side).append(order);
 
-        // Searching the top is O(log n) with the same n (+1)
-        let top = self.get_best_for_side(order.side);
+        // Searching the top and its volume is O(log n) with the same n (+1)
+        self.get_top_for_side(order.side)
+    }
+
+    /// Get the best price for the specified side along with its volume. This
+    /// operation can be performed in *O*(log *n*) where *n* is the size of the
+    /// tree.
+    fn get_top_for_side(&self, side: Side) -> (Option<u32>, Option<u32>) {
+        let top = self.get_best_for_side(side);
         // Searching a red-black tree is the same O(log n)
-        let volume = top.map_or(None, |t| self.get_side(order.side).get_price_volume(t));
+        let volume = top.and_then(|t| self.get_side(side).get_price_volume(t));
 
         (top, volume)
     }
 
-    /// Append an order to the corresponding book side, and returns the outcome.
-    /// The complexity for this operation is *O*(log *n* + *m*), where *n* is
-    /// the size of the tree and *m* is the length of the price level.
+    /// Check that the book is consistent: each side is, see
+    /// `BookSide::check_invariants`, the book isn't crossed outside auctions
+    /// and halts unless some order has a minimum quantity, and the index holds
+    /// exactly the orders resting on the book or waiting for their trigger,
+    /// each of them once. Pending orders are filed under their stop price, and
+    /// pegged orders rest on the book. This is meant for tests and fuzzing,
+    /// the complexity for this operation is *O*(*n* log *n* + *k*), where *n*
+    /// is the size of the tree and *k* is the number of orders.
+    ///
+    /// # Errors
+    /// Return a description of the first inconsistency found.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::OrderBook;
+    /// use orderbook::order::Side;
+    ///
+    /// let mut order_book = OrderBook::new();
+    /// order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+    /// order_book.submit_order(Side::Bid, 11, 40, 2, 2);
+    ///
+    /// assert_eq!(order_book.check_invariants(), Ok(()));
+    /// ```
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.asks.check_invariants(Side::Ask)?;
+        self.bids.check_invariants(Side::Bid)?;
+
+        let resting: Vec<Order> = self
+            .asks
+            .orders()
+            .into_iter()
+            .chain(self.bids.orders())
+            .collect();
+
+        // Outside auctions, and halts that froze them, crossing orders either
+        // trade or are rejected. When trading, minimum quantities that can't be
+        // met are skipped instead, even by the uncross, so that only crosses
+        // with an order without a minimum on both sides are wrong
+        let call = self.session.is_auction() || self.session == Session::Halted;
+        let best = |side: Side| {
+            let prices = resting
+                .iter()
+                .filter(|order| order.side == side)
+                .filter(|order| self.mode == MatchingMode::Reject || order.min_fill() == 0)
+                .map(|order| order.price);
+
+            match side {
+                Side::Bid => prices.max(),
+                Side::Ask => prices.min(),
+            }
+        };
+
+        if let (Some(bid), Some(ask)) = (best(Side::Bid), best(Side::Ask)) {
+            if bid >= ask && !call {
+                return Err(format!("The book is crossed, bid {bid} and ask {ask}"));
+            }
+        }
+
+        let pending = [(Side::Bid, &self.buy_stops), (Side::Ask, &self.sell_stops)];
+        let mut seen = HashSet::new();
+
+        for order in &resting {
+            let indexed = self.orders.get(&order.id);
+
+            if !seen.insert(order.id) {
+                return Err(format!("Order {} is on the book twice", order.id));
+            }
+            if !indexed.map_or(false, |indexed| {
+                (indexed.side, indexed.stop) == (order.side, None)
+            }) {
+                return Err(format!("Resting order {} isn't in the index", order.id));
+            }
+        }
+
+        for (side, stops) in pending.iter() {
+            for (stop, order) in stops.entries() {
+                if !seen.insert(order.id) {
+                    return Err(format!("Order {} is pending more than once", order.id));
+                }
+                if order.side != *side || order.stop != Some(stop) {
+                    return Err(format!("Order {} is pending under stop {stop}", order.id));
+                }
+                if self.orders.get(&order.id) != Some(&order) {
+                    return Err(format!("Pending order {} isn't in the index", order.id));
+                }
+            }
+        }
+
+        if seen.len() != self.orders.len() {
+            let missing = self.orders.len() - seen.len();
+            return Err(format!(
+                "{missing} indexed orders are neither resting nor pending"
+            ));
+        }
+
+        if let Some(id) = self
+            .pegged
+            .iter()
+            .find(|&&id| !resting.iter().any(|o| o.id == id))
+        {
+            return Err(format!("Pegged order {id} isn't resting on the book"));
+        }
+
+        Ok(())
+    }
+
+    /// Cancel an order and remove it from the corresponding book side, then
+    /// returns the outcomes. The complexity for this operation is
+    /// *O*(log *n* + *m*), where *n* is the size of the tree and *m* is the
+    /// length of the price level.
     ///
-    /// # Panics
-    /// This method assumes that the order ID is already in the order book and
-    /// it will always panic if the condition is not met.
+    /// Pending conditional orders are cancelled as well, and since they don't
+    /// affect the top of the book they're acknowledged by a `Created` outcome.
+    /// When the top of the book moves the pegged orders are repriced, and an
+    /// `Amended` outcome follows for each of them. During the call phase of an
+    /// auction an `Indicative` outcome follows when the indicative clearing
+    /// changes. Cancels are rejected while the book is closed, and so are
+    /// cancels of orders that aren't in the book. Rejections go to the user
+    /// requesting the cancel.
     ///
     /// # Example
     /// ```
@@ -140,32 +818,53 @@ impl OrderBook {
     /// let mut order_book = OrderBook::new();
     ///
     /// order_book.submit_order(Side::Ask, 10, 100, 1, 1);
-    /// order_book.cancel_order(1);
+    /// order_book.cancel_order(1, 1);
     ///
     /// assert_eq!(order_book.best_ask_price(), None);
     /// ```
-    pub fn cancel_order(&mut self, order_id: usize) -> OrderOutcome {
-        let order = *self.orders.get(&order_id).unwrap();
+    pub fn cancel_order(&mut self, user_id: usize, order_id: usize) -> Vec<OrderOutcome> {
+        let order = match self.orders.get(&order_id) {
+            Some(&order) => order,
+            None => {
+                let reason = RejectReason::UnknownOrder;
+                return vec![OrderOutcome::Rejected { user_id, order_id, reason }];
+            }
+        };
         let side = order.side;
 
-        let top = self.get_best_for_side(side).unwrap();
-        self.remove(order);
+        if !self.session.allows_cancels() {
+            let reason = RejectReason::Session;
+            return vec![OrderOutcome::Rejected { user_id, order_id, reason }];
+        }
 
-        if top != order.price {
-            return OrderOutcome::Created { user_id: order.user_id, order_id };
+        if let Some(stop) = order.stop {
+            // Pending conditional orders aren't on the book yet
+            self.orders.remove(&order_id);
+            self.get_stops_mut(side).remove(stop, order_id);
+            return vec![OrderOutcome::Created { user_id: order.user_id, order_id }];
         }
 
-        let top_price = self.get_best_for_side(side);
+        let top = self.get_top_for_side(side);
+        self.remove(order);
 
-        let volume = top_price.map_or(None, |top| self.get_side(order.side).get_price_volume(top));
+        let (top_price, volume) = self.get_top_for_side(side);
 
-        OrderOutcome::TopOfBook {
-            user_id: order.user_id,
-            order_id,
-            side,
-            top_price,
-            volume,
-        }
+        let outcome = if (top_price, volume) == top {
+            OrderOutcome::Created { user_id: order.user_id, order_id }
+        } else {
+            OrderOutcome::TopOfBook {
+                user_id: order.user_id,
+                order_id,
+                side,
+                top_price,
+                volume,
+            }
+        };
+
+        let mut outcomes = vec![outcome];
+        outcomes.extend(self.reprice());
+        outcomes.extend(self.publish());
+        outcomes
     }
 
     /// Remove an order from the corresponding side and return it. The
@@ -189,76 +888,155 @@ impl OrderBook {
         }
     }
 
-    /// Perform a trade on a side for the specified price and quantity. The
-    /// complexity for this operation is *O*(log *n* + *m*) where *n* is the
-    /// size of the order book tree and *m* is the length of the price level.
-    fn trade(&mut self, side: Side, price: u32, quantity: u32) -> Option<Order> {
-        self.get_side_mut(!side).trade(price, quantity)
-    }
-
-    /// Try to execute a trade and return `None` in case it couldn't be
-    /// performed. The complexity for this operation is *O*(log *n* + *m*) where
+    /// Perform a trade against the opposite side at the specified price level
+    /// and for at most the specified quantity, following the allocation
+    /// policy. The complexity for this operation is *O*(log *n* + *m*) where
     /// *n* is the size of the order book tree and *m* is the length of the
     /// price level.
-    fn try_trade(
-        &mut self,
-        side: Side,
-        price: u32,
-        quantity: u32,
-        user_id: usize,
-        order_id: usize,
-    ) -> Option<OrderOutcome> {
-        let top_price = self.get_best_for_side(!side);
+    fn trade(&mut self, side: Side, price: u32, quantity: u32) -> Vec<Fill> {
+        let allocation = self.allocation;
+        self.get_side_mut(!side).trade(price, quantity, allocation)
+    }
 
-        // Check whether there is a matching opposite order
-        if let Some(order) = self.trade(side, price, quantity) {
-            // Matching order found, remove corresponding order
-            self.orders.remove(&order.id);
+    /// Match an incoming order against the opposite side for as long as the
+    /// book is crossed, and return the executed trades. Price levels are
+    /// visited from the best one, and orders within a level in time priority.
+    /// Market orders cross every price level. Trades never print outside the
+    /// dynamic band around the last trade price at the arrival of the order.
+    ///
+    /// Resting orders with a minimum quantity, or all-or-none, are skipped when
+    /// the quantity left to execute can't satisfy them and keep their time
+    /// priority. An incoming order with such a constraint only trades when the
+    /// crossing levels can execute at least its minimum, otherwise nothing is
+    /// traded.
+    /// The complexity for this operation is *O*(*k* (log *n* + *m*)) where *n*
+    /// is the size of the order book tree, *k* the number of visited levels
+    /// and *m* is the length of the price level.
+    fn try_trade(&mut self, order: &mut Order) -> Vec<Trade> {
+        let side = order.side;
+        let mut trades = Vec::new();
 
-            // Set buy and sell IDs according to the execution side
-            let ids = if order.side == Side::Ask {
-                (user_id, order_id, order.user_id, order.id)
-            } else {
-                (order.user_id, order.id, user_id, order_id)
+        // Opposite prices crossed by the order, from the best one
+        let comparator = Self::get_cmp_for_side(!side);
+        let limit = if order.is_market() {
+            None
+        } else {
+            Some(order.price)
+        };
+        let band = self.dynamic_band.zip(self.last_price);
+        let through = |price: u32| {
+            limit.map_or(true, |limit| comparator(&price, &limit))
+                && band.map_or(true, |(band, last)| band.contains(last, price))
+        };
+
+        if order.min_fill() > 0 && self.available(!side, order.leaves(), through) < order.min_fill()
+        {
+            // The book can't satisfy the minimum quantity of the order
+            return trades;
+        }
+
+        let mut skipped = Vec::new();
+
+        while order.leaves() > 0 {
+            let price = match self.get_best_for_side(!side) {
+                Some(price) if through(price) => price,
+                _ => break,
             };
 
-            // Check whether the top of the book is changed, if so assign a top price, side
-            // and volume for the new top of the book
-            let (top_price, traded_side, volume) = if top_price.unwrap() == price {
-                let top_price = self.get_best_for_side(!side);
-                let volume = if let Some(top) = top_price {
-                    let volume = self.get_side(!side).get_price_volume(top);
-                    volume.map(|v| v)
+            for fill in self.trade(side, price, order.leaves()) {
+                order.fill(fill.quantity);
+
+                // Keep the order index consistent with the executions
+                if fill.is_complete() {
+                    self.orders.remove(&fill.order.id);
+                }
+
+                // Set buy and sell IDs according to the execution side
+                let (buy, sell) = if side == Side::Bid {
+                    (&*order, &fill.order)
                 } else {
-                    None
+                    (&fill.order, &*order)
                 };
 
-                (top_price, Some(!side), volume)
-            } else {
-                (None, None, None)
-            };
+                trades.push(Trade {
+                    user_id_buy: buy.user_id,
+                    order_id_buy: buy.id,
+                    user_id_sell: sell.user_id,
+                    order_id_sell: sell.id,
+                    price,
+                    quantity: fill.quantity,
+                });
+            }
 
-            // Destructure IDs and return the result
-            let (user_id_buy, order_id_buy, user_id_sell, order_id_sell) = ids;
-            return Some(OrderOutcome::Traded {
-                user_id,
-                order_id,
-                user_id_buy,
-                order_id_buy,
-                user_id_sell,
-                order_id_sell,
-                price,
-                quantity,
-                side: traded_side,
-                top_price,
-                volume,
-            });
+            // Whatever is left of the level was skipped for its minimum, it's
+            // set aside to reach the following levels
+            if order.leaves() > 0 {
+                skipped.extend(self.get_side_mut(!side).take(price));
+            }
+        }
+
+        for price_level in skipped {
+            self.get_side_mut(!side).put(price_level);
+        }
+
+        // An aggressive iceberg shows a new slice for its remaining quantity
+        order.replenish();
+
+        trades
+    }
+
+    /// Visit the price levels of a side from the best one, until `visit`
+    /// returns false or the side runs out of levels. Only the ends of the tree
+    /// can be reached without walking it whole, so visited levels are taken out
+    /// of the side to reach the following ones, and put back once the walk is
+    /// over. The complexity for this operation is *O*(*k* log *n*), where *n*
+    /// is the size of the tree and *k* is the number of visited levels.
+    fn walk(&mut self, side: Side, mut visit: impl FnMut(&PriceLevel) -> bool) {
+        let mut visited = Vec::new();
+
+        while let Some(price_level) = self
+            .get_best_for_side(side)
+            .and_then(|price| self.get_side_mut(side).take(price))
+        {
+            let more = visit(&price_level);
+            visited.push(price_level);
+
+            if !more {
+                break;
+            }
+        }
+
+        for price_level in visited {
+            self.get_side_mut(side).put(price_level);
         }
+    }
+
+    /// Compute how much of `quantity` would be executed against the levels of
+    /// a side from the best one, as long as `through` accepts their price,
+    /// without changing the book.
+    fn available(&mut self, side: Side, quantity: u32, through: impl Fn(u32) -> bool) -> u32 {
+        let allocation = self.allocation;
+        let mut available = 0;
+
+        self.walk(side, |price_level| {
+            if !through(price_level.price) {
+                return false;
+            }
+
+            available += price_level.available(quantity - available, allocation);
+            available < quantity
+        });
 
-        None
+        available
     }
 
-    /// Append an order to the corresponding book side, and returns the outcome.
+    /// Whether a limit order would cross the best price of the opposite side.
+    fn crosses(&self, order: &Order) -> bool {
+        let comparator = Self::get_cmp_for_side(order.side);
+        matches!(self.get_best_for_side(!order.side), Some(best) if comparator(&order.price, &best))
+    }
+
+    /// Append an order to the corresponding book side, and returns the outcomes.
     /// The complexity for this operation is *O*(log *n* + *m*), where *n* is
     /// the size of the tree and *m* is the length of the price level.
     ///
@@ -279,182 +1057,2030 @@ impl OrderBook {
         quantity: u32,
         user_id: usize,
         order_id: usize,
-    ) -> OrderOutcome {
-        // Try to trade the current order
-        //Xif let Some(outcome) = self.try_trade(side, price, quantity, user_id, order_id) {
-        //X    return outcome;
-        //X}
+    ) -> Vec<OrderOutcome> {
+        self.submit(Order::new(order_id, user_id, side, price, quantity))
+    }
 
-        // Get the best for the own and opposite side
-        let own_best = self.get_best_for_side(side);
-        let opp_best = self.get_best_for_side(!side);
+    /// Match an order against the book and append whatever is left of it to
+    /// the corresponding book side, then returns the outcomes. This is the
+    /// general form of `submit_order` and it accepts every kind of order. In
+    /// reject mode, the default one, orders that would cross the book are
+    /// rejected instead of matched, see `with_mode`.
+    ///
+    /// Post-only orders are never matched: if they would cross the book
+    /// they're either rejected or slid one tick behind the opposite best price,
+    /// according to their flag. Conditional orders wait away from the book
+    /// until a trade triggers them, each trigger is reported by a `Triggered`
+    /// outcome followed by the outcome of the activated order. Activations can
+    /// trigger further conditional orders, they're all processed within the
+    /// same call following the order in which they were triggered.
+    ///
+    /// Pegged orders never take liquidity, they rest at the price derived from
+    /// the book or they're rejected when the reference price is missing. Once
+    /// the order is processed the pegged orders are repriced if the top of the
+    /// book moved, each of them is reported by an `Amended` outcome.
+    ///
+    /// Minimum quantity and all-or-none orders can't be icebergs, since only
+    /// the shown slice could trade at once, and they're rejected. Whatever is
+    /// left of an order that still crosses resting orders skipped for their
+    /// minimum quantity is cancelled, and a `Cancelled` outcome follows its
+    /// `Traded` one.
+    ///
+    /// Orders breaking the instrument rules are rejected, see
+    /// `InstrumentRules::check`. Orders are only accepted when the session
+    /// allows them, during auctions they accumulate on the book without being
+    /// matched. Limit prices outside the price bands are rejected, and a trade
+    /// that would print outside the dynamic band starts a volatility auction
+    /// instead, see `with_dynamic_band`.
+    ///
+    /// The complexity for this operation is *O*(*n* + *k* (log *n* + *m*)),
+    /// where *n* is the size of the tree, *k* the number of traded price levels
+    /// and *m* is the length of the price level, for each activated order.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::{MatchingMode, OrderBook, OrderOutcome};
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+    /// order_book.submit(Order::new(1, 1, Side::Ask, 10, 100).with_display(20));
+    /// let outcomes = order_book.submit(Order::new(2, 2, Side::Bid, 10, 50));
+    ///
+    /// let trades = outcomes[0].trades();
+    /// assert_eq!(trades.iter().map(|t| t.quantity).sum::<u32>(), 50);
+    /// assert_eq!(order_book.best_ask_price(), Some(10));
+    /// ```
+    pub fn submit(&mut self, order: Order) -> Vec<OrderOutcome> {
+        let mut outcomes = Vec::new();
+        let mut activations = VecDeque::new();
 
-        // Get comparators for the own and opposite side
-        let comparator = Self::get_cmp_for_side(side);
+        if !self.session.allows_orders() {
+            let (user_id, order_id) = (order.user_id, order.id);
+            return vec![OrderOutcome::Rejected {
+                user_id,
+                order_id,
+                reason: RejectReason::Session,
+            }];
+        }
 
-        if let Some(best) = opp_best {
-            if comparator(&price, &best) {
-                // This would cross the book
-                return OrderOutcome::Rejected { user_id, order_id };
-            }
+        // Icebergs only show a slice at a time, which can't honour a minimum
+        let constrained = order.all_or_none || order.min_quantity.is_some();
+        if order.display.is_some() && constrained {
+            let (user_id, order_id) = (order.user_id, order.id);
+            return vec![OrderOutcome::Rejected {
+                user_id,
+                order_id,
+                reason: RejectReason::Unsupported,
+            }];
         }
 
-        let order = Order::new(order_id, user_id, side, price, quantity);
+        if let Err(reason) = self.rules.check(&order) {
+            let (user_id, order_id) = (order.user_id, order.id);
+            return vec![OrderOutcome::Rejected { user_id, order_id, reason }];
+        }
 
-        if let Some(best) = own_best {
-            if comparator(&price, &best) {
-                // This is the new top of the book
-                let (top_price, volume) = self.append(order);
-                return OrderOutcome::TopOfBook { user_id, order_id, top_price, volume, side };
-            }
-        } else {
-            // This is the first order on the side
-            let (top_price, volume) = self.append(order);
-            return OrderOutcome::TopOfBook { user_id, order_id, top_price, volume, side };
+        if !self.within_bands(&order) {
+            let (user_id, order_id) = (order.user_id, order.id);
+            return vec![OrderOutcome::Rejected {
+                user_id,
+                order_id,
+                reason: RejectReason::PriceBand,
+            }];
         }
 
-        self.append(order);
-        OrderOutcome::Created { user_id, order_id }
+        let outcome = self.execute(order);
+        self.settle(outcome, &mut outcomes, &mut activations);
+        self.activate(&mut outcomes, activations);
+
+        // Pegged orders follow the book once everything settled
+        outcomes.extend(self.reprice());
+        outcomes.extend(self.publish());
+        outcomes
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Move the book to the provided session, and return the outcomes. The
+    /// transition is reported by a `Transitioned` outcome, while transitions
+    /// the current session doesn't allow are ignored and produce no outcome.
+    ///
+    /// Entering continuous trading, or closing the book, uncrosses the book
+    /// first: this is how auctions end, see `uncross`. In reject mode that
+    /// also cancels the orders whose minimum quantity keeps them crossing the
+    /// book, as it can't be left crossed. Once closed, the last trade price
+    /// becomes the reference price of the static band. Entering an auction
+    /// publishes the `Indicative` outcome of the book, if it is already
+    /// crossed. During auctions orders accumulate on the book without being
+    /// matched, and market and pegged orders are rejected as they have no
+    /// limit price to take part in the clearing price with.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::{OrderBook, OrderOutcome};
+    /// use orderbook::order::Side;
+    /// use orderbook::session::Session;
+    ///
+    /// let mut order_book = OrderBook::new().with_session(Session::PreOpen);
+    /// order_book.transition(Session::OpeningAuction);
+    ///
+    /// order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+    /// order_book.submit_order(Side::Bid, 11, 60, 2, 101);
+    /// let outcomes = order_book.transition(Session::Continuous);
+    ///
+    /// assert!(matches!(outcomes[1], OrderOutcome::Uncrossed { price: 10, .. }));
+    /// assert_eq!(outcomes[1].trades()[0].quantity, 60);
+    /// assert_eq!(order_book.session(), Session::Continuous);
+    /// assert_eq!(order_book.best_ask_price(), Some(10));
+    /// ```
+    pub fn transition(&mut self, session: Session) -> Vec<OrderOutcome> {
+        let from = self.session;
 
-    #[test]
-    fn test_get_best_ask_bid_prices() {
-        let mut order_book = OrderBook::new();
+        if !from.can_transition_to(session) {
+            return Vec::new();
+        }
 
-        let low_bid_price = 1;
-        let high_bid_price = 1;
-        let low_ask_price = 2;
-        let high_ask_price = 2;
+        self.session = session;
+        self.indicative = None;
 
-        order_book.append(Order::new(1, 1, Side::Bid, low_bid_price, 1));
-        order_book.append(Order::new(2, 1, Side::Bid, high_bid_price, 1));
-        order_book.append(Order::new(3, 1, Side::Ask, low_ask_price, 1));
-        order_book.append(Order::new(4, 1, Side::Ask, high_ask_price, 1));
+        let mut outcomes = vec![OrderOutcome::Transitioned { from, to: session }];
 
-        assert_eq!(order_book.best_bid_price().unwrap(), high_bid_price);
-        assert_eq!(order_book.best_ask_price().unwrap(), low_ask_price);
+        if matches!(session, Session::Continuous | Session::Closed) {
+            outcomes.extend(self.uncross());
+        }
+
+        // The close is the reference price of the next session
+        if session == Session::Closed {
+            self.reference_price = self.last_price.or(self.reference_price);
+        }
+
+        outcomes.extend(self.publish());
+        outcomes
     }
 
-    #[test]
-    fn test_append_remove() {
-        let mut order_book = OrderBook::new();
-        let bid_order = Order::new(1, 1, Side::Bid, 1, 1);
-        let ask_order = Order::new(2, 1, Side::Ask, 1, 1);
+    /// Schedule a transition to the provided session at the provided time, it
+    /// happens once the clock of the book is advanced to that time. Transitions
+    /// scheduled at the same time happen in the order they were scheduled.
+    /// The complexity for this operation is *O*(*n*), where *n* is the number
+    /// of scheduled transitions.
+    pub fn schedule(&mut self, time: u64, session: Session) {
+        let pos = self.schedule.partition_point(|&(at, _)| at <= time);
+        self.schedule.insert(pos, (time, session));
+    }
 
-        order_book.append(bid_order);
-        order_book.append(ask_order);
+    /// Advance the clock of the book to the provided time, performing every
+    /// transition scheduled up to it, and return their outcomes. The clock
+    /// never goes back.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order_book::OrderBook;
+    /// use orderbook::session::Session;
+    ///
+    /// let mut order_book = OrderBook::new();
+    /// order_book.schedule(1600, Session::Closed);
+    /// order_book.schedule(1550, Session::ClosingAuction);
+    ///
+    /// order_book.advance(1555);
+    /// assert_eq!(order_book.session(), Session::ClosingAuction);
+    ///
+    /// order_book.advance(1600);
+    /// assert_eq!(order_book.session(), Session::Closed);
+    /// ```
+    pub fn advance(&mut self, now: u64) -> Vec<OrderOutcome> {
+        let mut outcomes = Vec::new();
+        self.now = self.now.max(now);
 
-        order_book.remove(bid_order);
-        order_book.remove(ask_order);
+        while self.schedule.front().map_or(false, |&(at, _)| at <= now) {
+            let (_, session) = self.schedule.pop_front().unwrap();
+            outcomes.extend(self.transition(session));
+        }
 
-        assert_eq!(order_book.orders.get(&ask_order.user_id), None);
-        assert_eq!(order_book.orders.get(&bid_order.user_id), None);
-        assert_eq!(order_book.best_bid_price(), None);
-        assert_eq!(order_book.best_ask_price(), None);
+        outcomes
     }
 
-    #[test]
-    fn test_submit_order_created_and_top() {
-        let mut order_book = OrderBook::new();
+    /// Compute the price the book would uncross at, along with its volume and
+    /// imbalance, see `auction::clearing_price` for the rules. The last trade
+    /// price is used as the reference price. The complexity for this operation
+    /// is *O*(*n* log *n* + *k*), where *n* is the size of the tree and *k*
+    /// the number of orders.
+    #[must_use]
+    pub fn indicative(&self) -> Option<Clearing> {
+        let bids = self.bids.depth();
+        let asks = self.asks.depth();
 
-        let bid_price = 2;
-        let ask_price = 3;
+        auction::clearing_price(&bids, &asks, self.last_price)
+    }
 
-        let bid_outcome = order_book.submit_order(Side::Bid, bid_price, 1, 1, 1);
-        let ask_outcome = order_book.submit_order(Side::Ask, ask_price, 2, 1, 101);
+    /// Uncross the book at the clearing price, if it is crossed, and return
+    /// the outcomes. Every eligible order executes at the clearing price: bids
+    /// at or above it and asks at or below it, from the best price and
+    /// following the allocation policy within each price level. Buy and sell
+    /// executions are paired in that sequence, and reported by a single
+    /// `Uncrossed` outcome.
+    ///
+    /// Orders with a minimum quantity, or all-or-none, are skipped when their
+    /// minimum can't be met, the uncrossed volume shrinks accordingly. When
+    /// that leaves the other orders crossed they're uncrossed again, at their
+    /// own clearing price, with another `Uncrossed` outcome. In reject mode
+    /// continuous trading can't resume on a crossed book, which would reject
+    /// every order priced through the touch, so the skipped orders still
+    /// crossing it are then cancelled, each reported by a `Cancelled` outcome.
+    /// The auction trades trigger conditional orders like any other trade when
+    /// continuous trading starts, then the pegged orders follow the book.
+    fn uncross(&mut self) -> Vec<OrderOutcome> {
+        let clearing = match self.indicative() {
+            Some(clearing) => clearing,
+            None => return Vec::new(),
+        };
 
-        assert_eq!(
-            bid_outcome,
-            OrderOutcome::TopOfBook {
-                user_id: 1,
-                order_id: 1,
-                side: Side::Bid,
-                top_price: Some(bid_price),
-                volume: Some(1)
+        let mut outcomes = Vec::new();
+        let mut activations = VecDeque::new();
+        let mut outcome = self.clear(clearing);
+
+        // Skipped minimum quantities can hold the clearing price where the
+        // other orders can't trade, those are uncrossed again on their own. A
+        // round only follows one that traded, and each trade shrinks the open
+        // quantity of the book, so the rounds come to an end
+        loop {
+            let (bids, asks) = (self.plain_depth(Side::Bid), self.plain_depth(Side::Ask));
+            let next = match auction::clearing_price(&bids, &asks, self.last_price) {
+                Some(clearing) => self.clear(clearing),
+                None => break,
+            };
+
+            if next.trades().is_empty() {
+                break;
             }
-        );
-        assert_eq!(
-            ask_outcome,
-            OrderOutcome::TopOfBook {
-                user_id: 1,
-                order_id: 101,
-                side: Side::Ask,
-                top_price: Some(ask_price),
-                volume: Some(2)
+
+            // A round without trades is only reported when it's the last one
+            if !outcome.trades().is_empty() {
+                self.settle(outcome, &mut outcomes, &mut activations);
             }
-        );
+            outcome = next;
+        }
 
-        assert_eq!(order_book.best_bid_price().unwrap(), bid_price);
-        assert_eq!(order_book.best_ask_price().unwrap(), ask_price);
+        self.settle(outcome, &mut outcomes, &mut activations);
 
-        let bid_outcome = order_book.submit_order(Side::Bid, 1, 1, 1, 2);
-        let ask_outcome = order_book.submit_order(Side::Ask, 4, 2, 1, 102);
+        if self.mode == MatchingMode::Reject {
+            outcomes.extend(self.cancel_crossing());
+        }
 
-        assert_eq!(
-            bid_outcome,
-            OrderOutcome::Created { user_id: 1, order_id: 2 }
-        );
-        assert_eq!(
-            ask_outcome,
-            OrderOutcome::Created { user_id: 1, order_id: 102 }
-        );
+        self.activate(&mut outcomes, activations);
 
-        assert_eq!(order_book.best_bid_price().unwrap(), bid_price);
-        assert_eq!(order_book.best_ask_price().unwrap(), ask_price);
+        outcomes.extend(self.reprice());
+        outcomes
     }
 
-    #[test]
-    fn test_submit_order_rejected() {
-        let mut order_book = OrderBook::new();
+    /// Return the price of every level of a side along with the open quantity
+    /// of its orders without a minimum quantity, in ascending price order, as
+    /// `BookSide::depth` does for every order. The complexity for this
+    /// operation is *O*(*n* log *n* + *k*), where *n* is the size of the tree
+    /// and *k* is the number of orders.
+    fn plain_depth(&self, side: Side) -> Vec<(u32, u32)> {
+        let book_side = self.get_side(side);
+        let mut depth = Vec::new();
 
-        let bid_outcome = order_book.submit_order(Side::Bid, 2, 2, 1, 101);
-        let ask_outcome = order_book.submit_order(Side::Ask, 1, 1, 1, 1);
+        for price in book_side.prices() {
+            let quantity = book_side.get_level(price).map_or(0, |level| {
+                level
+                    .iter()
+                    .filter(|order| order.min_fill() == 0)
+                    .map(Order::leaves)
+                    .sum()
+            });
 
-        assert_eq!(
-            bid_outcome,
-            OrderOutcome::TopOfBook {
-                user_id: 1,
-                order_id: 101,
-                side: Side::Bid,
-                top_price: Some(2),
-                volume: Some(2)
+            if quantity > 0 {
+                depth.push((price, quantity));
             }
-        );
-        assert_eq!(
-            ask_outcome,
-            OrderOutcome::Rejected { user_id: 1, order_id: 1 }
-        );
+        }
+
+        depth
     }
 
-    #[test]
-    fn test_submit_order_traded() {
-        let mut order_book = OrderBook::new();
+    /// Cancel the resting orders whose minimum quantity keeps them crossing
+    /// the book, from the best price of either side, and return the outcomes.
+    /// Orders without a minimum don't cross each other once uncrossed, so the
+    /// best level of one side at least is only made of such orders. Each
+    /// iteration empties a level, so the cancels come to an end as well.
+    fn cancel_crossing(&mut self) -> Vec<OrderOutcome> {
+        let mut outcomes = Vec::new();
 
-        order_book.submit_order(Side::Bid, 3, 2, 1, 101);
-        order_book.submit_order(Side::Bid, 2, 1, 1, 102);
-        let outcome = order_book.submit_order(Side::Ask, 2, 1, 2, 1);
+        while let (Some(bid), Some(ask)) = (self.best_bid_price(), self.best_ask_price()) {
+            if bid < ask {
+                break;
+            }
 
-        assert_eq!(
-            outcome,
-            OrderOutcome::Traded {
-                user_id: 2,
-                order_id: 1,
-                user_id_buy: 1,
-                order_id_buy: 102,
-                user_id_sell: 2,
-                order_id_sell: 1,
-                price: 2,
-                quantity: 1,
-                side: None,
-                top_price: None,
-                volume: None
+            let constrained = |level: &PriceLevel| level.iter().all(|o| o.min_fill() > 0);
+            let (side, price) = match self.bids.get_level(bid) {
+                Some(level) if constrained(level) => (Side::Bid, bid),
+                _ => (Side::Ask, ask),
+            };
+            let orders: Vec<Order> = match self.get_side(side).get_level(price) {
+                Some(level) => level.iter().copied().collect(),
+                None => break,
+            };
+
+            for order in orders {
+                let (user_id, order_id, quantity) = (order.user_id, order.id, order.leaves());
+                self.remove(order);
+                outcomes.push(OrderOutcome::Cancelled { user_id, order_id, quantity });
             }
-        );
-        assert_eq!(order_book.orders.get(&1), None);
+        }
+
+        outcomes
+    }
+
+    /// Execute both sides against each other at the clearing price, and return
+    /// the `Uncrossed` outcome.
+    fn clear(&mut self, clearing: Clearing) -> OrderOutcome {
+        let price = clearing.price;
+        let bid_top = self.get_top_for_side(Side::Bid);
+        let ask_top = self.get_top_for_side(Side::Ask);
+
+        let bid_through = |bid: u32| bid >= price;
+        let ask_through = |ask: u32| ask <= price;
+
+        // Skipped minimum quantities may shrink the volume, until both sides
+        // can execute the same quantity. A volume beyond a single quantity is
+        // left to the following rounds of the uncross
+        let mut quantity = u32::try_from(clearing.volume).unwrap_or(u32::MAX);
+        loop {
+            let bids = self.available(Side::Bid, quantity, bid_through);
+            let asks = self.available(Side::Ask, quantity, ask_through);
+
+            if bids.min(asks) == quantity {
+                break;
+            }
+
+            quantity = bids.min(asks);
+        }
+
+        let bid_fills = self.fill(Side::Bid, quantity, bid_through);
+        let ask_fills = self.fill(Side::Ask, quantity, ask_through);
+
+        // Pair buy and sell executions in their sequence
+        let mut trades = Vec::new();
+        let mut asks = ask_fills.into_iter();
+        let mut ask = asks.next();
+
+        for mut bid in bid_fills {
+            while bid.quantity > 0 {
+                let sell = match ask.as_mut() {
+                    Some(sell) => sell,
+                    None => break,
+                };
+                let executed = bid.quantity.min(sell.quantity);
+
+                trades.push(Trade {
+                    user_id_buy: bid.order.user_id,
+                    order_id_buy: bid.order.id,
+                    user_id_sell: sell.order.user_id,
+                    order_id_sell: sell.order.id,
+                    price,
+                    quantity: executed,
+                });
+
+                bid.quantity -= executed;
+                sell.quantity -= executed;
+
+                if sell.quantity == 0 {
+                    ask = asks.next();
+                }
+            }
+        }
+
+        if !trades.is_empty() {
+            self.last_price = Some(price);
+        }
+
+        let mut tops = Vec::new();
+        for (side, before) in [(Side::Bid, bid_top), (Side::Ask, ask_top)] {
+            let (price, volume) = self.get_top_for_side(side);
+            if (price, volume) != before {
+                tops.push(Top { side, price, volume });
+            }
+        }
+
+        OrderOutcome::Uncrossed { price, trades, tops }
+    }
+
+    /// Execute up to `quantity` against the levels of a side from the best
+    /// one, as long as `through` accepts their price, and return the resulting
+    /// fills. Levels left with orders skipped for their minimum are set aside
+    /// to reach the following ones, as `try_trade` does.
+    fn fill(&mut self, side: Side, quantity: u32, through: impl Fn(u32) -> bool) -> Vec<Fill> {
+        let allocation = self.allocation;
+        let mut fills = Vec::new();
+        let mut skipped = Vec::new();
+        let mut remaining = quantity;
+
+        while remaining > 0 {
+            let price = match self.get_best_for_side(side) {
+                Some(price) if through(price) => price,
+                _ => break,
+            };
+
+            for fill in self.get_side_mut(side).trade(price, remaining, allocation) {
+                remaining -= fill.quantity;
+
+                // Keep the order index consistent with the executions
+                if fill.is_complete() {
+                    self.orders.remove(&fill.order.id);
+                }
+
+                fills.push(fill);
+            }
+
+            if remaining > 0 {
+                skipped.extend(self.get_side_mut(side).take(price));
+            }
+        }
+
+        for price_level in skipped {
+            self.get_side_mut(side).put(price_level);
+        }
+
+        fills
+    }
+
+    /// Publish the indicative clearing during the call phase of an auction,
+    /// only when it changed since it was last published.
+    fn publish(&mut self) -> Vec<OrderOutcome> {
+        if !self.session.is_auction() {
+            return Vec::new();
+        }
+
+        let clearing = self.indicative();
+
+        if clearing == self.indicative {
+            return Vec::new();
+        }
+
+        self.indicative = clearing;
+        vec![OrderOutcome::Indicative { clearing }]
+    }
+
+    /// Process the activations, and the ones they cause, in trigger order.
+    fn activate(&mut self, outcomes: &mut Vec<OrderOutcome>, mut activations: VecDeque<Order>) {
+        while let Some(order) = activations.pop_front() {
+            outcomes.push(OrderOutcome::Triggered { user_id: order.user_id, order_id: order.id });
+            let outcome = self.execute(order);
+            self.settle(outcome, outcomes, &mut activations);
+        }
+    }
+
+    /// Collect the outcome of an execution, followed by the updates of the
+    /// trailing stops. The conditional orders it triggers are queued up for
+    /// activation.
+    fn settle(
+        &mut self,
+        outcome: OrderOutcome,
+        outcomes: &mut Vec<OrderOutcome>,
+        activations: &mut VecDeque<Order>,
+    ) {
+        // Stops are triggered by the stop prices in place when the trades printed
+        activations.extend(self.trigger(&outcome));
+        let amendments = self.trail(&outcome);
+
+        outcomes.push(outcome);
+        outcomes.extend(self.dropped.take());
+        outcomes.extend(amendments);
+
+        // A breach of the dynamic band interrupts continuous trading
+        if mem::take(&mut self.interrupted) {
+            outcomes.extend(self.interrupt());
+        }
+    }
+
+    /// Start a volatility auction and schedule the reopening of continuous
+    /// trading, then return the outcomes.
+    fn interrupt(&mut self) -> Vec<OrderOutcome> {
+        let outcomes = self.transition(Session::VolatilityAuction);
+
+        if !outcomes.is_empty() {
+            self.schedule(self.now + self.reopen_after, Session::Continuous);
+        }
+
+        outcomes
+    }
+
+    /// Whether the limit price of an order is within the static band around
+    /// the reference price and, during continuous trading, within the dynamic
+    /// band around the last trade price. Market and pegged orders have no
+    /// limit price to check.
+    fn within_bands(&self, order: &Order) -> bool {
+        if order.is_market() || order.peg.is_some() {
+            return true;
+        }
+
+        let dynamic = self
+            .dynamic_band
+            .filter(|_| self.session == Session::Continuous);
+
+        [
+            (self.static_band, self.reference_price),
+            (dynamic, self.last_price),
+        ]
+        .iter()
+        .all(|&bounds| match bounds {
+            (Some(band), Some(reference)) => band.contains(reference, order.price),
+            _ => true,
+        })
+    }
+
+    /// Whether an order would still trade against the opposite best price,
+    /// which is outside the dynamic band around the provided reference price.
+    fn breaches_band(&self, order: &Order, reference: Option<u32>) -> bool {
+        let (band, reference) = match self.dynamic_band.zip(reference) {
+            Some(bounds) => bounds,
+            None => return false,
+        };
+
+        let crosses = order.is_market() || self.crosses(order);
+        let best = self.get_best_for_side(!order.side);
+
+        crosses && best.map_or(false, |best| !band.contains(reference, best))
+    }
+
+    /// Execute a single order, and return its outcome without processing the
+    /// conditional orders it triggers.
+    fn execute(&mut self, mut order: Order) -> OrderOutcome {
+        let (user_id, order_id, side) = (order.user_id, order.id, order.side);
+
+        // Trailing stops without a stop price start from the last trade price
+        if order.trail.is_some() && order.stop.is_none() {
+            match self.last_price {
+                Some(price) => order.stop = Some(order.trail_stop(price)),
+                None => {
+                    let reason = RejectReason::NoReference;
+                    return OrderOutcome::Rejected { user_id, order_id, reason };
+                }
+            }
+        }
+
+        // Conditional orders wait for their trigger away from the book
+        if let Some(stop) = order.stop {
+            self.orders.insert(order_id, order);
+            self.get_stops_mut(side).append(stop, order);
+            return OrderOutcome::Created { user_id, order_id };
+        }
+
+        // Orders accumulate during the call phase of an auction
+        if self.session.is_auction() {
+            return self.accumulate(order);
+        }
+
+        // Pegged orders rest at the price derived from the book
+        if order.peg.is_some() {
+            let references = self.get_references();
+            return match self.get_peg_price(&order, references) {
+                Some(price) => {
+                    order.price = price;
+                    self.pegged.push(order_id);
+                    self.rest(order)
+                }
+                None => {
+                    let reason = RejectReason::NoReference;
+                    OrderOutcome::Rejected { user_id, order_id, reason }
+                }
+            };
+        }
+
+        // Post-only orders never take liquidity
+        if let Some(post_only) = order.post_only {
+            if post_only == PostOnly::Slide {
+                self.slide(&mut order);
+            }
+
+            return self.rest(order);
+        }
+
+        // Without trading every order rests or is rejected
+        if self.mode == MatchingMode::Reject {
+            return self.rest(order);
+        }
+
+        // Get the top for the own and opposite side before any execution
+        let own_top = self.get_top_for_side(side);
+        let opp_top = self.get_top_for_side(!side);
+
+        // Try to trade the current order
+        let reference = self.last_price;
+        let trades = self.try_trade(&mut order);
+
+        // The rest of the order would trade outside the dynamic band, a limit
+        // remainder waits for the volatility auction on the crossed book
+        let breach = order.leaves() > 0 && self.breaches_band(&order, reference);
+        self.interrupted |= breach;
+
+        if trades.is_empty() {
+            return if breach {
+                // Market orders have no limit price to wait for the auction with
+                if order.is_market() {
+                    let reason = RejectReason::PriceBand;
+                    return OrderOutcome::Rejected { user_id, order_id, reason };
+                }
+
+                self.accumulate(order)
+            } else {
+                self.rest(order)
+            };
+        }
+
+        self.last_price = trades.last().map(|t| t.price);
+
+        // Whatever is left of a limit order rests on its own side, while
+        // market orders never rest. A remainder still crossing the book, only
+        // made of orders whose minimum quantity couldn't be met, is cancelled
+        if order.leaves() > 0 && !order.is_market() {
+            if breach || !self.crosses(&order) {
+                self.append(order);
+            } else {
+                let quantity = order.leaves();
+                self.dropped = Some(OrderOutcome::Cancelled { user_id, order_id, quantity });
+            }
+        }
+
+        // Collect every top of the book change, starting from the traded side
+        let mut tops = Vec::new();
+        for (side, before) in [(!side, opp_top), (side, own_top)] {
+            let (price, volume) = self.get_top_for_side(side);
+            if (price, volume) != before {
+                tops.push(Top { side, price, volume });
+            }
+        }
+
+        OrderOutcome::Traded { user_id, order_id, trades, tops }
+    }
+
+    /// Remove from the trigger books every conditional order triggered by the
+    /// trades of an outcome, and return them ready to be executed. Buy stops
+    /// come first, sorted by ascending stop price, followed by sell stops,
+    /// sorted by descending stop price, both in time priority within the same
+    /// stop price. Stops are only triggered during continuous trading, so the
+    /// trades of a closing auction leave them pending.
+    fn trigger(&mut self, outcome: &OrderOutcome) -> Vec<Order> {
+        let trades = outcome.trades();
+
+        // Stops only enter the book during continuous trading
+        if self.session != Session::Continuous {
+            return Vec::new();
+        }
+
+        let mut triggered = Vec::new();
+
+        // Buy stops trigger at or above their stop price
+        if let Some(high) = trades.iter().map(|t| t.price).max() {
+            triggered.extend(self.buy_stops.drain_up_to(high));
+        }
+
+        // Sell stops trigger at or below their stop price
+        if let Some(low) = trades.iter().map(|t| t.price).min() {
+            triggered.extend(self.sell_stops.drain_down_to(low));
+        }
+
+        // Activated orders are no longer conditional
+        for order in &mut triggered {
+            self.orders.remove(&order.id);
+            order.stop = None;
+            order.trail = None;
+        }
+
+        triggered
+    }
+
+    /// Move the trailing stops following the trades of an outcome, and return
+    /// an `Amended` outcome for each of them. Sell stops can only move up, with
+    /// the highest trade price, while buy stops can only move down, with the
+    /// lowest one. A moved stop loses its time priority within the new stop
+    /// price.
+    fn trail(&mut self, outcome: &OrderOutcome) -> Vec<OrderOutcome> {
+        let trades = outcome.trades();
+
+        let high = trades.iter().map(|t| t.price).max();
+        let low = trades.iter().map(|t| t.price).min();
+        let mut amendments = Vec::new();
+
+        for (side, price) in [(Side::Ask, high), (Side::Bid, low)] {
+            let price = match price {
+                Some(price) => price,
+                None => continue,
+            };
+
+            let comparator = Self::get_cmp_for_side(!side);
+            let stops = self.get_stops_mut(side);
+
+            for mut order in stops.orders() {
+                let stop = order.stop.unwrap();
+                let trailed = order.trail_stop(price);
+
+                // Only move the stop in the client's favour
+                if order.trail.is_none() || trailed == stop || !comparator(&trailed, &stop) {
+                    continue;
+                }
+
+                stops.remove(stop, order.id);
+                order.stop = Some(trailed);
+                stops.append(trailed, order);
+
+                amendments.push(OrderOutcome::Amended {
+                    user_id: order.user_id,
+                    order_id: order.id,
+                    price: order.price,
+                    stop: order.stop,
+                });
+            }
+        }
+
+        // Keep the order index consistent with the new stop prices
+        for amendment in &amendments {
+            if let OrderOutcome::Amended { order_id, stop, .. } = amendment {
+                if let Some(order) = self.orders.get_mut(order_id) {
+                    order.stop = *stop;
+                }
+            }
+        }
+
+        amendments
+    }
+
+    /// Reprice an order that would cross the book one tick behind the opposite
+    /// best price. The price is left untouched when there's no room for the
+    /// order behind the opposite side.
+    fn slide(&self, order: &mut Order) {
+        let side = order.side;
+        let comparator = Self::get_cmp_for_side(side);
+
+        if let Some(best) = self.get_best_for_side(!side) {
+            if comparator(&order.price, &best) {
+                order.price = self.get_price_behind(side, best).unwrap_or(order.price);
+            }
+        }
+    }
+
+    /// Return the price one tick behind the opposite best price for the
+    /// specified side, on the tick grid of the instrument, if there's room for
+    /// it.
+    fn get_price_behind(&self, side: Side, best: u32) -> Option<u32> {
+        let behind = if side == Side::Bid {
+            best.checked_sub(1)
+                .map(|price| self.rules.round_down(price))
+        } else {
+            best.checked_add(1)
+                .and_then(|price| self.rules.round_up(price))
+        };
+
+        behind.filter(|&p| p > 0)
+    }
+
+    /// Get the best price for the specified side ignoring the pegged orders,
+    /// these are the reference prices pegged orders follow. Leaving the pegged
+    /// orders out prevents them from chasing each other. The complexity for
+    /// this operation is *O*(*k* (log *n* + *m*)) where *n* is the size of the
+    /// tree, *k* is the number of visited levels and *m* is the length of the
+    /// price level.
+    fn get_reference_for_side(&mut self, side: Side) -> Option<u32> {
+        let mut reference = None;
+
+        self.walk(side, |price_level| {
+            if price_level.iter().any(|order| order.peg.is_none()) {
+                reference = Some(price_level.price);
+            }
+
+            reference.is_none()
+        });
+
+        reference
+    }
+
+    /// Get the reference prices of both sides, bid first.
+    fn get_references(&mut self) -> (Option<u32>, Option<u32>) {
+        (
+            self.get_reference_for_side(Side::Bid),
+            self.get_reference_for_side(Side::Ask),
+        )
+    }
+
+    /// Compute the price of a pegged order from the provided reference prices,
+    /// bid first. The price never crosses the opposite side, at most it rests
+    /// one tick behind it. The midpoint, and any price off the tick grid, is
+    /// rounded away from the opposite side.
+    fn get_peg_price(&self, order: &Order, references: (Option<u32>, Option<u32>)) -> Option<u32> {
+        let (side, peg) = (order.side, order.peg?);
+        let (own, opp) = match side {
+            Side::Bid => references,
+            Side::Ask => (references.1, references.0),
+        };
+
+        let reference = match peg.reference {
+            PegReference::Primary => own?,
+            PegReference::Market => opp?,
+            PegReference::Midpoint => {
+                let sum = u64::from(own?) + u64::from(opp?);
+                let mid = if side == Side::Bid {
+                    sum / 2
+                } else {
+                    sum / 2 + sum % 2
+                };
+                mid as u32
+            }
+        };
+
+        let price = i64::from(reference) + i64::from(peg.offset);
+        let price = u32::try_from(price).ok()?;
+
+        // Stay on the tick grid, rounding away from the opposite side
+        let price = if side == Side::Bid {
+            Some(self.rules.round_down(price))
+        } else {
+            self.rules.round_up(price)
+        };
+        let price = price.filter(|&p| p > 0)?;
+
+        // Never cross the opposite side
+        let comparator = Self::get_cmp_for_side(side);
+        match self.get_best_for_side(!side) {
+            Some(best) if comparator(&price, &best) => self.get_price_behind(side, best),
+            _ => Some(price),
+        }
+    }
+
+    /// Move the pegged orders to their new prices when the reference prices
+    /// changed, and return an `Amended` outcome for each of them. Bids are
+    /// repriced before asks, and within a side the moved orders are appended
+    /// in arrival order, so that they keep their time priority within the
+    /// pegged group. Since references ignore pegged orders a single pass is
+    /// enough. The complexity for this operation is *O*(*p* (log *n* + *m*))
+    /// where *p* is the number of pegged orders, *n* is the size of the tree
+    /// and *m* is the length of the price level.
+    fn reprice(&mut self) -> Vec<OrderOutcome> {
+        // Forget pegged orders that left the book
+        let orders = &self.orders;
+        self.pegged.retain(|id| orders.contains_key(id));
+
+        // Pegged orders only follow the book during continuous trading
+        if self.pegged.is_empty() || self.session != Session::Continuous {
+            return Vec::new();
+        }
+
+        let references = self.get_references();
+
+        if references == self.peg_references {
+            return Vec::new();
+        }
+
+        self.peg_references = references;
+        let mut amendments = Vec::new();
+
+        for side in [Side::Bid, Side::Ask] {
+            let mut moved = Vec::new();
+
+            for id in self.pegged.clone() {
+                let order = match self.order_status(id) {
+                    Some(order) if order.side == side => order,
+                    _ => continue,
+                };
+
+                // Without a reference the order stays where it is
+                let price = self
+                    .get_peg_price(&order, references)
+                    .unwrap_or(order.price);
+
+                if price != order.price {
+                    self.remove(order);
+                    moved.push(Order { price, ..order });
+                }
+            }
+
+            for order in moved {
+                self.append(order);
+                amendments.push(OrderOutcome::Amended {
+                    user_id: order.user_id,
+                    order_id: order.id,
+                    price: order.price,
+                    stop: None,
+                });
+            }
+        }
+
+        amendments
+    }
+
+    /// Append an order to the corresponding book side during the call phase of
+    /// an auction, without matching it, and returns the outcome. Market and
+    /// pegged orders are rejected.
+    fn accumulate(&mut self, order: Order) -> OrderOutcome {
+        let (user_id, order_id, side) = (order.user_id, order.id, order.side);
+
+        if order.is_market() || order.peg.is_some() {
+            return OrderOutcome::Rejected { user_id, order_id, reason: RejectReason::Session };
+        }
+
+        let top = self.get_top_for_side(side);
+        let (top_price, volume) = self.append(order);
+
+        if (top_price, volume) == top {
+            OrderOutcome::Created { user_id, order_id }
+        } else {
+            OrderOutcome::TopOfBook { user_id, order_id, side, top_price, volume }
+        }
+    }
+
+    /// Append an order that didn't trade to the corresponding book side, and
+    /// returns the outcome. The order is rejected if it would cross the book,
+    /// or if it is a market order since those never rest.
+    fn rest(&mut self, order: Order) -> OrderOutcome {
+        let (user_id, order_id, side, price) = (order.user_id, order.id, order.side, order.price);
+
+        if order.is_market() {
+            // There's no liquidity for this order
+            let reason = RejectReason::NoLiquidity;
+            return OrderOutcome::Rejected { user_id, order_id, reason };
+        }
+
+        // Get the best for the own and opposite side
+        let own_best = self.get_best_for_side(side);
+        let opp_best = self.get_best_for_side(!side);
+
+        // Get comparators for the own and opposite side
+        let comparator = Self::get_cmp_for_side(side);
+
+        if let Some(best) = opp_best {
+            if comparator(&price, &best) {
+                // This would cross the book
+                let reason = RejectReason::Crossing;
+                return OrderOutcome::Rejected { user_id, order_id, reason };
+            }
+        }
+
+        if let Some(best) = own_best {
+            if comparator(&price, &best) {
+                // This is the new top of the book
+                let (top_price, volume) = self.append(order);
+                return OrderOutcome::TopOfBook { user_id, order_id, top_price, volume, side };
+            }
+        } else {
+            // This is the first order on the side
+            let (top_price, volume) = self.append(order);
+            return OrderOutcome::TopOfBook { user_id, order_id, top_price, volume, side };
+        }
+
+        self.append(order);
+        OrderOutcome::Created { user_id, order_id }
+    }
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::Trail;
+
+    // The sell orders filled by an outcome along with the filled quantity, in
+    // the sequence of the trades
+    fn fills(outcome: &OrderOutcome) -> Vec<(usize, u32)> {
+        outcome
+            .trades()
+            .iter()
+            .map(|trade| (trade.order_id_sell, trade.quantity))
+            .collect()
+    }
+
+    // The top of the book changes reported by an outcome
+    fn tops(outcome: &OrderOutcome) -> Vec<Top> {
+        match outcome {
+            OrderOutcome::Traded { tops, .. } | OrderOutcome::Uncrossed { tops, .. } => {
+                tops.clone()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_get_best_ask_bid_prices() {
+        let mut order_book = OrderBook::new();
+
+        let low_bid_price = 1;
+        let high_bid_price = 1;
+        let low_ask_price = 2;
+        let high_ask_price = 2;
+
+        order_book.append(Order::new(1, 1, Side::Bid, low_bid_price, 1));
+        order_book.append(Order::new(2, 1, Side::Bid, high_bid_price, 1));
+        order_book.append(Order::new(3, 1, Side::Ask, low_ask_price, 1));
+        order_book.append(Order::new(4, 1, Side::Ask, high_ask_price, 1));
+
+        assert_eq!(order_book.best_bid_price().unwrap(), high_bid_price);
+        assert_eq!(order_book.best_ask_price().unwrap(), low_ask_price);
+    }
+
+    #[test]
+    fn test_append_remove() {
+        let mut order_book = OrderBook::new();
+        let bid_order = Order::new(1, 1, Side::Bid, 1, 1);
+        let ask_order = Order::new(2, 1, Side::Ask, 1, 1);
+
+        order_book.append(bid_order);
+        order_book.append(ask_order);
+
+        order_book.remove(bid_order);
+        order_book.remove(ask_order);
+
+        assert_eq!(order_book.orders.get(&ask_order.user_id), None);
+        assert_eq!(order_book.orders.get(&bid_order.user_id), None);
+        assert_eq!(order_book.best_bid_price(), None);
+        assert_eq!(order_book.best_ask_price(), None);
+    }
+
+    #[test]
+    fn test_submit_order_created_and_top() {
+        let mut order_book = OrderBook::new();
+
+        let bid_price = 2;
+        let ask_price = 3;
+
+        let bid_outcome = order_book.submit_order(Side::Bid, bid_price, 1, 1, 1);
+        let ask_outcome = order_book.submit_order(Side::Ask, ask_price, 2, 1, 101);
+
+        assert_eq!(
+            bid_outcome,
+            vec![OrderOutcome::TopOfBook {
+                user_id: 1,
+                order_id: 1,
+                side: Side::Bid,
+                top_price: Some(bid_price),
+                volume: Some(1)
+            }]
+        );
+        assert_eq!(
+            ask_outcome,
+            vec![OrderOutcome::TopOfBook {
+                user_id: 1,
+                order_id: 101,
+                side: Side::Ask,
+                top_price: Some(ask_price),
+                volume: Some(2)
+            }]
+        );
+
+        assert_eq!(order_book.best_bid_price().unwrap(), bid_price);
+        assert_eq!(order_book.best_ask_price().unwrap(), ask_price);
+
+        let bid_outcome = order_book.submit_order(Side::Bid, 1, 1, 1, 2);
+        let ask_outcome = order_book.submit_order(Side::Ask, 4, 2, 1, 102);
+
+        assert_eq!(
+            bid_outcome,
+            vec![OrderOutcome::Created { user_id: 1, order_id: 2 }]
+        );
+        assert_eq!(
+            ask_outcome,
+            vec![OrderOutcome::Created { user_id: 1, order_id: 102 }]
+        );
+
+        assert_eq!(order_book.best_bid_price().unwrap(), bid_price);
+        assert_eq!(order_book.best_ask_price().unwrap(), ask_price);
+    }
+
+    #[test]
+    fn test_submit_order_rejected() {
+        let mut order_book = OrderBook::new();
+
+        let bid_outcome = order_book.submit_order(Side::Bid, 2, 2, 1, 101);
+        let ask_outcome = order_book.submit_order(Side::Ask, 1, 1, 1, 1);
+
+        assert_eq!(
+            bid_outcome,
+            vec![OrderOutcome::TopOfBook {
+                user_id: 1,
+                order_id: 101,
+                side: Side::Bid,
+                top_price: Some(2),
+                volume: Some(2)
+            }]
+        );
+        assert_eq!(
+            ask_outcome,
+            vec![OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 1,
+                reason: RejectReason::Crossing
+            }]
+        );
+    }
+
+    #[test]
+    fn test_submit_order_partial_cross() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        let bid_outcome = order_book.submit_order(Side::Bid, 2, 2, 1, 101);
+        let ask_outcome = order_book.submit_order(Side::Ask, 1, 1, 1, 1);
+
+        assert_eq!(
+            bid_outcome,
+            vec![OrderOutcome::TopOfBook {
+                user_id: 1,
+                order_id: 101,
+                side: Side::Bid,
+                top_price: Some(2),
+                volume: Some(2)
+            }]
+        );
+        assert_eq!(
+            ask_outcome,
+            vec![OrderOutcome::Traded {
+                user_id: 1,
+                order_id: 1,
+                trades: vec![Trade {
+                    user_id_buy: 1,
+                    order_id_buy: 101,
+                    user_id_sell: 1,
+                    order_id_sell: 1,
+                    price: 2,
+                    quantity: 1
+                }],
+                tops: vec![Top { side: Side::Bid, price: Some(2), volume: Some(1) }]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_submit_order_post_only_reject() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        order_book.submit_order(Side::Bid, 2, 2, 1, 101);
+        let order = Order::new(1, 1, Side::Ask, 1, 1).with_post_only(PostOnly::Reject);
+        let ask_outcome = order_book.submit(order);
+
+        assert_eq!(
+            ask_outcome,
+            vec![OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 1,
+                reason: RejectReason::Crossing
+            }]
+        );
+        assert_eq!(order_book.get_top_for_side(Side::Bid), (Some(2), Some(2)));
+    }
+
+    #[test]
+    fn test_submit_order_post_only_slide() {
+        let mut order_book = OrderBook::new();
+
+        order_book.submit_order(Side::Bid, 5, 2, 1, 101);
+        let order = Order::new(1, 1, Side::Ask, 3, 1).with_post_only(PostOnly::Slide);
+        let ask_outcome = order_book.submit(order);
+
+        assert_eq!(
+            ask_outcome,
+            vec![OrderOutcome::TopOfBook {
+                user_id: 1,
+                order_id: 1,
+                side: Side::Ask,
+                top_price: Some(6),
+                volume: Some(1)
+            }]
+        );
+
+        // Not crossing orders keep their price
+        let order = Order::new(2, 1, Side::Bid, 4, 1).with_post_only(PostOnly::Slide);
+        order_book.submit(order);
+        assert_eq!(order_book.orders.get(&2).unwrap().price, 4);
+
+        // There's no room behind a best ask of one
+        let mut order_book = OrderBook::new();
+        order_book.submit_order(Side::Ask, 1, 1, 1, 1);
+        let order = Order::new(101, 1, Side::Bid, 1, 1).with_post_only(PostOnly::Slide);
+
+        assert_eq!(
+            order_book.submit(order),
+            vec![OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 101,
+                reason: RejectReason::Crossing
+            }]
+        );
+    }
+
+    #[test]
+    fn test_submit_order_traded() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        order_book.submit_order(Side::Bid, 3, 2, 1, 101);
+        order_book.submit_order(Side::Bid, 2, 1, 1, 102);
+        let outcome = order_book.submit_order(Side::Ask, 2, 1, 2, 1);
+
+        assert_eq!(
+            outcome,
+            vec![OrderOutcome::Traded {
+                user_id: 2,
+                order_id: 1,
+                trades: vec![Trade {
+                    user_id_buy: 1,
+                    order_id_buy: 101,
+                    user_id_sell: 2,
+                    order_id_sell: 1,
+                    price: 3,
+                    quantity: 1
+                }],
+                tops: vec![Top { side: Side::Bid, price: Some(3), volume: Some(1) }]
+            }]
+        );
+        assert_eq!(order_book.orders.get(&1), None);
+        assert!(order_book.orders.contains_key(&101));
+    }
+
+    #[test]
+    fn test_submit_order_traded_across_levels() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+        order_book.submit_order(Side::Ask, 11, 100, 1, 2);
+        let outcome = order_book.submit_order(Side::Bid, 11, 250, 2, 101);
+
+        let prices: Vec<_> = outcome[0].trades().iter().map(|t| t.price).collect();
+        assert_eq!(prices, vec![10, 11]);
+        assert_eq!(fills(&outcome[0]), vec![(1, 100), (2, 100)]);
+        assert_eq!(
+            tops(&outcome[0]),
+            vec![
+                Top { side: Side::Ask, price: None, volume: None },
+                Top { side: Side::Bid, price: Some(11), volume: Some(50) }
+            ]
+        );
+
+        assert_eq!(order_book.orders.len(), 1);
+        assert_eq!(order_book.best_bid_price(), Some(11));
+    }
+
+    #[test]
+    fn test_submit_iceberg() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        let outcome = order_book.submit(Order::new(1, 1, Side::Ask, 10, 100).with_display(20));
+
+        assert_eq!(
+            outcome,
+            vec![OrderOutcome::TopOfBook {
+                user_id: 1,
+                order_id: 1,
+                side: Side::Ask,
+                top_price: Some(10),
+                volume: Some(20)
+            }]
+        );
+
+        // Trade through the shown slice and into the hidden quantity
+        order_book.submit_order(Side::Ask, 10, 5, 1, 2);
+        let outcome = order_book.submit_order(Side::Bid, 10, 50, 2, 101);
+
+        assert_eq!(fills(&outcome[0]), vec![(1, 20), (2, 5), (1, 20), (1, 5)]);
+        assert_eq!(
+            tops(&outcome[0]),
+            vec![Top { side: Side::Ask, price: Some(10), volume: Some(15) }]
+        );
+
+        // Cancelling the iceberg takes away its hidden quantity as well
+        order_book.cancel_order(1, 1);
+        assert_eq!(order_book.best_ask_price(), None);
+    }
+
+    #[test]
+    fn test_submit_market_order() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        // Nothing to trade against
+        assert_eq!(
+            order_book.submit(Order::new(1, 1, Side::Bid, 0, 10).with_market()),
+            vec![OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 1,
+                reason: RejectReason::NoLiquidity
+            }]
+        );
+
+        order_book.submit_order(Side::Ask, 10, 5, 2, 101);
+        order_book.submit_order(Side::Ask, 20, 5, 2, 102);
+        let outcomes = order_book.submit(Order::new(2, 1, Side::Bid, 0, 15).with_market());
+
+        // Every level is crossed and the remainder never rests
+        assert_eq!(fills(&outcomes[0]), vec![(101, 5), (102, 5)]);
+        assert_eq!(order_book.best_ask_price(), None);
+        assert_eq!(order_book.best_bid_price(), None);
+        assert!(order_book.orders.is_empty());
+    }
+
+    #[test]
+    fn test_submit_stop_orders() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        order_book.submit_order(Side::Ask, 10, 5, 1, 1);
+        order_book.submit_order(Side::Ask, 11, 5, 1, 2);
+        order_book.submit_order(Side::Ask, 12, 5, 1, 3);
+
+        // A stop-limit above the market and a stop-market that it will trigger
+        let stop_limit = Order::new(101, 2, Side::Bid, 12, 5).with_stop(11);
+        let stop_market = Order::new(102, 3, Side::Bid, 0, 5)
+            .with_market()
+            .with_stop(10);
+
+        assert_eq!(
+            order_book.submit(stop_limit),
+            vec![OrderOutcome::Created { user_id: 2, order_id: 101 }]
+        );
+        order_book.submit(stop_market);
+
+        // Pending stops are not on the book
+        assert_eq!(order_book.get_top_for_side(Side::Bid), (None, None));
+
+        let outcomes = order_book.submit_order(Side::Bid, 10, 5, 4, 103);
+        let triggered: Vec<_> = outcomes
+            .iter()
+            .filter_map(|o| match o {
+                OrderOutcome::Triggered { order_id, .. } => Some(*order_id),
+                _ => None,
+            })
+            .collect();
+
+        // The stop-market prints at 11, which triggers the stop-limit in turn
+        assert_eq!(triggered, vec![102, 101]);
+        assert_eq!(outcomes.len(), 5);
+        assert_eq!(order_book.best_ask_price(), None);
+        assert!(order_book.buy_stops.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_unknown_order() {
+        let mut order_book = OrderBook::new();
+
+        order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+        order_book.cancel_order(1, 1);
+
+        // Cancelled orders are unknown as well
+        for order_id in 1..=2 {
+            assert_eq!(
+                order_book.cancel_order(2, order_id),
+                vec![OrderOutcome::Rejected {
+                    user_id: 2,
+                    order_id,
+                    reason: RejectReason::UnknownOrder
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_cancel_stop_order() {
+        let mut order_book = OrderBook::new();
+
+        order_book.submit(Order::new(1, 1, Side::Ask, 0, 5).with_market().with_stop(9));
+
+        assert_eq!(
+            order_book.cancel_order(1, 1),
+            vec![OrderOutcome::Created { user_id: 1, order_id: 1 }]
+        );
+        assert!(order_book.sell_stops.is_empty());
+        assert!(order_book.orders.is_empty());
+    }
+
+    #[test]
+    fn test_trailing_stop() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        // Without a trade there's nothing to trail
+        let trailing = Order::new(1, 1, Side::Ask, 0, 5)
+            .with_market()
+            .with_trail(Trail::Offset(2));
+        assert_eq!(
+            order_book.submit(trailing),
+            vec![OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 1,
+                reason: RejectReason::NoReference
+            }]
+        );
+
+        order_book.submit_order(Side::Ask, 10, 1, 2, 101);
+        order_book.submit_order(Side::Bid, 10, 1, 3, 201);
+        assert_eq!(order_book.last_price(), Some(10));
+
+        order_book.submit(trailing);
+        assert_eq!(order_book.order_status(1).unwrap().stop, Some(8));
+
+        // The market moves up and the stop follows it
+        order_book.submit_order(Side::Ask, 12, 1, 2, 102);
+        let outcomes = order_book.submit_order(Side::Bid, 12, 1, 3, 202);
+
+        assert_eq!(
+            outcomes[1],
+            OrderOutcome::Amended { user_id: 1, order_id: 1, price: 0, stop: Some(10) }
+        );
+        assert_eq!(order_book.order_status(1).unwrap().stop, Some(10));
+
+        // The market moves down and the stop stays put until it's triggered
+        order_book.submit_order(Side::Bid, 11, 1, 3, 203);
+        order_book.submit_order(Side::Bid, 9, 5, 3, 204);
+        let outcomes = order_book.submit_order(Side::Ask, 11, 1, 2, 103);
+
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(order_book.order_status(1).unwrap().stop, Some(10));
+
+        order_book.submit_order(Side::Bid, 10, 1, 3, 205);
+        let outcomes = order_book.submit_order(Side::Ask, 10, 1, 2, 104);
+
+        assert_eq!(
+            outcomes[1],
+            OrderOutcome::Triggered { user_id: 1, order_id: 1 }
+        );
+        assert_eq!(order_book.order_status(1), None);
+        assert_eq!(order_book.last_price(), Some(9));
+    }
+
+    #[test]
+    fn test_submit_pegged_orders() {
+        let mut order_book = OrderBook::new();
+
+        // There's no reference price yet
+        let primary = Order::new(1, 1, Side::Bid, 0, 5).with_peg(PegReference::Primary, 0);
+        assert_eq!(
+            order_book.submit(primary),
+            vec![OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 1,
+                reason: RejectReason::NoReference
+            }]
+        );
+
+        order_book.submit_order(Side::Bid, 10, 5, 2, 101);
+        order_book.submit_order(Side::Ask, 13, 5, 2, 201);
+
+        order_book.submit(primary);
+        let midpoint = Order::new(2, 1, Side::Ask, 0, 5).with_peg(PegReference::Midpoint, 0);
+        order_book.submit(midpoint);
+        let market = Order::new(3, 1, Side::Bid, 0, 5).with_peg(PegReference::Market, 0);
+        order_book.submit(market);
+
+        assert_eq!(order_book.order_status(1).unwrap().price, 10);
+        assert_eq!(order_book.order_status(2).unwrap().price, 12);
+        // A market peg never crosses the opposite side
+        assert_eq!(order_book.order_status(3).unwrap().price, 11);
+    }
+
+    #[test]
+    fn test_reprice_pegged_orders() {
+        let mut order_book = OrderBook::new();
+
+        order_book.submit_order(Side::Bid, 10, 5, 2, 101);
+        order_book.submit(Order::new(1, 1, Side::Bid, 0, 5).with_peg(PegReference::Primary, 0));
+        order_book.submit(Order::new(2, 1, Side::Bid, 0, 5).with_peg(PegReference::Primary, 0));
+
+        // The top of the book moves and the pegged orders follow it in order
+        let outcomes = order_book.submit_order(Side::Bid, 11, 5, 2, 102);
+
+        assert_eq!(
+            outcomes[1..],
+            [
+                OrderOutcome::Amended { user_id: 1, order_id: 1, price: 11, stop: None },
+                OrderOutcome::Amended { user_id: 1, order_id: 2, price: 11, stop: None }
+            ]
+        );
+
+        let level = order_book.bids.get_level(11).unwrap();
+        let ids: Vec<_> = level.iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![102, 1, 2]);
+
+        // Moves that don't change the references leave them alone
+        let outcomes = order_book.submit_order(Side::Bid, 9, 5, 2, 103);
+        assert_eq!(outcomes.len(), 1);
+
+        // Cancels move the references as well
+        let outcomes = order_book.cancel_order(2, 102);
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(order_book.order_status(2).unwrap().price, 10);
+        assert_eq!(order_book.best_bid_price(), Some(10));
+    }
+
+    #[test]
+    fn test_submit_min_quantity_orders() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        order_book.submit(Order::new(1, 1, Side::Ask, 10, 50).with_all_or_none());
+        order_book.submit_order(Side::Ask, 11, 30, 1, 2);
+
+        // Too small for the all-or-none order, the next level is traded
+        let outcomes = order_book.submit_order(Side::Bid, 11, 20, 2, 101);
+
+        assert_eq!(fills(&outcomes[0]), vec![(2, 20)]);
+
+        // The incoming minimum can't be met by the crossing levels
+        let outcomes =
+            order_book.submit(Order::new(102, 2, Side::Bid, 11, 70).with_min_quantity(65));
+
+        assert_eq!(
+            outcomes,
+            vec![OrderOutcome::Rejected {
+                user_id: 2,
+                order_id: 102,
+                reason: RejectReason::Crossing
+            }]
+        );
+        assert_eq!(order_book.order_status(1).unwrap().leaves(), 50);
+
+        // Enough for both, the all-or-none order keeps its priority
+        let outcomes =
+            order_book.submit(Order::new(103, 2, Side::Bid, 11, 70).with_min_quantity(55));
+
+        assert_eq!(fills(&outcomes[0]), vec![(1, 50), (2, 10)]);
+
+        assert_eq!(order_book.best_ask_price(), None);
+        assert_eq!(order_book.best_bid_price(), Some(11));
+    }
+
+    #[test]
+    fn test_submit_min_quantity_iceberg() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        // Only the shown slice could trade, the minimum would be broken
+        let iceberg = Order::new(1, 1, Side::Ask, 10, 30)
+            .with_display(10)
+            .with_all_or_none();
+
+        assert_eq!(
+            order_book.submit(iceberg),
+            vec![OrderOutcome::Rejected {
+                user_id: 1,
+                order_id: 1,
+                reason: RejectReason::Unsupported
+            }]
+        );
+
+        order_book.submit_order(Side::Ask, 10, 25, 1, 2);
+        let outcomes = order_book.submit_order(Side::Bid, 10, 40, 2, 101);
+
+        assert_eq!(outcomes[0].trades().len(), 1);
+        assert_eq!(order_book.order_status(1), None);
+        assert_eq!(order_book.order_status(101).unwrap().leaves(), 15);
+        assert_eq!(order_book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_submit_min_quantity_remainder() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+
+        order_book.submit(Order::new(1, 1, Side::Ask, 10, 50).with_all_or_none());
+        order_book.submit_order(Side::Ask, 10, 20, 1, 2);
+
+        // The all-or-none order is skipped, the remainder can't rest across it
+        let outcomes = order_book.submit_order(Side::Bid, 10, 30, 2, 101);
+
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].trades().len(), 1);
+        assert_eq!(
+            outcomes[1],
+            OrderOutcome::Cancelled { user_id: 2, order_id: 101, quantity: 10 }
+        );
+        assert_eq!(order_book.order_status(101), None);
+        assert_eq!(order_book.best_bid_price(), None);
+        assert_eq!(order_book.order_status(1).unwrap().leaves(), 50);
+    }
+
+    #[test]
+    fn test_submit_order_pro_rata() {
+        let allocation = Allocation::ProRata { top_order: true, min_allocation: 0 };
+        let mut order_book = OrderBook::new()
+            .with_mode(MatchingMode::Trade)
+            .with_allocation(allocation);
+
+        order_book.submit_order(Side::Ask, 10, 10, 1, 1);
+        order_book.submit_order(Side::Ask, 10, 30, 1, 2);
+        order_book.submit_order(Side::Ask, 10, 60, 1, 3);
+
+        // The top order is filled first, the rest is spread by size
+        let outcomes = order_book.submit_order(Side::Bid, 10, 28, 2, 101);
+
+        assert_eq!(fills(&outcomes[0]), vec![(1, 10), (2, 6), (3, 12)]);
+
+        assert_eq!(order_book.order_status(1), None);
+        assert_eq!(order_book.order_status(3).unwrap().quantity, 48);
+    }
+
+    #[test]
+    fn test_auction_call() {
+        let mut order_book = OrderBook::new().with_session(Session::PreOpen);
+
+        assert_eq!(
+            order_book.transition(Session::OpeningAuction),
+            vec![OrderOutcome::Transitioned {
+                from: Session::PreOpen,
+                to: Session::OpeningAuction
+            }]
+        );
+
+        // Uncrossed book, nothing to publish
+        order_book.submit_order(Side::Bid, 10, 100, 2, 101);
+        let outcomes = order_book.submit_order(Side::Ask, 11, 100, 1, 1);
+        assert_eq!(outcomes.len(), 1);
+
+        // The book crosses without trading
+        let outcomes = order_book.submit_order(Side::Ask, 9, 40, 1, 2);
+        let clearing = Clearing {
+            price: 10,
+            volume: 40,
+            imbalance: 60,
+            side: Some(Side::Bid),
+        };
+
+        assert_eq!(
+            outcomes[1],
+            OrderOutcome::Indicative { clearing: Some(clearing) }
+        );
+        assert_eq!(order_book.best_ask_price(), Some(9));
+        assert_eq!(order_book.best_bid_price(), Some(10));
+
+        // Unchanged indicative clearings aren't published again
+        let outcomes = order_book.submit_order(Side::Ask, 12, 10, 1, 3);
+        assert_eq!(outcomes.len(), 1);
+
+        // Market and pegged orders can't take part in the call
+        assert_eq!(
+            order_book.submit(Order::new(102, 2, Side::Bid, 0, 10).with_market()),
+            vec![OrderOutcome::Rejected {
+                user_id: 2,
+                order_id: 102,
+                reason: RejectReason::Session
+            }]
+        );
+
+        // Cancels update the indicative clearing as well
+        let outcomes = order_book.cancel_order(1, 2);
+        assert_eq!(outcomes[1], OrderOutcome::Indicative { clearing: None });
+    }
+
+    #[test]
+    fn test_auction_uncross() {
+        let mut order_book = OrderBook::new()
+            .with_mode(MatchingMode::Trade)
+            .with_session(Session::PreOpen);
+        order_book.transition(Session::OpeningAuction);
+
+        order_book.submit_order(Side::Bid, 12, 30, 2, 101);
+        order_book.submit_order(Side::Bid, 10, 50, 2, 102);
+        order_book.submit_order(Side::Ask, 9, 40, 1, 1);
+        order_book.submit_order(Side::Ask, 10, 20, 1, 2);
+        order_book.submit_order(Side::Ask, 11, 100, 1, 3);
+        order_book.submit(
+            Order::new(103, 2, Side::Bid, 0, 5)
+                .with_market()
+                .with_stop(10),
+        );
+
+        let outcomes = order_book.transition(Session::Continuous);
+
+        let trade = |order_id_buy, order_id_sell, quantity| Trade {
+            user_id_buy: 2,
+            order_id_buy,
+            user_id_sell: 1,
+            order_id_sell,
+            price: 10,
+            quantity,
+        };
+
+        assert_eq!(
+            outcomes[1],
+            OrderOutcome::Uncrossed {
+                price: 10,
+                trades: vec![trade(101, 1, 30), trade(102, 1, 10), trade(102, 2, 20)],
+                tops: vec![
+                    Top { side: Side::Bid, price: Some(10), volume: Some(20) },
+                    Top {
+                        side: Side::Ask,
+                        price: Some(11),
+                        volume: Some(100)
+                    }
+                ]
+            }
+        );
+
+        // The auction trades trigger the stops, back in continuous trading
+        assert_eq!(
+            outcomes[2],
+            OrderOutcome::Triggered { user_id: 2, order_id: 103 }
+        );
+        assert!(matches!(outcomes[3], OrderOutcome::Traded { .. }));
+        assert_eq!(order_book.session(), Session::Continuous);
+        assert_eq!(order_book.last_price(), Some(11));
+    }
+
+    #[test]
+    fn test_auction_uncross_large_quantities() {
+        let mut order_book = OrderBook::new().with_session(Session::PreOpen);
+        order_book.transition(Session::OpeningAuction);
+
+        order_book.submit_order(Side::Ask, 10, u32::MAX, 1, 1);
+        order_book.submit_order(Side::Ask, 11, 5, 1, 2);
+        order_book.submit_order(Side::Bid, 11, 5, 2, 101);
+        order_book.submit_order(Side::Bid, 12, u32::MAX, 2, 102);
+
+        // The cleared volume doesn't fit a single quantity, the rest of it
+        // trades in another round
+        let outcomes = order_book.transition(Session::Continuous);
+        let volume: u64 = outcomes
+            .iter()
+            .flat_map(OrderOutcome::trades)
+            .map(|trade| u64::from(trade.quantity))
+            .sum();
+
+        assert_eq!(volume, u64::from(u32::MAX) + 5);
+        assert_eq!(order_book.best_bid_price(), None);
+        assert_eq!(order_book.best_ask_price(), None);
+    }
+
+    #[test]
+    fn test_auction_uncross_min_quantity() {
+        for mode in [MatchingMode::Reject, MatchingMode::Trade] {
+            let mut order_book = OrderBook::new()
+                .with_mode(mode)
+                .with_session(Session::PreOpen);
+            order_book.transition(Session::OpeningAuction);
+
+            order_book.submit(Order::new(101, 2, Side::Bid, 5, 25).with_all_or_none());
+            order_book.submit_order(Side::Bid, 4, 1, 2, 102);
+            order_book.submit_order(Side::Ask, 1, 1, 1, 1);
+            order_book.submit_order(Side::Ask, 3, 2, 1, 2);
+
+            // The all-or-none order holds the clearing price where nothing
+            // trades, the other orders are uncrossed on their own
+            let outcomes = order_book.transition(Session::Continuous);
+
+            assert!(matches!(
+                outcomes[1],
+                OrderOutcome::Uncrossed { price: 1, .. }
+            ));
+            assert_eq!(outcomes[1].trades().len(), 1);
+            assert_eq!(order_book.best_ask_price(), Some(3));
+
+            // Only rejecting crossing orders leaves the book uncrossed
+            if mode == MatchingMode::Reject {
+                assert_eq!(
+                    outcomes[2],
+                    OrderOutcome::Cancelled { user_id: 2, order_id: 101, quantity: 25 }
+                );
+                assert_eq!(order_book.best_bid_price(), None);
+            } else {
+                assert_eq!(outcomes.len(), 2);
+                assert_eq!(order_book.best_bid_price(), Some(5));
+            }
+            assert_eq!(order_book.check_invariants(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_session_operations() {
+        let mut order_book = OrderBook::new();
+        order_book.submit_order(Side::Ask, 10, 10, 1, 1);
+        order_book.submit_order(Side::Ask, 11, 10, 1, 2);
+
+        // Invalid transitions are ignored
+        assert_eq!(order_book.transition(Session::PreOpen), vec![]);
+
+        // Only cancels are allowed while halted
+        order_book.transition(Session::Halted);
+        let rejected = OrderOutcome::Rejected {
+            user_id: 2,
+            order_id: 101,
+            reason: RejectReason::Session,
+        };
+
+        assert_eq!(
+            order_book.submit_order(Side::Bid, 10, 10, 2, 101),
+            vec![rejected]
+        );
+        assert_eq!(order_book.cancel_order(1, 1).len(), 1);
+
+        // Nothing is allowed once closed
+        order_book.transition(Session::Closed);
+        let rejected = OrderOutcome::Rejected {
+            user_id: 1,
+            order_id: 2,
+            reason: RejectReason::Session,
+        };
+
+        assert_eq!(order_book.cancel_order(1, 2), vec![rejected]);
+        assert_eq!(order_book.best_ask_price(), Some(11));
+    }
+
+    #[test]
+    fn test_session_schedule() {
+        let mut order_book = OrderBook::new();
+
+        order_book.submit_order(Side::Ask, 10, 10, 1, 1);
+        order_book.submit(
+            Order::new(2, 1, Side::Ask, 0, 5)
+                .with_market()
+                .with_stop(10),
+        );
+        order_book.schedule(1600, Session::Closed);
+        order_book.schedule(1550, Session::ClosingAuction);
+
+        assert_eq!(order_book.advance(1500), vec![]);
+        assert_eq!(order_book.advance(1550).len(), 1);
+
+        order_book.submit_order(Side::Bid, 10, 10, 2, 101);
+        let outcomes = order_book.advance(1700);
+
+        assert_eq!(
+            outcomes[0],
+            OrderOutcome::Transitioned { from: Session::ClosingAuction, to: Session::Closed }
+        );
+        assert!(matches!(outcomes[1], OrderOutcome::Uncrossed { .. }));
+
+        // The closing auction doesn't trigger stops
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(order_book.order_status(2).unwrap().stop, Some(10));
+    }
+
+    #[test]
+    fn test_static_band() {
+        let mut order_book = OrderBook::new()
+            .with_mode(MatchingMode::Trade)
+            .with_static_band(Band::new(10));
+
+        // Without a reference price the band doesn't apply yet
+        order_book.submit_order(Side::Ask, 100, 10, 1, 1);
+        order_book.submit_order(Side::Bid, 100, 10, 2, 101);
+        order_book.transition(Session::Closed);
+
+        assert_eq!(order_book.reference_price(), Some(100));
+
+        order_book.transition(Session::PreOpen);
+        order_book.transition(Session::OpeningAuction);
+        let rejected = OrderOutcome::Rejected {
+            user_id: 1,
+            order_id: 2,
+            reason: RejectReason::PriceBand,
+        };
+
+        assert_eq!(
+            order_book.submit_order(Side::Ask, 89, 10, 1, 2),
+            vec![rejected]
+        );
+        assert_eq!(order_book.submit_order(Side::Ask, 90, 10, 1, 3).len(), 1);
+        assert_eq!(order_book.best_ask_price(), Some(90));
+    }
+
+    #[test]
+    fn test_dynamic_band() {
+        let mut order_book = OrderBook::new()
+            .with_mode(MatchingMode::Trade)
+            .with_dynamic_band(Band::new(10), 30);
+        order_book.submit_order(Side::Ask, 100, 10, 1, 1);
+        order_book.submit_order(Side::Ask, 105, 10, 1, 2);
+        order_book.submit_order(Side::Ask, 120, 10, 1, 3);
+        order_book.submit_order(Side::Bid, 100, 5, 2, 101);
+
+        // Limit prices follow the last trade price
+        let rejected = OrderOutcome::Rejected {
+            user_id: 2,
+            order_id: 102,
+            reason: RejectReason::PriceBand,
+        };
+
+        assert_eq!(
+            order_book.submit_order(Side::Bid, 111, 30, 2, 102),
+            vec![rejected]
+        );
+
+        // Trades stop at the band, the rest of the order waits for the auction
+        order_book.advance(10);
+        let outcomes = order_book.submit(Order::new(103, 2, Side::Bid, 0, 30).with_market());
+
+        assert_eq!(outcomes[0].trades().len(), 2);
+        assert_eq!(
+            outcomes[1],
+            OrderOutcome::Transitioned {
+                from: Session::Continuous,
+                to: Session::VolatilityAuction
+            }
+        );
+        assert_eq!(order_book.session(), Session::VolatilityAuction);
+
+        let outcomes = order_book.submit_order(Side::Bid, 125, 10, 2, 104);
+        assert!(matches!(
+            outcomes[1],
+            OrderOutcome::Indicative { clearing: Some(_) }
+        ));
+
+        // The book reopens with an uncross
+        assert_eq!(order_book.advance(39), vec![]);
+        let outcomes = order_book.advance(40);
+
+        assert!(matches!(
+            outcomes[1],
+            OrderOutcome::Uncrossed { price: 120, .. }
+        ));
+        assert_eq!(order_book.session(), Session::Continuous);
+    }
+
+    #[test]
+    fn test_dynamic_band_market_order() {
+        let mut order_book = OrderBook::new()
+            .with_mode(MatchingMode::Trade)
+            .with_dynamic_band(Band::new(10), 0);
+        order_book.submit_order(Side::Bid, 80, 10, 2, 101);
+        order_book.submit_order(Side::Ask, 100, 5, 1, 1);
+        order_book.submit_order(Side::Bid, 100, 5, 2, 102);
+
+        // A market order that would only trade outside the band is rejected
+        let outcomes = order_book.submit(Order::new(2, 1, Side::Ask, 0, 10).with_market());
+        let rejected = OrderOutcome::Rejected {
+            user_id: 1,
+            order_id: 2,
+            reason: RejectReason::PriceBand,
+        };
+
+        assert_eq!(outcomes[0], rejected);
+        assert_eq!(order_book.session(), Session::VolatilityAuction);
+        assert_eq!(order_book.best_bid_price(), Some(80));
+    }
+
+    #[test]
+    fn test_instrument_rules() {
+        let rules = InstrumentRules::new().with_tick(5).with_lot(10);
+        let mut order_book = OrderBook::new().with_rules(rules);
+        let rejected = |order_id, reason| OrderOutcome::Rejected { user_id: 1, order_id, reason };
+
+        assert_eq!(
+            order_book.submit_order(Side::Ask, 12, 10, 1, 1),
+            vec![rejected(1, RejectReason::TickSize)]
+        );
+        assert_eq!(
+            order_book.submit_order(Side::Ask, 15, 0, 1, 2),
+            vec![rejected(2, RejectReason::QuantityTooLow)]
+        );
+
+        // Slid and pegged orders stay on the tick grid
+        order_book.submit_order(Side::Ask, 20, 10, 1, 3);
+        let order = Order::new(4, 1, Side::Bid, 25, 10).with_post_only(PostOnly::Slide);
+        order_book.submit(order);
+        let order = Order::new(5, 1, Side::Bid, 0, 10).with_peg(PegReference::Market, -3);
+        order_book.submit(order);
+
+        assert_eq!(order_book.order_status(4).unwrap().price, 15);
+        assert_eq!(order_book.order_status(5).unwrap().price, 15);
+    }
+
+    #[test]
+    fn test_reject_mode() {
+        let mut order_book = OrderBook::new().with_mode(MatchingMode::Reject);
+        order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+
+        let rejected = OrderOutcome::Rejected {
+            user_id: 2,
+            order_id: 2,
+            reason: RejectReason::Crossing,
+        };
+
+        // Crossing orders never trade, the others rest as usual
+        assert_eq!(
+            order_book.submit_order(Side::Bid, 10, 50, 2, 2),
+            vec![rejected]
+        );
+        order_book.submit_order(Side::Bid, 9, 50, 2, 3);
+
+        assert_eq!(order_book.best_ask_price(), Some(10));
+        assert_eq!(order_book.best_bid_price(), Some(9));
+        assert_eq!(order_book.last_price(), None);
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut order_book = OrderBook::new();
+        order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+        order_book.submit_order(Side::Bid, 9, 50, 2, 2);
+        order_book.submit(Order::new(3, 2, Side::Bid, 12, 50).with_stop(11));
+
+        assert_eq!(order_book.check_invariants(), Ok(()));
+
+        // A resting order missing from the index
+        order_book.orders.remove(&2);
+        assert!(order_book.check_invariants().is_err());
+
+        // Orders skipped for their minimum can only cross the book when trading
+        let all_or_none = Order::new(4, 2, Side::Bid, 10, 500).with_all_or_none();
+        for mode in [MatchingMode::Reject, MatchingMode::Trade] {
+            let mut order_book = OrderBook::new().with_mode(mode);
+            order_book.submit_order(Side::Ask, 10, 100, 1, 1);
+            order_book.append(all_or_none);
+
+            let crossed = order_book.check_invariants().is_err();
+            assert_eq!(crossed, mode == MatchingMode::Reject);
+
+            // But not when the other side of the cross is an order without one
+            order_book.append(Order::new(5, 2, Side::Bid, 10, 50));
+            assert!(order_book.check_invariants().is_err());
+        }
     }
 }
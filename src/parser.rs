@@ -0,0 +1,908 @@
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use csv::{Reader, ReaderBuilder, StringRecord, StringRecordsIntoIter, Trim};
+
+use crate::auction::Clearing;
+use crate::command::{Command, FlushPolicy};
+use crate::engine::Event;
+use crate::order::{Order, PegReference, PostOnly, Side, Trail};
+use crate::order_book::{OrderOutcome, RejectReason, Top, Trade};
+
+/// Why a record of the input file couldn't be turned into a command: a field
+/// is missing or has an invalid value, the command letter is unknown, an order
+/// has an unknown attribute or the record itself can't be read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    MissingField { field: &'static str },
+    InvalidField { field: &'static str, value: String },
+    UnknownCommand { command: String },
+    UnknownAttribute { attribute: String },
+    Malformed { message: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingField { field } => write!(f, "Missing {field}"),
+            Self::InvalidField { field, value } => write!(f, "Invalid {field} {value}"),
+            Self::UnknownCommand { command } => write!(f, "Unknown command {command}"),
+            Self::UnknownAttribute { attribute } => write!(f, "Unknown attribute {attribute}"),
+            Self::Malformed { message } => write!(f, "Malformed record: {message}"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// A parse error along with where it happened: the file name and the line
+/// number, starting from one. The user and order IDs of the record are there
+/// as well, when they could be read, so that the order can be rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordError {
+    pub file: String,
+    pub line: u64,
+    pub error: ParseError,
+    pub order: Option<(usize, usize)>,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.error)
+    }
+}
+
+impl Error for RecordError {}
+
+impl RecordError {
+    /// The outcome rejecting the order of the record, when its IDs could be
+    /// read.
+    #[must_use]
+    pub fn outcome(&self) -> Option<OrderOutcome> {
+        let (user_id, order_id) = self.order?;
+        let reason = RejectReason::Malformed;
+
+        Some(OrderOutcome::Rejected { user_id, order_id, reason })
+    }
+}
+
+/// What to do with a record that can't be parsed: stop processing the input,
+/// skip the record after reporting it, or report it and reject its order, as
+/// if the book had refused it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorPolicy {
+    Abort,
+    Skip,
+    Reject,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        Self::Abort
+    }
+}
+
+/// Iterates over the commands of an input source, each parse error comes with
+/// its location. Reading goes on after an error, it's up to the caller to
+/// stop.
+pub struct CommandReader<R> {
+    file: String,
+    records: StringRecordsIntoIter<R>,
+    line: u64,
+}
+
+impl<R: Read> CommandReader<R> {
+    /// Read the commands of the provided source, the file name is only used to
+    /// locate the errors.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::command::Command;
+    /// use orderbook::parser::{CommandReader, ParseError};
+    ///
+    /// let input = "# comment\nK, 930\n\n# comment\nN, 1, IBM, 10, 100, X, 1\n";
+    /// let mut reader = CommandReader::new("input.csv", input.as_bytes());
+    ///
+    /// assert_eq!(reader.next(), Some(Ok(Command::Clock { time: 930 })));
+    /// assert_eq!(reader.line(), 2);
+    ///
+    /// let error = reader.next().unwrap().unwrap_err();
+    /// assert_eq!(error.to_string(), "input.csv:5: Invalid side X");
+    /// assert_eq!(error.order, Some((1, 1)));
+    /// ```
+    pub fn new(file: &str, reader: R) -> Self {
+        Self {
+            file: file.to_string(),
+            records: csv_reader(reader).into_records(),
+            line: 0,
+        }
+    }
+
+    /// The line of the last record read, zero before the first one.
+    #[must_use]
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl<R: Read> Iterator for CommandReader<R> {
+    type Item = Result<Command, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let file = self.file.clone();
+
+        let command = match self.records.next()? {
+            Ok(record) => {
+                // Records are located where the reader started looking for
+                // them, before any comment or blank line, while the reader
+                // stops on the line after them unless the input is over
+                let start = record.position().map_or(0, csv::Position::line);
+                self.line = start.max(self.records.reader().position().line() - 1);
+
+                parse_record(&record).map_err(|error| RecordError {
+                    file,
+                    line: self.line,
+                    error,
+                    order: parse_ids(&record),
+                })
+            }
+            Err(error) => Err(RecordError {
+                file,
+                line: error.position().map_or(0, csv::Position::line),
+                error: ParseError::Malformed { message: error.to_string() },
+                order: None,
+            }),
+        };
+
+        Some(command)
+    }
+}
+
+/// Read the user and order IDs of a new order or a cancel record, if they're
+/// there.
+fn parse_ids(record: &StringRecord) -> Option<(usize, usize)> {
+    let order = match record.get(0)? {
+        "N" => 6,
+        "C" => 2,
+        _ => return None,
+    };
+
+    Some((
+        record.get(1)?.parse().ok()?,
+        record.get(order)?.parse().ok()?,
+    ))
+}
+
+/// Build a CSV reader for the input format: fields are trimmed, records can
+/// have any number of fields, there's no header and lines starting with `#`
+/// are comments.
+pub fn csv_reader<R: Read>(reader: R) -> Reader<R> {
+    ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .has_headers(false)
+        .comment(Some(b'#'))
+        .from_reader(reader)
+}
+
+/// Parse a CSV string record to build an in-memory command representation.
+///
+/// # Errors
+/// Return the first problem found in the record.
+///
+/// # Example
+/// ```
+/// use csv::StringRecord;
+/// use orderbook::command::Command;
+/// use orderbook::parser::{parse_record, ParseError};
+///
+/// let record = StringRecord::from(vec!["C", "1", "2"]);
+/// assert_eq!(parse_record(&record), Ok(Command::Cancel { user_id: 1, order_id: 2 }));
+///
+/// let record = StringRecord::from(vec!["Z"]);
+/// let command = String::from("Z");
+/// assert_eq!(parse_record(&record), Err(ParseError::UnknownCommand { command }));
+/// ```
+pub fn parse_record(record: &StringRecord) -> Result<Command, ParseError> {
+    let command = match get_field(record, 0, "command")? {
+        "N" => Command::New {
+            symbol: get_field(record, 2, "symbol")?.to_string(),
+            order: parse_order(record)?,
+        },
+        "C" => Command::Cancel {
+            user_id: parse_field(record, 1, "user")?,
+            order_id: parse_field(record, 2, "order")?,
+        },
+        "X" => parse_session(record)?,
+        "K" => Command::Clock { time: parse_field(record, 1, "time")? },
+        "F" => Command::Flush { policy: parse_flush_policy(record.get(1))? },
+        command => return Err(ParseError::UnknownCommand { command: command.to_string() }),
+    };
+
+    Ok(command)
+}
+
+/// Get a field of a record, the name describes it when it's missing.
+fn get_field<'a>(
+    record: &'a StringRecord,
+    pos: usize,
+    field: &'static str,
+) -> Result<&'a str, ParseError> {
+    record.get(pos).ok_or(ParseError::MissingField { field })
+}
+
+/// Parse a field of a record, the name describes it when it's missing or it
+/// has an invalid value.
+fn parse_field<T: FromStr>(
+    record: &StringRecord,
+    pos: usize,
+    field: &'static str,
+) -> Result<T, ParseError> {
+    parse_value(get_field(record, pos, field)?, field)
+}
+
+/// Parse a value, the name describes it when it's invalid.
+fn parse_value<T: FromStr>(value: &str, field: &'static str) -> Result<T, ParseError> {
+    value.parse().map_err(|_| invalid(field, value))
+}
+
+/// Build the error of an invalid value.
+fn invalid(field: &'static str, value: &str) -> ParseError {
+    ParseError::InvalidField { field, value: value.to_string() }
+}
+
+/// Build a session command, it moves the book of a symbol to a session right
+/// away or, when a time is provided, once the clock gets to that time.
+fn parse_session(record: &StringRecord) -> Result<Command, ParseError> {
+    let symbol = get_field(record, 1, "symbol")?.to_string();
+    let session = parse_field(record, 2, "session")?;
+    let time = record
+        .get(3)
+        .map(|time| parse_value(time, "time"))
+        .transpose()?;
+
+    Ok(Command::Session { symbol, session, time })
+}
+
+/// Converts a flush argument to its flush policy, a bare flush resets the
+/// order books.
+fn parse_flush_policy(csv_policy: Option<&str>) -> Result<FlushPolicy, ParseError> {
+    match csv_policy {
+        None | Some("reset") => Ok(FlushPolicy::Reset),
+        Some("close") => Ok(FlushPolicy::Close),
+        Some(policy) => Err(invalid("flush policy", policy)),
+    }
+}
+
+/// Build the order of a new order command. The mandatory fields can be
+/// followed by optional `key=value` attributes, the `market` flag makes it a
+/// market order whose price is ignored, `display` turns the order into an
+/// iceberg showing that much quantity at a time and `post` makes it a
+/// post-only order that either gets rejected (`reject`) or repriced (`slide`)
+/// when it would cross the book, while `stop` makes it a conditional order and
+/// `trail` a trailing stop, either by a fixed offset or by a percentage (`5%`).
+/// Then `peg` pegs the order to the `primary`, `market` or `midpoint` price
+/// with an optional offset (`primary-1`). Finally `min` sets a minimum quantity
+/// for each execution and the `aon` flag makes the order all-or-none.
+fn parse_order(record: &StringRecord) -> Result<Order, ParseError> {
+    let mut order = Order::new(
+        parse_field(record, 6, "order")?,
+        parse_field(record, 1, "user")?,
+        parse_side_from_csv(get_field(record, 5, "side")?)?,
+        parse_field(record, 3, "price")?,
+        parse_field(record, 4, "quantity")?,
+    );
+
+    for attribute in record.iter().skip(7) {
+        // Flags are attributes without a value
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+
+        order = match key.trim() {
+            "market" if value.is_empty() => order.with_market(),
+            "display" => order.with_display(parse_value(value, "display")?),
+            "post" => order.with_post_only(parse_post_only(value)?),
+            "stop" => order.with_stop(parse_value(value, "stop")?),
+            "trail" => order.with_trail(parse_trail(value)?),
+            "peg" => {
+                let (reference, offset) = parse_peg(value)?;
+                order.with_peg(reference, offset)
+            }
+            "min" => order.with_min_quantity(parse_value(value, "min")?),
+            "aon" if value.is_empty() => order.with_all_or_none(),
+            _ => {
+                let attribute = attribute.to_string();
+                return Err(ParseError::UnknownAttribute { attribute });
+            }
+        };
+    }
+
+    Ok(order)
+}
+
+/// Converts a post-only behaviour to its in-memory representation.
+pub(crate) fn parse_post_only(csv_post: &str) -> Result<PostOnly, ParseError> {
+    match csv_post {
+        "reject" => Ok(PostOnly::Reject),
+        "slide" => Ok(PostOnly::Slide),
+        _ => Err(invalid("post", csv_post)),
+    }
+}
+
+/// Converts a trailing distance to its in-memory representation.
+pub(crate) fn parse_trail(csv_trail: &str) -> Result<Trail, ParseError> {
+    match csv_trail.strip_suffix('%') {
+        Some(percent) => Ok(Trail::Percent(parse_value(percent.trim(), "trail")?)),
+        None => Ok(Trail::Offset(parse_value(csv_trail, "trail")?)),
+    }
+}
+
+/// Converts a peg reference and its optional signed offset to their in-memory
+/// representation.
+pub(crate) fn parse_peg(csv_peg: &str) -> Result<(PegReference, i32), ParseError> {
+    let (reference, offset) = match csv_peg.find(['+', '-']) {
+        Some(pos) => (
+            &csv_peg[..pos],
+            parse_value(csv_peg[pos..].trim_start_matches('+'), "peg")?,
+        ),
+        None => (csv_peg, 0),
+    };
+
+    let reference = match reference.trim() {
+        "primary" => PegReference::Primary,
+        "market" => PegReference::Market,
+        "midpoint" => PegReference::Midpoint,
+        _ => return Err(invalid("peg", csv_peg)),
+    };
+
+    Ok((reference, offset))
+}
+
+/// Converts a side character to its in-memory Side representation.
+///
+/// # Errors
+/// Return an error for anything but `B` and `S`.
+///
+/// # Example
+/// ```
+/// use orderbook::order::Side;
+/// use orderbook::parser::parse_side_from_csv;
+///
+/// assert_eq!(parse_side_from_csv("B"), Ok(Side::Bid));
+/// assert!(parse_side_from_csv("b").is_err());
+/// ```
+pub fn parse_side_from_csv(csv_side: &str) -> Result<Side, ParseError> {
+    match csv_side {
+        "B" => Ok(Side::Bid),
+        "S" => Ok(Side::Ask),
+        _ => Err(invalid("side", csv_side)),
+    }
+}
+
+/// Converts a Side enum to a side character for output purposes.
+#[must_use]
+pub fn parse_side_to_csv(side: Side) -> &'static str {
+    if side == Side::Bid {
+        "B"
+    } else {
+        "S"
+    }
+}
+
+/// Format a command as a record of the input file, the inverse of
+/// `parse_record`: parsing the record gives the command back.
+///
+/// # Example
+/// ```
+/// use orderbook::command::Command;
+/// use orderbook::order::{Order, PegReference, Side};
+/// use orderbook::parser::format_command;
+///
+/// let order = Order::new(7, 1, Side::Bid, 0, 100)
+///     .with_display(20)
+///     .with_peg(PegReference::Primary, -1);
+/// let command = Command::New { symbol: String::from("IBM"), order };
+///
+/// assert_eq!(
+///     format_command(&command),
+///     "N, 1, IBM, 0, 100, B, 7, display=20, peg=primary-1"
+/// );
+/// ```
+#[must_use]
+pub fn format_command(command: &Command) -> String {
+    match command {
+        Command::New { symbol, order } => {
+            let (symbol, side) = (separated(symbol), parse_side_to_csv(order.side));
+            let mut fields = vec![format!(
+                "N, {},{symbol}, {}, {}, {side}, {}",
+                order.user_id,
+                order.price,
+                order.leaves(),
+                order.id
+            )];
+            fields.extend(format_attributes(order));
+
+            fields.join(", ")
+        }
+        Command::Cancel { user_id, order_id } => format!("C, {user_id}, {order_id}"),
+        Command::Session { symbol, session, time } => match (separated(symbol), time) {
+            (symbol, Some(time)) => format!("X,{symbol}, {}, {time}", session.name()),
+            (symbol, None) => format!("X,{symbol}, {}", session.name()),
+        },
+        Command::Clock { time } => format!("K, {time}"),
+        Command::Flush { policy: FlushPolicy::Reset } => String::from("F"),
+        Command::Flush { policy: FlushPolicy::Close } => String::from("F, close"),
+    }
+}
+
+/// Format a field along with the space after its comma. Fields that would
+/// otherwise be split, or cut short, when read back are quoted instead, and
+/// quotes have to come right after the comma.
+pub(crate) fn separated(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        format!(" {field}")
+    }
+}
+
+/// Format the optional attributes of an order as `key=value` fields, the way
+/// `parse_record` reads them.
+pub(crate) fn format_attributes(order: &Order) -> Vec<String> {
+    let mut fields = Vec::new();
+
+    if order.market {
+        fields.push(String::from("market"));
+    }
+    if let Some(display) = order.display {
+        fields.push(format!("display={display}"));
+    }
+    if let Some(post_only) = order.post_only {
+        let post_only = match post_only {
+            PostOnly::Reject => "reject",
+            PostOnly::Slide => "slide",
+        };
+        fields.push(format!("post={post_only}"));
+    }
+    if let Some(stop) = order.stop {
+        fields.push(format!("stop={stop}"));
+    }
+    match order.trail {
+        Some(Trail::Offset(offset)) => fields.push(format!("trail={offset}")),
+        Some(Trail::Percent(percent)) => fields.push(format!("trail={percent}%")),
+        None => {}
+    }
+    if let Some(peg) = order.peg {
+        let reference = match peg.reference {
+            PegReference::Primary => "primary",
+            PegReference::Market => "market",
+            PegReference::Midpoint => "midpoint",
+        };
+        fields.push(match peg.offset {
+            0 => format!("peg={reference}"),
+            offset => format!("peg={reference}{offset:+}"),
+        });
+    }
+    if let Some(min_quantity) = order.min_quantity {
+        fields.push(format!("min={min_quantity}"));
+    }
+    if order.all_or_none {
+        fields.push(String::from("aon"));
+    }
+
+    fields
+}
+
+/// Format an outcome as the lines of the output file.
+///
+/// # Example
+/// ```
+/// use orderbook::order::Side;
+/// use orderbook::parser::format_outcome;
+/// use orderbook::OrderOutcome;
+///
+/// let outcome = OrderOutcome::TopOfBook {
+///     user_id: 1,
+///     order_id: 2,
+///     side: Side::Ask,
+///     top_price: Some(10),
+///     volume: None,
+/// };
+///
+/// assert_eq!(format_outcome(&outcome), vec!["A, 1, 2", "B, S, 10, -"]);
+/// ```
+#[must_use]
+pub fn format_outcome(outcome: &OrderOutcome) -> Vec<String> {
+    match outcome {
+        OrderOutcome::Created { user_id, order_id } => {
+            vec![format!("A, {user_id}, {order_id}")]
+        }
+        OrderOutcome::Cancelled { user_id, order_id, quantity } => {
+            vec![format!("C, {user_id}, {order_id}, {quantity}")]
+        }
+        OrderOutcome::TopOfBook { user_id, order_id, side, top_price, volume } => {
+            let top = Top { side: *side, price: *top_price, volume: *volume };
+            vec![format!("A, {user_id}, {order_id}"), format_top(&top)]
+        }
+        OrderOutcome::Rejected { user_id, order_id, .. } => {
+            vec![format!("R, {user_id}, {order_id}")]
+        }
+        OrderOutcome::Triggered { user_id, order_id } => {
+            vec![format!("S, {user_id}, {order_id}")]
+        }
+        OrderOutcome::Amended { user_id, order_id, price, stop } => {
+            let stop = stop.map_or(String::from("-"), |s| s.to_string());
+            vec![format!("M, {user_id}, {order_id}, {price}, {stop}")]
+        }
+        OrderOutcome::Traded { user_id, order_id, trades, tops } => {
+            let mut lines = vec![format!("A, {user_id}, {order_id}")];
+            lines.extend(format_trades(trades, tops));
+            lines
+        }
+        OrderOutcome::Transitioned { from, to } => {
+            let (from, to) = (from.name(), to.name());
+            vec![format!("X, {from}, {to}")]
+        }
+        OrderOutcome::Indicative { clearing } => match clearing {
+            Some(Clearing { price, volume, imbalance, side }) => {
+                let side = side.map_or("-", parse_side_to_csv);
+                vec![format!("I, {price}, {volume}, {side}, {imbalance}")]
+            }
+            None => vec![String::from("I, -, -, -, -")],
+        },
+        OrderOutcome::Uncrossed { price, trades, tops } => {
+            let mut lines = vec![format!("U, {price}")];
+            lines.extend(format_trades(trades, tops));
+            lines
+        }
+    }
+}
+
+/// Format the executions of an outcome, followed by the top of the book
+/// changes they caused.
+fn format_trades(trades: &[Trade], tops: &[Top]) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for trade in trades {
+        let Trade {
+            user_id_buy,
+            order_id_buy,
+            user_id_sell,
+            order_id_sell,
+            price,
+            quantity,
+        } = trade;
+        lines.push(format!(
+            "T, {user_id_buy}, {order_id_buy}, {user_id_sell}, {order_id_sell}, {price}, {quantity}"
+        ));
+    }
+
+    lines.extend(tops.iter().map(format_top));
+    lines
+}
+
+/// Format a top of the book change, the price and the volume are `-` when the
+/// side has been emptied.
+fn format_top(top: &Top) -> String {
+    let side = parse_side_to_csv(top.side);
+    let top_price = top.price.map_or(String::from("-"), |p| p.to_string());
+    let volume = top.volume.map_or(String::from("-"), |v| v.to_string());
+    format!("B, {side}, {top_price}, {volume}")
+}
+
+/// Format an event as a single line JSON object, the `event` field tells the
+/// kind of outcome and the other fields follow the output file. Unavailable
+/// values are `null`.
+///
+/// # Example
+/// ```
+/// use orderbook::engine::Event;
+/// use orderbook::parser::format_event_json;
+/// use orderbook::{OrderOutcome, RejectReason};
+///
+/// let outcome = OrderOutcome::Rejected {
+///     user_id: 1,
+///     order_id: 2,
+///     reason: RejectReason::Crossing,
+/// };
+/// let event = Event { symbol: String::from("IBM"), outcome };
+///
+/// assert_eq!(
+///     format_event_json(&event),
+///     r#"{"symbol":"IBM","event":"reject","user":1,"order":2,"reason":"crossing"}"#
+/// );
+/// ```
+#[must_use]
+pub fn format_event_json(event: &Event) -> String {
+    let fields = match &event.outcome {
+        OrderOutcome::Created { user_id, order_id } => {
+            format!(r#""event":"ack","user":{user_id},"order":{order_id}"#)
+        }
+        OrderOutcome::Cancelled { user_id, order_id, quantity } => {
+            format!(r#""event":"cancel","user":{user_id},"order":{order_id},"quantity":{quantity}"#)
+        }
+        OrderOutcome::TopOfBook { user_id, order_id, side, top_price, volume } => {
+            let top = Top { side: *side, price: *top_price, volume: *volume };
+            let top = format_top_json(&top);
+            format!(r#""event":"ack","user":{user_id},"order":{order_id},"tops":[{top}]"#)
+        }
+        OrderOutcome::Rejected { user_id, order_id, reason } => {
+            let reason = reason.name();
+            format!(r#""event":"reject","user":{user_id},"order":{order_id},"reason":"{reason}""#)
+        }
+        OrderOutcome::Triggered { user_id, order_id } => {
+            format!(r#""event":"trigger","user":{user_id},"order":{order_id}"#)
+        }
+        OrderOutcome::Amended { user_id, order_id, price, stop } => {
+            let stop = json_number(*stop);
+            format!(
+                r#""event":"amend","user":{user_id},"order":{order_id},"price":{price},"stop":{stop}"#
+            )
+        }
+        OrderOutcome::Traded { user_id, order_id, trades, tops } => {
+            let trades = format_trades_json(trades, tops);
+            format!(r#""event":"ack","user":{user_id},"order":{order_id},{trades}"#)
+        }
+        OrderOutcome::Transitioned { from, to } => {
+            let (from, to) = (from.name(), to.name());
+            format!(r#""event":"session","from":"{from}","to":"{to}""#)
+        }
+        OrderOutcome::Indicative { clearing } => {
+            let price = json_number(clearing.map(|c| c.price));
+            let volume = json_number(clearing.map(|c| c.volume));
+            let imbalance = json_number(clearing.map(|c| c.imbalance));
+            let side = match clearing.and_then(|c| c.side) {
+                Some(side) => format!(r#""{}""#, parse_side_to_csv(side)),
+                None => String::from("null"),
+            };
+            format!(
+                r#""event":"indicative","price":{price},"volume":{volume},"side":{side},"imbalance":{imbalance}"#
+            )
+        }
+        OrderOutcome::Uncrossed { price, trades, tops } => {
+            let trades = format_trades_json(trades, tops);
+            format!(r#""event":"uncross","price":{price},{trades}"#)
+        }
+    };
+
+    let symbol = event.symbol.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(r#"{{"symbol":"{symbol}",{fields}}}"#)
+}
+
+/// Format the executions of an outcome and the top of the book changes they
+/// caused as two JSON fields.
+fn format_trades_json(trades: &[Trade], tops: &[Top]) -> String {
+    let trades: Vec<String> = trades
+        .iter()
+        .map(|trade| {
+            let Trade {
+                user_id_buy,
+                order_id_buy,
+                user_id_sell,
+                order_id_sell,
+                price,
+                quantity,
+            } = trade;
+            format!(
+                r#"{{"buy_user":{user_id_buy},"buy_order":{order_id_buy},"sell_user":{user_id_sell},"sell_order":{order_id_sell},"price":{price},"quantity":{quantity}}}"#
+            )
+        })
+        .collect();
+    let tops: Vec<String> = tops.iter().map(format_top_json).collect();
+
+    format!(
+        r#""trades":[{}],"tops":[{}]"#,
+        trades.join(","),
+        tops.join(",")
+    )
+}
+
+/// Format a top of the book change as a JSON object.
+fn format_top_json(top: &Top) -> String {
+    let side = parse_side_to_csv(top.side);
+    let (price, volume) = (json_number(top.price), json_number(top.volume));
+    format!(r#"{{"side":"{side}","price":{price},"volume":{volume}}}"#)
+}
+
+/// Format an optional number as JSON.
+fn json_number<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::from("null"), |value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::Session;
+
+    use super::*;
+
+    fn record(fields: &str) -> StringRecord {
+        csv_reader(fields.as_bytes())
+            .records()
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_order() {
+        let command = parse_record(&record("N, 1, IBM, 10, 100, B, 1, display=20, post=slide"));
+        let order = Order::new(1, 1, Side::Bid, 10, 100)
+            .with_display(20)
+            .with_post_only(PostOnly::Slide);
+
+        assert_eq!(
+            command,
+            Ok(Command::New { symbol: String::from("IBM"), order })
+        );
+
+        let command = parse_record(&record("N, 1, IBM, 0, 100, S, 2, peg=market-1, aon"));
+        let order = Order::new(2, 1, Side::Ask, 0, 100)
+            .with_peg(PegReference::Market, -1)
+            .with_all_or_none();
+
+        assert_eq!(
+            command,
+            Ok(Command::New { symbol: String::from("IBM"), order })
+        );
+
+        let command = parse_record(&record("N, 1, IBM, 0, 100, B, 3, market, stop=12"));
+        let order = Order::new(3, 1, Side::Bid, 0, 100)
+            .with_market()
+            .with_stop(12);
+
+        assert_eq!(
+            command,
+            Ok(Command::New { symbol: String::from("IBM"), order })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let invalid =
+            |field, value: &str| ParseError::InvalidField { field, value: value.to_string() };
+
+        assert_eq!(
+            parse_record(&record("N, 1, IBM, 10, 100, X, 1")),
+            Err(invalid("side", "X"))
+        );
+        assert_eq!(
+            parse_record(&record("N, 1, IBM, ten, 100, B, 1")),
+            Err(invalid("price", "ten"))
+        );
+        assert_eq!(
+            parse_record(&record("N, 1, IBM, 10, 100, B")),
+            Err(ParseError::MissingField { field: "order" })
+        );
+        assert_eq!(
+            parse_record(&record("N, 1, IBM, 10, 100, B, 1, color=red")),
+            Err(ParseError::UnknownAttribute { attribute: String::from("color=red") })
+        );
+        assert_eq!(
+            parse_record(&record("F, later")),
+            Err(invalid("flush policy", "later"))
+        );
+        assert_eq!(
+            parse_record(&record("X, IBM, lunch")),
+            Err(invalid("session", "lunch"))
+        );
+    }
+
+    #[test]
+    fn test_parse_session_and_clock() {
+        let session = Command::Session {
+            symbol: String::from("IBM"),
+            session: Session::OpeningAuction,
+            time: Some(900),
+        };
+
+        assert_eq!(parse_record(&record("X, IBM, opening, 900")), Ok(session));
+        assert_eq!(
+            parse_record(&record("K, 930")),
+            Ok(Command::Clock { time: 930 })
+        );
+    }
+
+    #[test]
+    fn test_format_command() {
+        let records = [
+            "N, 1, IBM, 10, 100, S, 1, display=20, post=slide, min=30",
+            "N, 2, IBM, 0, 50, B, 2, market, stop=12, trail=5%",
+            "N, 3, IBM, 0, 50, S, 3, peg=midpoint+2, aon",
+            "C, 1, 1",
+            "X, IBM, closing, 1600",
+            "X, IBM, halted",
+            "X,\"A, \"\"B\"\"\", halted",
+            "K, 930",
+            "F, close",
+            "F",
+        ];
+
+        // Formatting a parsed record gives the record back
+        for &fields in records.iter() {
+            let command = parse_record(&record(fields)).unwrap();
+            assert_eq!(format_command(&command), fields);
+        }
+    }
+
+    #[test]
+    fn test_command_reader_errors() {
+        let input = "N, 1, IBM, 10, 100, B, 1\nC, 1\nC, 1, 1\nQ\n".as_bytes();
+        let results: Vec<_> = CommandReader::new("input.csv", input).collect();
+
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok() && results[2].is_ok());
+
+        let error = results[1].as_ref().unwrap_err();
+        assert_eq!((error.line, error.outcome()), (2, None));
+        assert_eq!(error.error, ParseError::MissingField { field: "order" });
+
+        let error = results[3].as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "input.csv:4: Unknown command Q");
+
+        // Invalid UTF-8 can't even be read as a record
+        let results: Vec<_> = CommandReader::new("input.csv", &b"K, \xff\n"[..]).collect();
+        assert!(matches!(
+            results[0],
+            Err(RecordError { error: ParseError::Malformed { .. }, .. })
+        ));
+    }
+
+    #[test]
+    fn test_record_error_outcome() {
+        let input = "N, 2, IBM, 10, ten, B, 7\n".as_bytes();
+        let error = CommandReader::new("input.csv", input)
+            .next()
+            .unwrap()
+            .unwrap_err();
+        let reason = RejectReason::Malformed;
+
+        assert_eq!(
+            error.outcome(),
+            Some(OrderOutcome::Rejected { user_id: 2, order_id: 7, reason })
+        );
+    }
+
+    #[test]
+    fn test_format_event_json() {
+        let trade = Trade {
+            user_id_buy: 2,
+            order_id_buy: 3,
+            user_id_sell: 1,
+            order_id_sell: 1,
+            price: 10,
+            quantity: 100,
+        };
+        let top = Top { side: Side::Ask, price: None, volume: None };
+        let outcome = OrderOutcome::Traded {
+            user_id: 2,
+            order_id: 3,
+            trades: vec![trade],
+            tops: vec![top],
+        };
+        let event = Event { symbol: String::from("A\"B"), outcome };
+
+        assert_eq!(
+            format_event_json(&event),
+            concat!(
+                r#"{"symbol":"A\"B","event":"ack","user":2,"order":3,"trades":[{"buy_user":2,"#,
+                r#""buy_order":3,"sell_user":1,"sell_order":1,"price":10,"quantity":100}],"#,
+                r#""tops":[{"side":"S","price":null,"volume":null}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_cancelled() {
+        let outcome = OrderOutcome::Cancelled { user_id: 1, order_id: 2, quantity: 30 };
+
+        assert_eq!(format_outcome(&outcome), vec!["C, 1, 2, 30"]);
+
+        let event = Event { symbol: String::from("IBM"), outcome };
+        assert_eq!(
+            format_event_json(&event),
+            r#"{"symbol":"IBM","event":"cancel","user":1,"order":2,"quantity":30}"#
+        );
+    }
+}
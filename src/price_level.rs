@@ -1,9 +1,27 @@
 use std::collections::VecDeque;
 
+use crate::allocation::Allocation;
 use crate::order::Order;
 
+/// A single execution against a resting order of a price level, it holds the
+/// order as it was right before the execution along with the traded quantity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fill {
+    pub order: Order,
+    pub quantity: u32,
+}
+
+impl Fill {
+    /// Whether the execution consumed the whole resting order, hidden quantity
+    /// included.
+    #[must_use]
+    pub const fn is_complete(&self) -> bool {
+        self.quantity == self.order.leaves()
+    }
+}
+
 /// A interface for a queue containing every order at a specific price level.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct PriceLevel {
     pub volume: u32,
     pub price: u32,
@@ -17,7 +35,8 @@ impl PriceLevel {
     }
 
     /// Appends an element to the back of the queue and updates the volume
-    /// accordingly. This method has *O*(1) complexity.
+    /// accordingly, only the shown quantity of an order counts toward the
+    /// volume. This method has *O*(1) complexity.
     ///
     /// # Example
     /// ```
@@ -39,8 +58,9 @@ impl PriceLevel {
     }
 
     /// Removes and order from the queue, and update the volume accordingly.
-    /// This method has *O*(*n*) complexity, where *n* is the length of the
-    /// queue.
+    /// Orders are matched by ID, so the provided copy doesn't need to be up to
+    /// date with the executions. This method has *O*(*n*) complexity, where
+    /// *n* is the length of the queue.
     ///
     /// # Example
     /// ```
@@ -57,15 +77,11 @@ impl PriceLevel {
     /// assert!(price_level.is_empty());
     /// ```
     pub fn remove(&mut self, order: Order) -> Option<Order> {
-        self.volume -= order.quantity;
-
-        let pos = self.orders.iter().position(|&o| o == order);
+        let pos = self.orders.iter().position(|o| o.id == order.id)?;
+        let removed = self.orders.remove(pos)?;
+        self.volume -= removed.quantity;
 
-        if let Some(pos) = pos {
-            self.orders.remove(pos)
-        } else {
-            None
-        }
+        Some(removed)
     }
 
     /// The length of the price level is defined as the length of its internal
@@ -88,12 +104,30 @@ impl PriceLevel {
         self.orders.front()
     }
 
-    /// Search for an exact quantity in the queue and remove the matching order
-    /// by means of the `remove` method. This method has *O*(*n*) complexity,
-    /// where *n* is the length of the queue.
+    /// Returns an iterator over the queue, in time priority.
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.orders.iter()
+    }
+
+    /// Returns the order with the provided ID, if it is in the queue. This
+    /// method has *O*(*n*) complexity, where *n* is the length of the queue.
+    #[must_use]
+    pub fn get(&self, order_id: usize) -> Option<&Order> {
+        self.orders.iter().find(|o| o.id == order_id)
+    }
+
+    /// Execute up to `quantity` against the queue following the provided
+    /// allocation policy, and return the resulting fills. When the shown slice
+    /// of an iceberg order is consumed a new one is revealed at the back of the
+    /// queue, losing its time priority. Orders with a minimum quantity, or
+    /// all-or-none orders, are skipped when the quantity allocated to them
+    /// can't satisfy them, they keep their place in the queue and the other
+    /// orders are matched instead. This method has *O*(*n*) complexity, where
+    /// *n* is the length of the queue, for each round of icebergs revealed.
     ///
     /// # Example
     /// ```
+    /// use orderbook::allocation::Allocation;
     /// use orderbook::price_level::PriceLevel;
     /// use orderbook::order::{Order, Side};
     ///
@@ -101,25 +135,144 @@ impl PriceLevel {
     /// let order = Order::new(1, 1, Side::Ask, 10, 100);
     ///
     /// price_level.append(order);
-    /// price_level.trade(100);
+    /// price_level.trade(100, Allocation::Fifo);
     ///
     /// assert_eq!(price_level.volume, 0);
     /// assert!(price_level.is_empty());
     /// ```
-    pub fn trade(&mut self, quantity: u32) -> Option<Order> {
+    pub fn trade(&mut self, quantity: u32, allocation: Allocation) -> Vec<Fill> {
+        if allocation == Allocation::Fifo {
+            return self.trade_fifo(quantity);
+        }
+
+        let mut fills = Vec::new();
+        let mut remaining = quantity;
+
+        // Each round allocates the shown quantities, revealed iceberg slices
+        // take part in the next one
+        while remaining > 0 {
+            let allocations = allocation.allocate(&self.orders, remaining);
+            let executed: u32 = allocations.iter().sum();
+
+            if executed == 0 {
+                break;
+            }
+
+            for (order, &allocated) in self.orders.iter_mut().zip(&allocations) {
+                if allocated > 0 {
+                    fills.push(Fill { order: *order, quantity: allocated });
+                    order.fill(allocated);
+                }
+            }
+
+            self.volume -= executed;
+            remaining -= executed;
+
+            // Drop the consumed orders, keeping the icebergs to be revealed
+            let mut revealed = Vec::new();
+            self.orders.retain(|order| {
+                let mut order = *order;
+
+                if order.quantity > 0 {
+                    return true;
+                }
+
+                if order.replenish() {
+                    revealed.push(order);
+                }
+
+                false
+            });
+
+            for order in revealed {
+                // Same order, new slice and new time priority
+                self.append(order);
+            }
+        }
+
+        fills
+    }
+
+    /// Execute up to `quantity` against the queue in time priority, visiting
+    /// each order only once unless it reveals a new iceberg slice.
+    fn trade_fifo(&mut self, quantity: u32) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        let mut remaining = quantity;
+        let mut pos = 0;
+
+        while remaining > 0 && pos < self.orders.len() {
+            let order = &mut self.orders[pos];
+
+            if Self::is_skipped(order, remaining) {
+                pos += 1;
+                continue;
+            }
+
+            // Only the shown quantity can be executed before the order moves
+            let executed = remaining.min(order.quantity);
+            fills.push(Fill { order: *order, quantity: executed });
+            order.fill(executed);
+            self.volume -= executed;
+            remaining -= executed;
+
+            if order.quantity > 0 {
+                // The incoming quantity is exhausted
+                break;
+            }
+
+            let mut order = self.orders.remove(pos).unwrap();
+
+            if order.replenish() {
+                // Same order, new slice and new time priority
+                self.append(order);
+            }
+        }
+
+        fills
+    }
+
+    /// Compute how much of `quantity` the `trade` method would execute with the
+    /// provided allocation policy, without changing the queue. This method has
+    /// the same complexity as `trade`.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::allocation::Allocation;
+    /// use orderbook::price_level::PriceLevel;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut price_level = PriceLevel::new(10);
+    ///
+    /// price_level.append(Order::new(1, 1, Side::Ask, 10, 100).with_all_or_none());
+    /// price_level.append(Order::new(2, 1, Side::Ask, 10, 20));
+    ///
+    /// assert_eq!(price_level.available(50, Allocation::Fifo), 20);
+    /// assert_eq!(price_level.available(150, Allocation::Fifo), 120);
+    /// ```
+    #[must_use]
+    pub fn available(&self, quantity: u32, allocation: Allocation) -> u32 {
+        // Allocations depend on the whole queue, and icebergs go to the back
+        // of it as they're replenished, simulate them on a copy
+        if allocation != Allocation::Fifo || self.orders.iter().any(|order| order.hidden > 0) {
+            let fills = self.clone().trade(quantity, allocation);
+            return fills.iter().map(|fill| fill.quantity).sum();
+        }
+
+        let mut remaining = quantity;
+
         for order in &self.orders {
-            if order.quantity == quantity {
-                // Matching order found
-                //
-                // Note: the target var declaration is required to avoid
-                // the annoying mutable borrow reservaton conflict
-                let target = *order;
-                self.remove(target);
-                return Some(target);
+            if !Self::is_skipped(order, remaining) {
+                remaining -= remaining.min(order.leaves());
             }
         }
 
-        None
+        quantity - remaining
+    }
+
+    /// Whether a resting order has to be skipped because its minimum can't be
+    /// satisfied by the quantity left to execute.
+    fn is_skipped(order: &Order, remaining: u32) -> bool {
+        remaining.min(order.leaves()) < order.min_fill()
     }
 }
 
@@ -184,6 +337,18 @@ mod tests {
         assert_eq!(price_level.len(), 0);
     }
 
+    #[test]
+    fn test_get() {
+        let price = 1;
+        let mut price_level = PriceLevel::new(price);
+        let order = Order::new(1, 1, Side::Ask, price, 1);
+
+        price_level.append(order);
+
+        assert_eq!(price_level.get(1), Some(&order));
+        assert_eq!(price_level.get(2), None);
+    }
+
     #[test]
     fn test_front() {
         let price = 1;
@@ -205,9 +370,10 @@ mod tests {
         let order = Order::new(1, 1, Side::Ask, price, 1);
         price_level.append(order);
 
-        let outcome = price_level.trade(1);
+        let fills = price_level.trade(1, Allocation::Fifo);
 
-        assert_eq!(outcome.unwrap(), order);
+        assert_eq!(fills, vec![Fill { order, quantity: 1 }]);
+        assert!(fills[0].is_complete());
     }
 
     #[test]
@@ -221,9 +387,131 @@ mod tests {
         price_level.append(first_order);
         price_level.append(second_order);
 
-        let outcome = price_level.trade(1);
+        let fills = price_level.trade(1, Allocation::Fifo);
 
-        assert_eq!(outcome.unwrap(), first_order);
+        assert_eq!(fills[0].order, first_order);
         assert_eq!(price_level.len(), 1);
     }
+
+    #[test]
+    fn test_trade_partial() {
+        let price = 1;
+        let mut price_level = PriceLevel::new(price);
+
+        let first_order = Order::new(1, 1, Side::Ask, price, 10);
+        let second_order = Order::new(2, 1, Side::Ask, price, 10);
+
+        price_level.append(first_order);
+        price_level.append(second_order);
+
+        let fills = price_level.trade(15, Allocation::Fifo);
+
+        assert_eq!(fills.len(), 2);
+        assert!(fills[0].is_complete());
+        assert!(!fills[1].is_complete());
+        assert_eq!(fills[1].quantity, 5);
+        assert_eq!(price_level.volume, 5);
+        assert_eq!(price_level.front().unwrap().quantity, 5);
+    }
+
+    #[test]
+    fn test_trade_iceberg_replenish() {
+        let price = 1;
+        let mut price_level = PriceLevel::new(price);
+
+        let iceberg = Order::new(1, 1, Side::Ask, price, 100).with_display(10);
+        let other = Order::new(2, 1, Side::Ask, price, 5);
+
+        price_level.append(iceberg);
+        price_level.append(other);
+
+        // Only the shown slice counts toward the volume
+        assert_eq!(price_level.volume, 15);
+
+        // The first slice is consumed, the replenished one goes behind `other`
+        let fills = price_level.trade(12, Allocation::Fifo);
+
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].order.id, 1);
+        assert_eq!(fills[0].quantity, 10);
+        assert_eq!(fills[1].order.id, 2);
+        assert_eq!(fills[1].quantity, 2);
+        assert_eq!(price_level.volume, 13);
+        assert_eq!(price_level.front().unwrap().id, 2);
+
+        // Fills keep going against the hidden quantity
+        let fills = price_level.trade(23, Allocation::Fifo);
+
+        assert_eq!(fills.len(), 3);
+        assert_eq!(fills.iter().map(|f| f.quantity).sum::<u32>(), 23);
+        assert_eq!(price_level.len(), 1);
+        assert_eq!(price_level.front().unwrap().hidden, 60);
+    }
+
+    #[test]
+    fn test_available_iceberg() {
+        let price = 1;
+        let mut price_level = PriceLevel::new(price);
+
+        price_level.append(Order::new(1, 1, Side::Bid, price, 48).with_display(1));
+        price_level.append(Order::new(2, 1, Side::Bid, price, 2).with_min_quantity(2));
+
+        // The minimum quantity is met once the first slice is replenished
+        assert_eq!(price_level.available(49, Allocation::Fifo), 49);
+        let fills = price_level.clone().trade(49, Allocation::Fifo);
+        assert_eq!(fills[1], Fill { order: price_level.orders[1], quantity: 2 });
+        assert_eq!(price_level.available(50, Allocation::Fifo), 50);
+    }
+
+    #[test]
+    fn test_trade_skips_min_quantity() {
+        let price = 1;
+        let mut price_level = PriceLevel::new(price);
+
+        let all_or_none = Order::new(1, 1, Side::Ask, price, 10).with_all_or_none();
+        let min_quantity = Order::new(2, 1, Side::Ask, price, 10).with_min_quantity(6);
+        let plain = Order::new(3, 1, Side::Ask, price, 10);
+
+        price_level.append(all_or_none);
+        price_level.append(min_quantity);
+        price_level.append(plain);
+
+        // Too small for both the constrained orders
+        let fills = price_level.trade(5, Allocation::Fifo);
+
+        assert_eq!(fills, vec![Fill { order: plain, quantity: 5 }]);
+        assert_eq!(price_level.front().unwrap().id, 1);
+
+        // Enough for the minimum quantity but not for the all-or-none order
+        let fills = price_level.trade(8, Allocation::Fifo);
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].order.id, 2);
+        assert_eq!(price_level.get(2).unwrap().min_fill(), 2);
+
+        // Enough for everyone, in time priority
+        let fills = price_level.trade(17, Allocation::Fifo);
+        let ids: Vec<_> = fills.iter().map(|f| f.order.id).collect();
+
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert!(price_level.is_empty());
+    }
+
+    #[test]
+    fn test_trade_pro_rata() {
+        let price = 1;
+        let mut price_level = PriceLevel::new(price);
+        let allocation = Allocation::ProRata { top_order: false, min_allocation: 0 };
+
+        price_level.append(Order::new(1, 1, Side::Ask, price, 10));
+        price_level.append(Order::new(2, 1, Side::Ask, price, 40).with_display(30));
+
+        // Each round spreads the shown quantities, the revealed slice follows
+        let fills = price_level.trade(45, allocation);
+        let quantities: Vec<_> = fills.iter().map(|f| (f.order.id, f.quantity)).collect();
+
+        assert_eq!(quantities, vec![(1, 10), (2, 30), (2, 5)]);
+        assert_eq!(price_level.volume, 5);
+        assert_eq!(price_level.available(10, allocation), 5);
+    }
 }
@@ -0,0 +1,301 @@
+use crate::order::{Order, Side};
+use crate::order_book::{MatchingMode, OrderOutcome, RejectReason, Top, Trade};
+
+/// A deliberately naive order book, to check `OrderBook` against. Resting
+/// orders are kept in a single vector in arrival order, and every call sorts
+/// them again to find the best ones, so that there's no index nor tree to get
+/// wrong. Only limit and market orders are supported, during continuous
+/// trading and in time priority, and they produce the same outcomes they
+/// would on an `OrderBook`.
+#[derive(Default)]
+pub struct ReferenceBook {
+    mode: MatchingMode,
+    orders: Vec<Order>,
+}
+
+impl ReferenceBook {
+    #[must_use]
+    pub fn new() -> Self {
+        Self { mode: MatchingMode::Reject, orders: Vec::new() }
+    }
+
+    /// Use the provided matching mode, instead of rejecting crossing orders.
+    #[must_use]
+    pub fn with_mode(mut self, mode: MatchingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Whether an order is resting on the book.
+    #[must_use]
+    pub fn contains(&self, order_id: usize) -> bool {
+        self.orders.iter().any(|order| order.id == order_id)
+    }
+
+    /// Return the resting orders of a side in ascending price order, and then
+    /// in time priority, the same way as `BookSide::orders`. The complexity for
+    /// this operation is *O*(*k* log *k*), where *k* is the number of orders.
+    #[must_use]
+    pub fn orders(&self, side: Side) -> Vec<Order> {
+        let mut orders: Vec<Order> = self
+            .orders
+            .iter()
+            .filter(|order| order.side == side)
+            .copied()
+            .collect();
+
+        // Sorting is stable, orders at the same price keep their arrival order
+        orders.sort_by_key(|order| order.price);
+        orders
+    }
+
+    /// Match an order against the opposite side, from its best price and in
+    /// time priority, and rest whatever is left of a limit order. The
+    /// complexity for this operation is *O*(*t* *k* log *k*), where *t* is the
+    /// number of trades and *k* is the number of orders.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::order::{Order, Side};
+    /// use orderbook::order_book::{MatchingMode, OrderBook};
+    /// use orderbook::reference::ReferenceBook;
+    ///
+    /// let mut reference = ReferenceBook::new().with_mode(MatchingMode::Trade);
+    /// let mut order_book = OrderBook::new().with_mode(MatchingMode::Trade);
+    ///
+    /// for order in [
+    ///     Order::new(1, 1, Side::Ask, 10, 100),
+    ///     Order::new(2, 2, Side::Bid, 11, 150),
+    /// ]
+    /// .iter()
+    /// {
+    ///     assert_eq!(reference.submit(*order), order_book.submit(*order));
+    /// }
+    ///
+    /// assert_eq!(reference.orders(Side::Bid), order_book.snapshot().bids);
+    /// ```
+    pub fn submit(&mut self, mut order: Order) -> Vec<OrderOutcome> {
+        if self.mode == MatchingMode::Reject {
+            return vec![self.rest(order)];
+        }
+
+        let side = order.side;
+        let (own_top, opp_top) = (self.top(side), self.top(!side));
+        let mut trades = Vec::new();
+
+        while order.leaves() > 0 {
+            let best = match self.queue(!side).first() {
+                Some(best) if reaches(&order, best.price) => *best,
+                _ => break,
+            };
+            let quantity = order.leaves().min(best.leaves());
+
+            order.fill(quantity);
+            let index = self.position(best.id);
+            self.orders[index].fill(quantity);
+            if self.orders[index].leaves() == 0 {
+                self.orders.remove(index);
+            }
+
+            let (buy, sell) = match side {
+                Side::Bid => (&order, &best),
+                Side::Ask => (&best, &order),
+            };
+            trades.push(Trade {
+                user_id_buy: buy.user_id,
+                order_id_buy: buy.id,
+                user_id_sell: sell.user_id,
+                order_id_sell: sell.id,
+                price: best.price,
+                quantity,
+            });
+        }
+
+        if trades.is_empty() {
+            return vec![self.rest(order)];
+        }
+
+        if order.leaves() > 0 && !order.is_market() {
+            self.orders.push(order);
+        }
+
+        let tops = [(!side, opp_top), (side, own_top)]
+            .iter()
+            .filter(|&&(side, before)| self.top(side) != before)
+            .map(|&(side, _)| {
+                let (price, volume) = self.top(side);
+                Top { side, price, volume }
+            })
+            .collect();
+
+        vec![OrderOutcome::Traded {
+            user_id: order.user_id,
+            order_id: order.id,
+            trades,
+            tops,
+        }]
+    }
+
+    /// Cancel a resting order, and return the outcomes.
+    ///
+    /// # Panics
+    /// This method assumes that the order is resting on the book, and it will
+    /// always panic if the condition is not met.
+    pub fn cancel_order(&mut self, order_id: usize) -> Vec<OrderOutcome> {
+        let order = self.orders[self.position(order_id)];
+        let (user_id, before) = (order.user_id, self.top(order.side));
+
+        self.orders.retain(|order| order.id != order_id);
+        let (top_price, volume) = self.top(order.side);
+
+        let outcome = if (top_price, volume) == before {
+            OrderOutcome::Created { user_id, order_id }
+        } else {
+            OrderOutcome::TopOfBook {
+                user_id,
+                order_id,
+                side: order.side,
+                top_price,
+                volume,
+            }
+        };
+
+        vec![outcome]
+    }
+
+    /// Append an order that didn't trade, unless it's a market order or it
+    /// would cross the book.
+    fn rest(&mut self, order: Order) -> OrderOutcome {
+        let (user_id, order_id, side) = (order.user_id, order.id, order.side);
+
+        if order.is_market() {
+            let reason = RejectReason::NoLiquidity;
+            return OrderOutcome::Rejected { user_id, order_id, reason };
+        }
+        if self
+            .queue(!side)
+            .first()
+            .map_or(false, |best| reaches(&order, best.price))
+        {
+            let reason = RejectReason::Crossing;
+            return OrderOutcome::Rejected { user_id, order_id, reason };
+        }
+
+        // The order is the new best one when nothing on its side beats it
+        let best = self
+            .queue(side)
+            .first()
+            .map_or(true, |best| reaches(&order, best.price));
+        self.orders.push(order);
+
+        if best {
+            let (top_price, volume) = self.top(side);
+            OrderOutcome::TopOfBook { user_id, order_id, side, top_price, volume }
+        } else {
+            OrderOutcome::Created { user_id, order_id }
+        }
+    }
+
+    /// The resting orders of a side in priority order, best price first.
+    fn queue(&self, side: Side) -> Vec<Order> {
+        let mut orders = self.orders(side);
+
+        if side == Side::Bid {
+            // Reversing would lose the time priority within a price
+            orders.sort_by_key(|order| std::cmp::Reverse(order.price));
+        }
+
+        orders
+    }
+
+    /// The best price of a side along with the quantity resting at it.
+    fn top(&self, side: Side) -> (Option<u32>, Option<u32>) {
+        let orders = self.orders(side);
+
+        let best = match side {
+            Side::Ask => orders.iter().map(|order| order.price).min(),
+            Side::Bid => orders.iter().map(|order| order.price).max(),
+        };
+        let volume = best.map(|best| {
+            orders
+                .iter()
+                .filter(|order| order.price == best)
+                .map(|order| order.quantity)
+                .sum()
+        });
+
+        (best, volume)
+    }
+
+    /// The index of a resting order in the vector.
+    fn position(&self, order_id: usize) -> usize {
+        self.orders
+            .iter()
+            .position(|order| order.id == order_id)
+            .unwrap()
+    }
+}
+
+/// Whether the price of an order is at or better than the provided one, for a
+/// buyer or a seller according to its side. Against the opposite side the
+/// order trades, against its own side it goes first.
+fn reaches(order: &Order, price: u32) -> bool {
+    match order.side {
+        _ if order.is_market() => true,
+        Side::Bid => order.price >= price,
+        Side::Ask => order.price <= price,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_submit() {
+        let mut book = ReferenceBook::new().with_mode(MatchingMode::Trade);
+
+        book.submit(Order::new(1, 1, Side::Ask, 11, 100));
+        book.submit(Order::new(2, 1, Side::Ask, 10, 50));
+        book.submit(Order::new(3, 1, Side::Ask, 10, 50));
+        let outcomes = book.submit(Order::new(4, 2, Side::Bid, 0, 120).with_market());
+
+        let prices: Vec<(usize, u32, u32)> = outcomes[0]
+            .trades()
+            .iter()
+            .map(|trade| (trade.order_id_sell, trade.price, trade.quantity))
+            .collect();
+
+        assert_eq!(prices, vec![(2, 10, 50), (3, 10, 50), (1, 11, 20)]);
+        assert_eq!(book.orders(Side::Ask)[0].quantity, 80);
+        assert!(book.orders(Side::Bid).is_empty());
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut book = ReferenceBook::new().with_mode(MatchingMode::Reject);
+
+        book.submit(Order::new(1, 1, Side::Bid, 10, 100));
+        book.submit(Order::new(2, 1, Side::Bid, 9, 100));
+        let outcomes = book.submit(Order::new(3, 2, Side::Ask, 10, 100));
+
+        assert!(matches!(
+            outcomes[0],
+            OrderOutcome::Rejected { reason: RejectReason::Crossing, .. }
+        ));
+        assert_eq!(
+            book.cancel_order(2),
+            vec![OrderOutcome::Created { user_id: 1, order_id: 2 }]
+        );
+        assert_eq!(
+            book.cancel_order(1),
+            vec![OrderOutcome::TopOfBook {
+                user_id: 1,
+                order_id: 1,
+                side: Side::Bid,
+                top_price: None,
+                volume: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+
+use crate::engine::MatchingEngine;
+use crate::parser::{format_outcome, CommandReader, RecordError};
+
+/// A named section of an input or expected output file, starting at its
+/// `#name:` marker and going on until the next one. The body keeps every line
+/// of the section, comments included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    pub line: u64,
+    pub body: String,
+}
+
+impl Scenario {
+    /// Run the commands of the scenario through the provided engine, and
+    /// return the output lines. Parse errors are located in the whole file
+    /// rather than in the scenario.
+    ///
+    /// # Errors
+    /// Return the first record that can't be parsed.
+    pub fn run(&self, file: &str, engine: &mut MatchingEngine) -> Result<Vec<String>, RecordError> {
+        let mut lines = Vec::new();
+
+        for command in CommandReader::new(file, self.body.as_bytes()) {
+            let command = command.map_err(|mut error| {
+                error.line += self.line;
+                error
+            })?;
+
+            for event in engine.process(command) {
+                lines.extend(format_outcome(&event.outcome));
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// The output lines listed by the scenario, without comments nor blank
+    /// lines, and with their fields separated the same way as the ones the
+    /// program writes.
+    #[must_use]
+    pub fn output(&self) -> Vec<String> {
+        self.body
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(normalize)
+            .collect()
+    }
+}
+
+/// Split a file into its scenarios, whatever comes before the first marker is
+/// ignored. Names are compared with their whitespace collapsed, so that
+/// `scenario  1` and `scenario 1` are the same scenario.
+///
+/// # Example
+/// ```
+/// use orderbook::scenario::split_scenarios;
+///
+/// let text = "# header\n#name: scenario  1\nA,1,1\n\n#name: scenario 2\nA, 1, 2\n";
+/// let scenarios = split_scenarios(text);
+///
+/// assert_eq!(scenarios[0].name, "scenario 1");
+/// assert_eq!(scenarios[0].output(), vec!["A, 1, 1"]);
+/// assert_eq!(scenarios[1].line, 5);
+/// ```
+#[must_use]
+pub fn split_scenarios(text: &str) -> Vec<Scenario> {
+    let mut scenarios: Vec<Scenario> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        match line.trim_start().strip_prefix("#name:") {
+            Some(name) => scenarios.push(Scenario {
+                name: name.split_whitespace().collect::<Vec<_>>().join(" "),
+                line: index as u64 + 1,
+                body: String::new(),
+            }),
+            None => {
+                if let Some(scenario) = scenarios.last_mut() {
+                    scenario.body.push_str(line);
+                    scenario.body.push('\n');
+                }
+            }
+        }
+    }
+
+    scenarios
+}
+
+/// A line of the difference between the expected and the actual output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffLine {
+    // Lines found in both outputs
+    Same(String),
+    // Expected lines the program didn't write
+    Missing(String),
+    // Lines the program wrote but weren't expected
+    Unexpected(String),
+}
+
+impl fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Same(line) => write!(f, "  {line}"),
+            Self::Missing(line) => write!(f, "- {line}"),
+            Self::Unexpected(line) => write!(f, "+ {line}"),
+        }
+    }
+}
+
+/// The outcome of a scenario: its expected output next to the actual one.
+/// Scenarios found in only one of the files have the other output empty.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub name: String,
+    pub expected: Vec<String>,
+    pub actual: Vec<String>,
+}
+
+impl Report {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+
+    /// The line by line difference between the expected and the actual
+    /// output, along their longest common subsequence. The complexity for
+    /// this operation is *O*(*e* *a*), where *e* and *a* are the lengths of
+    /// the expected and actual outputs.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::scenario::{DiffLine, Report};
+    ///
+    /// let report = Report {
+    ///     name: String::from("scenario 1"),
+    ///     expected: vec![String::from("A, 1, 1"), String::from("R, 1, 2")],
+    ///     actual: vec![String::from("A, 1, 1"), String::from("A, 1, 2")],
+    /// };
+    ///
+    /// assert_eq!(
+    ///     report.diff(),
+    ///     vec![
+    ///         DiffLine::Same(String::from("A, 1, 1")),
+    ///         DiffLine::Missing(String::from("R, 1, 2")),
+    ///         DiffLine::Unexpected(String::from("A, 1, 2")),
+    ///     ]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn diff(&self) -> Vec<DiffLine> {
+        let (expected, actual) = (&self.expected, &self.actual);
+
+        // Length of the common subsequence of the suffixes starting at each pair of lines
+        let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+        for i in (0..expected.len()).rev() {
+            for j in (0..actual.len()).rev() {
+                common[i][j] = if expected[i] == actual[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        let mut diff = Vec::new();
+
+        while i < expected.len() || j < actual.len() {
+            if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+                diff.push(DiffLine::Same(expected[i].clone()));
+                i += 1;
+                j += 1;
+            } else if j == actual.len()
+                || (i < expected.len() && common[i + 1][j] >= common[i][j + 1])
+            {
+                diff.push(DiffLine::Missing(expected[i].clone()));
+                i += 1;
+            } else {
+                diff.push(DiffLine::Unexpected(actual[j].clone()));
+                j += 1;
+            }
+        }
+
+        diff
+    }
+}
+
+impl fmt::Display for Report {
+    /// A single line for passed scenarios, followed by the difference for the
+    /// failed ones.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.passed() {
+            return write!(f, "{} ... ok", self.name);
+        }
+
+        write!(f, "{} ... FAILED", self.name)?;
+        for line in self.diff() {
+            write!(f, "\n    {line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run every scenario of an input file, each through a new engine, and
+/// compare its output with the scenario of the same name in the expected
+/// file. Reports follow the order of the input file, then come the expected
+/// scenarios missing from it.
+///
+/// # Errors
+/// Return the first record of the input file that can't be parsed.
+///
+/// # Example
+/// ```
+/// use orderbook::engine::MatchingEngine;
+/// use orderbook::scenario::run_scenarios;
+///
+/// let input = "#name: scenario 1\nN, 1, IBM, 10, 100, B, 1\nF\n";
+/// let expected = "#name: scenario 1\nA, 1, 1\nB, B, 10, 100\n";
+/// let reports = run_scenarios("input.csv", input, expected, MatchingEngine::new).unwrap();
+///
+/// assert!(reports[0].passed());
+/// ```
+pub fn run_scenarios<F>(
+    file: &str,
+    input: &str,
+    expected: &str,
+    mut new_engine: F,
+) -> Result<Vec<Report>, RecordError>
+where
+    F: FnMut() -> MatchingEngine,
+{
+    let mut expected = split_scenarios(expected);
+    let mut reports = Vec::new();
+
+    for scenario in split_scenarios(input) {
+        let actual = scenario.run(file, &mut new_engine())?;
+        let expected = match expected
+            .iter()
+            .position(|other| other.name == scenario.name)
+        {
+            Some(index) => expected.remove(index).output(),
+            None => Vec::new(),
+        };
+
+        reports.push(Report { name: scenario.name, expected, actual });
+    }
+
+    reports.extend(expected.into_iter().map(|scenario| Report {
+        expected: scenario.output(),
+        name: scenario.name,
+        actual: Vec::new(),
+    }));
+
+    Ok(reports)
+}
+
+/// Separate the fields of an output line with a comma and a single space.
+fn normalize(line: &str) -> String {
+    line.split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ParseError;
+
+    use super::*;
+
+    #[test]
+    fn test_run_scenarios() {
+        let input = "\
+#name: scenario 1
+N, 1, IBM, 10, 100, B, 1
+F
+
+#name: scenario 2
+N, 1, IBM, 12, 100, S, 1
+";
+        let expected = "\
+#name: scenario 2
+A,1,1
+B,S,12,100
+
+#name: scenario 1
+A, 1, 1
+B, B, 11, 100
+
+#name: scenario 3
+A, 1, 1
+";
+        let reports = run_scenarios("input.csv", input, expected, MatchingEngine::new).unwrap();
+        let names: Vec<&str> = reports.iter().map(|report| report.name.as_str()).collect();
+
+        assert_eq!(names, vec!["scenario 1", "scenario 2", "scenario 3"]);
+        assert!(!reports[0].passed());
+        assert!(reports[1].passed());
+        assert!(!reports[2].passed());
+        assert_eq!(
+            reports[0].to_string(),
+            "scenario 1 ... FAILED\n      A, 1, 1\n    - B, B, 11, 100\n    + B, B, 10, 100"
+        );
+    }
+
+    #[test]
+    fn test_run_scenarios_error() {
+        let input = "# header\n\n#name: scenario 1\nN, 1, IBM, 10, 100, B, 1\nZ\n";
+        let error = run_scenarios("input.csv", input, "", MatchingEngine::new).unwrap_err();
+
+        assert_eq!(error.line, 5);
+        assert_eq!(
+            error.error,
+            ParseError::UnknownCommand { command: String::from("Z") }
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// An event waiting for its time, along with its sequence number, which keeps
+/// the events due at the same time in the order they were scheduled.
+struct Scheduled<T> {
+    time: u64,
+    seq: u64,
+    event: T,
+}
+
+impl<T> PartialEq for Scheduled<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl<T> Eq for Scheduled<T> {}
+
+impl<T> PartialOrd for Scheduled<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scheduled<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+/// Events ordered by the simulated time they're due at, and then first in,
+/// first out. Events are kept in a binary heap, so that scheduling an event
+/// and taking the next one both take *O*(log *n*), where *n* is the number of
+/// pending events.
+pub struct Scheduler<T> {
+    events: BinaryHeap<Reverse<Scheduled<T>>>,
+    seq: u64,
+}
+
+impl<T> Default for Scheduler<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Scheduler<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self { events: BinaryHeap::new(), seq: 0 }
+    }
+
+    /// The number of pending events.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The time of the next event, if any.
+    #[must_use]
+    pub fn next_time(&self) -> Option<u64> {
+        self.events.peek().map(|Reverse(scheduled)| scheduled.time)
+    }
+
+    /// Schedule an event at the provided time, after the ones already
+    /// scheduled at the same time.
+    pub fn schedule(&mut self, time: u64, event: T) {
+        let seq = self.seq;
+        self.seq += 1;
+
+        self.events.push(Reverse(Scheduled { time, seq, event }));
+    }
+
+    /// Take the next event along with its time, as long as it's due by the
+    /// provided time.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::scheduler::Scheduler;
+    ///
+    /// let mut scheduler = Scheduler::new();
+    /// scheduler.schedule(5, "late");
+    /// scheduler.schedule(2, "first");
+    /// scheduler.schedule(2, "second");
+    ///
+    /// assert_eq!(scheduler.pop_until(4), Some((2, "first")));
+    /// assert_eq!(scheduler.pop_until(4), Some((2, "second")));
+    /// assert_eq!(scheduler.pop_until(4), None);
+    /// assert_eq!(scheduler.next_time(), Some(5));
+    /// ```
+    pub fn pop_until(&mut self, time: u64) -> Option<(u64, T)> {
+        if self.next_time()? > time {
+            return None;
+        }
+
+        self.events
+            .pop()
+            .map(|Reverse(scheduled)| (scheduled.time, scheduled.event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let mut scheduler = Scheduler::new();
+
+        for (time, event) in [(3, 'a'), (1, 'b'), (3, 'c'), (0, 'd'), (1, 'e')].iter() {
+            scheduler.schedule(*time, *event);
+        }
+        assert_eq!(scheduler.len(), 5);
+
+        let mut events = Vec::new();
+        while let Some((_, event)) = scheduler.pop_until(u64::MAX) {
+            events.push(event);
+
+            // Events scheduled while popping wait for their turn
+            if event == 'b' {
+                scheduler.schedule(1, 'f');
+            }
+        }
+
+        assert_eq!(events, vec!['d', 'b', 'e', 'f', 'a', 'c']);
+        assert!(scheduler.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::str::FromStr;
+
+/// Trading session of an order book, each state decides which operations are
+/// allowed:
+///
+/// state              | orders | cancels | matching
+/// ------------------ | ------ | ------- | -------------------
+/// pre-open           | no     | yes     | no
+/// opening auction    | yes    | yes     | at the uncross
+/// continuous         | yes    | yes     | on arrival
+/// volatility auction | yes    | yes     | at the uncross
+/// halted             | no     | yes     | no
+/// closing auction    | yes    | yes     | at the uncross
+/// closed             | no     | no      | no
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Session {
+    PreOpen,
+    OpeningAuction,
+    Continuous,
+    VolatilityAuction,
+    Halted,
+    ClosingAuction,
+    Closed,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::Continuous
+    }
+}
+
+impl Session {
+    /// The name of the session in the input and output files.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::session::Session;
+    ///
+    /// assert_eq!(Session::OpeningAuction.name(), "opening");
+    /// assert_eq!("opening".parse(), Ok(Session::OpeningAuction));
+    /// ```
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::PreOpen => "pre-open",
+            Self::OpeningAuction => "opening",
+            Self::Continuous => "continuous",
+            Self::VolatilityAuction => "volatility",
+            Self::Halted => "halted",
+            Self::ClosingAuction => "closing",
+            Self::Closed => "closed",
+        }
+    }
+
+    /// Whether new orders are accepted.
+    #[must_use]
+    pub const fn allows_orders(self) -> bool {
+        matches!(
+            self,
+            Self::OpeningAuction
+                | Self::Continuous
+                | Self::VolatilityAuction
+                | Self::ClosingAuction
+        )
+    }
+
+    /// Whether resting and pending orders can be cancelled.
+    #[must_use]
+    pub const fn allows_cancels(self) -> bool {
+        !matches!(self, Self::Closed)
+    }
+
+    /// Whether orders accumulate without matching, waiting for the uncross.
+    #[must_use]
+    pub const fn is_auction(self) -> bool {
+        matches!(
+            self,
+            Self::OpeningAuction | Self::VolatilityAuction | Self::ClosingAuction
+        )
+    }
+
+    /// Whether the session can move to the provided one. The day goes from
+    /// pre-open to closed through the opening auction, the continuous trading
+    /// and the closing auction, while halts can be resumed directly or through
+    /// a reopening auction. Volatility auctions interrupt continuous trading
+    /// and go back to it. Every session can be closed early.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::session::Session;
+    ///
+    /// assert!(Session::PreOpen.can_transition_to(Session::OpeningAuction));
+    /// assert!(Session::Halted.can_transition_to(Session::OpeningAuction));
+    /// assert!(!Session::Closed.can_transition_to(Session::Continuous));
+    /// ```
+    #[must_use]
+    pub const fn can_transition_to(self, next: Self) -> bool {
+        matches!(
+            (self, next),
+            (Self::Closed, Self::PreOpen)
+                | (Self::PreOpen, Self::OpeningAuction | Self::Continuous)
+                | (Self::OpeningAuction, Self::Continuous | Self::Halted)
+                | (
+                    Self::Continuous,
+                    Self::VolatilityAuction | Self::Halted | Self::ClosingAuction
+                )
+                | (Self::VolatilityAuction, Self::Continuous | Self::Halted)
+                | (Self::Halted, Self::OpeningAuction | Self::Continuous)
+                | (Self::ClosingAuction, Self::Halted)
+                | (
+                    Self::PreOpen
+                        | Self::OpeningAuction
+                        | Self::Continuous
+                        | Self::VolatilityAuction
+                        | Self::Halted
+                        | Self::ClosingAuction,
+                    Self::Closed
+                )
+        )
+    }
+}
+
+impl FromStr for Session {
+    type Err = String;
+
+    /// Parse a session from its name, see `name`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pre-open" => Ok(Self::PreOpen),
+            "opening" => Ok(Self::OpeningAuction),
+            "continuous" => Ok(Self::Continuous),
+            "volatility" => Ok(Self::VolatilityAuction),
+            "halted" => Ok(Self::Halted),
+            "closing" => Ok(Self::ClosingAuction),
+            "closed" => Ok(Self::Closed),
+            _ => Err(format!("Unknown session {name}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_operations() {
+        assert!(!Session::PreOpen.allows_orders());
+        assert!(Session::PreOpen.allows_cancels());
+        assert!(Session::ClosingAuction.allows_orders());
+        assert!(Session::ClosingAuction.is_auction());
+        assert!(!Session::Halted.allows_orders());
+        assert!(Session::Halted.allows_cancels());
+        assert!(!Session::Closed.allows_cancels());
+    }
+
+    #[test]
+    fn test_trading_day() {
+        let day = [
+            Session::Closed,
+            Session::PreOpen,
+            Session::OpeningAuction,
+            Session::Continuous,
+            Session::Halted,
+            Session::Continuous,
+            Session::ClosingAuction,
+            Session::Closed,
+        ];
+
+        for pair in day.windows(2) {
+            assert!(pair[0].can_transition_to(pair[1]));
+        }
+
+        assert!(Session::Continuous.can_transition_to(Session::VolatilityAuction));
+        assert!(Session::VolatilityAuction.can_transition_to(Session::Continuous));
+        assert!(!Session::Continuous.can_transition_to(Session::Continuous));
+        assert!(!Session::ClosingAuction.can_transition_to(Session::Continuous));
+        assert!(!Session::Closed.can_transition_to(Session::Closed));
+    }
+}
@@ -0,0 +1,574 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use crate::auction::Clearing;
+use crate::engine::{Event, MatchingEngine};
+use crate::journal::{fnv1a, JournalError, JournalReader};
+use crate::order::Order;
+use crate::parser::{
+    csv_reader, format_attributes, parse_peg, parse_post_only, parse_side_from_csv,
+    parse_side_to_csv, parse_trail, separated, ParseError,
+};
+use crate::session::Session;
+
+/// The state of an order book, without its configuration: the session with
+/// its schedule and clock, the last published indicative clearing, the
+/// reference and last trade prices, the pegged orders with their references,
+/// the order index and the orders of both sides, resting and pending. Orders
+/// are listed the way the book sides and the trigger books return them, so
+/// that appending them in sequence rebuilds the same queues.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookSnapshot {
+    pub session: Session,
+    pub schedule: Vec<(u64, Session)>,
+    pub now: u64,
+    pub indicative: Option<Clearing>,
+    pub reference_price: Option<u32>,
+    pub last_price: Option<u32>,
+    pub peg_references: (Option<u32>, Option<u32>),
+    pub pegged: Vec<usize>,
+    pub orders: Vec<Order>,
+    pub asks: Vec<Order>,
+    pub bids: Vec<Order>,
+    pub buy_stops: Vec<(u32, Order)>,
+    pub sell_stops: Vec<(u32, Order)>,
+}
+
+/// The state of every order book of an engine, sorted by symbol, along with
+/// the sequence number of the last journal entry it includes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub seq: u64,
+    pub books: Vec<(String, BookSnapshot)>,
+}
+
+/// Why a snapshot can't be read back: the source failed, a line can't be
+/// parsed, the snapshot was altered or it's incomplete. Lines are numbered
+/// from one.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Malformed { line: u64 },
+    Parse { line: u64, error: ParseError },
+    Checksum,
+    Truncated,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Cannot read the snapshot: {error}"),
+            Self::Malformed { line } => write!(f, "{line}: Malformed snapshot line"),
+            Self::Parse { line, error } => write!(f, "{line}: {error}"),
+            Self::Checksum => write!(f, "Wrong snapshot checksum"),
+            Self::Truncated => write!(f, "Incomplete snapshot"),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl Snapshot {
+    /// Write the snapshot, one line for each piece of state, in the same
+    /// style as the input file. A book starts with its symbol, followed by its
+    /// session, clock and prices:
+    ///
+    /// `book, symbol`
+    ///
+    /// The symbol is quoted when it holds commas or quotes, like in the input
+    /// file.
+    ///
+    /// `state, session, now, last price, reference price, peg references`
+    ///
+    /// Then come the `schedule` and `indicative` lines, the `pegged` IDs, and
+    /// the orders of the `index`, the `ask` and `bid` sides and the
+    /// `buy-stop` and `sell-stop` queues, the latter along with their stop
+    /// price. Orders are written with every field:
+    ///
+    /// `id, user, side, price, quantity, hidden, attributes`
+    ///
+    /// The first line holds the sequence number, and the last one the
+    /// checksum of the previous ones, the same hash as the journal's.
+    ///
+    /// # Errors
+    /// Return the error of the writer.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut text = format!("snapshot, {}\n", self.seq);
+
+        for (symbol, book) in &self.books {
+            let [last_price, reference_price, peg_bid, peg_ask] = [
+                book.last_price,
+                book.reference_price,
+                book.peg_references.0,
+                book.peg_references.1,
+            ]
+            .map(format_price);
+
+            text.push_str(&format!("book,{}\n", separated(symbol)));
+            text.push_str(&format!(
+                "state, {}, {}, {last_price}, {reference_price}, {peg_bid}, {peg_ask}\n",
+                book.session.name(),
+                book.now
+            ));
+
+            for (time, session) in &book.schedule {
+                text.push_str(&format!("schedule, {time}, {}\n", session.name()));
+            }
+            if let Some(Clearing { price, volume, imbalance, side }) = book.indicative {
+                let side = side.map_or("-", parse_side_to_csv);
+                text.push_str(&format!(
+                    "indicative, {price}, {volume}, {side}, {imbalance}\n"
+                ));
+            }
+            for id in &book.pegged {
+                text.push_str(&format!("pegged, {id}\n"));
+            }
+
+            let sides = [
+                ("index", &book.orders),
+                ("ask", &book.asks),
+                ("bid", &book.bids),
+            ];
+            for (key, orders) in sides.iter() {
+                for order in orders.iter() {
+                    text.push_str(&format!("{key}, {}\n", format_order(order)));
+                }
+            }
+
+            let stops = [
+                ("buy-stop", &book.buy_stops),
+                ("sell-stop", &book.sell_stops),
+            ];
+            for (key, stops) in stops.iter() {
+                for (stop, order) in stops.iter() {
+                    text.push_str(&format!("{key}, {stop}, {}\n", format_order(order)));
+                }
+            }
+        }
+
+        let checksum = fnv1a(text.as_bytes());
+        writer.write_all(text.as_bytes())?;
+        writeln!(writer, "end, {checksum:016x}")?;
+        writer.flush()
+    }
+
+    /// Read a snapshot written by `write`.
+    ///
+    /// # Errors
+    /// Return the first line that can't be parsed, or the reason why the
+    /// snapshot can't be trusted.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::command::Command;
+    /// use orderbook::engine::MatchingEngine;
+    /// use orderbook::order::{Order, Side};
+    /// use orderbook::snapshot::Snapshot;
+    ///
+    /// let mut engine = MatchingEngine::new();
+    /// let order = Order::new(1, 1, Side::Bid, 10, 100).with_display(10);
+    /// engine.process(Command::New { symbol: String::from("IBM"), order });
+    ///
+    /// let mut text = Vec::new();
+    /// engine.snapshot(1).write(&mut text).unwrap();
+    ///
+    /// let snapshot = Snapshot::read(text.as_slice()).unwrap();
+    /// assert_eq!(snapshot, engine.snapshot(1));
+    /// ```
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, SnapshotError> {
+        let mut text = String::new();
+        let mut snapshot = None;
+        let mut checksum = None;
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let number = index as u64 + 1;
+
+            // Nothing can follow the checksum
+            if checksum.is_some() {
+                return Err(SnapshotError::Malformed { line: number });
+            }
+
+            let record = csv_reader(line.as_bytes())
+                .records()
+                .next()
+                .and_then(Result::ok)
+                .ok_or(SnapshotError::Malformed { line: number })?;
+            let fields: Vec<&str> = record.iter().collect();
+            let (key, fields) = fields
+                .split_first()
+                .ok_or(SnapshotError::Malformed { line: number })?;
+
+            if *key == "end" {
+                let value = fields
+                    .first()
+                    .and_then(|sum| u64::from_str_radix(sum, 16).ok());
+                checksum = Some(value.ok_or(SnapshotError::Malformed { line: number })?);
+                continue;
+            }
+
+            text.push_str(&line);
+            text.push('\n');
+
+            let fields = Fields { line: number, fields };
+            match (key, &mut snapshot) {
+                (&"snapshot", None) => {
+                    snapshot = Some(Self { seq: fields.parse(0, "seq")?, books: Vec::new() });
+                }
+                (_, Some(snapshot)) => read_line(snapshot, key, &fields)?,
+                _ => return Err(SnapshotError::Malformed { line: number }),
+            }
+        }
+
+        match (snapshot, checksum) {
+            (Some(snapshot), Some(checksum)) if checksum == fnv1a(text.as_bytes()) => Ok(snapshot),
+            (Some(_), Some(_)) => Err(SnapshotError::Checksum),
+            _ => Err(SnapshotError::Truncated),
+        }
+    }
+}
+
+/// Restore the engine from a snapshot, then replay the entries of the journal
+/// that came after it, and return their events. The entries the snapshot
+/// includes are still checked. Recovering into an engine set up like the
+/// original one, with the same mode and instruments, gives the same state as
+/// replaying the whole journal.
+///
+/// # Errors
+/// Return the first entry that can't be read back, without processing any
+/// further, or the first entry the snapshot includes when the journal ends
+/// before it.
+///
+/// # Example
+/// ```
+/// use orderbook::command::Command;
+/// use orderbook::engine::MatchingEngine;
+/// use orderbook::journal::{JournalReader, JournalWriter};
+/// use orderbook::snapshot::recover;
+///
+/// let mut engine = MatchingEngine::new();
+/// let mut journal = JournalWriter::new(Vec::new());
+///
+/// journal.append(&Command::Clock { time: 930 }).unwrap();
+/// let snapshot = engine.snapshot(1);
+/// journal.append(&Command::Clock { time: 1000 }).unwrap();
+///
+/// let journal = journal.into_inner();
+/// let mut reader = JournalReader::new(journal.as_slice());
+/// recover(&snapshot, &mut reader, &mut engine).unwrap();
+///
+/// assert_eq!(reader.seq(), 3);
+/// ```
+pub fn recover<R: BufRead>(
+    snapshot: &Snapshot,
+    journal: &mut JournalReader<R>,
+    engine: &mut MatchingEngine,
+) -> Result<Vec<Event>, JournalError> {
+    let mut events = Vec::new();
+    engine.restore(snapshot);
+
+    for entry in journal.by_ref() {
+        let entry = entry?;
+
+        if entry.seq > snapshot.seq {
+            events.extend(engine.process(entry.command));
+        }
+    }
+
+    if journal.seq() <= snapshot.seq {
+        return Err(JournalError::Truncated { seq: journal.seq() });
+    }
+
+    Ok(events)
+}
+
+/// The fields of a snapshot line after its key, along with its number.
+struct Fields<'a> {
+    line: u64,
+    fields: &'a [&'a str],
+}
+
+impl Fields<'_> {
+    /// Get a field, the name describes it when it's missing.
+    fn get(&self, pos: usize, field: &'static str) -> Result<&str, SnapshotError> {
+        let line = self.line;
+        let error = ParseError::MissingField { field };
+
+        self.fields
+            .get(pos)
+            .copied()
+            .ok_or(SnapshotError::Parse { line, error })
+    }
+
+    /// Parse a field, the name describes it when it's missing or invalid.
+    fn parse<T: FromStr>(&self, pos: usize, field: &'static str) -> Result<T, SnapshotError> {
+        let value = self.get(pos, field)?;
+
+        value.parse().map_err(|_| self.invalid(field, value))
+    }
+
+    /// Parse an optional price, written as `-` when it's unavailable.
+    fn parse_price(&self, pos: usize, field: &'static str) -> Result<Option<u32>, SnapshotError> {
+        match self.get(pos, field)? {
+            "-" => Ok(None),
+            _ => self.parse(pos, field).map(Some),
+        }
+    }
+
+    /// Parse an order written by `format_order`, starting at a field.
+    fn parse_order(&self, pos: usize) -> Result<Order, SnapshotError> {
+        let (id, user_id) = (self.parse(pos, "order")?, self.parse(pos + 1, "user")?);
+        let side = parse_side_from_csv(self.get(pos + 2, "side")?).map_err(|e| self.error(e))?;
+        let mut order = Order::new(
+            id,
+            user_id,
+            side,
+            self.parse(pos + 3, "price")?,
+            self.parse(pos + 4, "quantity")?,
+        );
+        order.hidden = self.parse(pos + 5, "hidden")?;
+
+        // The attributes are restored as they are, not applied to the order
+        for &attribute in self.fields.iter().skip(pos + 6) {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            let parse_u32 = |field| value.parse().map_err(|_| self.invalid(field, value));
+
+            match key.trim() {
+                "market" if value.is_empty() => order.market = true,
+                "display" => order.display = Some(parse_u32("display")?),
+                "post" => {
+                    order.post_only = Some(parse_post_only(value).map_err(|e| self.error(e))?)
+                }
+                "stop" => order.stop = Some(parse_u32("stop")?),
+                "trail" => order.trail = Some(parse_trail(value).map_err(|e| self.error(e))?),
+                "peg" => {
+                    let (reference, offset) = parse_peg(value).map_err(|e| self.error(e))?;
+                    order = order.with_peg(reference, offset);
+                }
+                "min" => order.min_quantity = Some(parse_u32("min")?),
+                "aon" if value.is_empty() => order.all_or_none = true,
+                _ => {
+                    let attribute = attribute.to_string();
+                    return Err(self.error(ParseError::UnknownAttribute { attribute }));
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Locate a parse error.
+    fn error(&self, error: ParseError) -> SnapshotError {
+        SnapshotError::Parse { line: self.line, error }
+    }
+
+    /// Build the error of an invalid value.
+    fn invalid(&self, field: &'static str, value: &str) -> SnapshotError {
+        self.error(ParseError::InvalidField { field, value: value.to_string() })
+    }
+}
+
+/// Read a line of the snapshot after the first one.
+fn read_line(snapshot: &mut Snapshot, key: &str, fields: &Fields) -> Result<(), SnapshotError> {
+    if key == "book" {
+        let book = BookSnapshot {
+            session: Session::default(),
+            schedule: Vec::new(),
+            now: 0,
+            indicative: None,
+            reference_price: None,
+            last_price: None,
+            peg_references: (None, None),
+            pegged: Vec::new(),
+            orders: Vec::new(),
+            asks: Vec::new(),
+            bids: Vec::new(),
+            buy_stops: Vec::new(),
+            sell_stops: Vec::new(),
+        };
+
+        snapshot
+            .books
+            .push((fields.get(0, "symbol")?.to_string(), book));
+        return Ok(());
+    }
+
+    // Everything else belongs to the last book
+    let book = match snapshot.books.last_mut() {
+        Some((_, book)) => book,
+        None => return Err(SnapshotError::Malformed { line: fields.line }),
+    };
+
+    match key {
+        "state" => {
+            book.session = fields.parse(0, "session")?;
+            book.now = fields.parse(1, "time")?;
+            book.last_price = fields.parse_price(2, "last price")?;
+            book.reference_price = fields.parse_price(3, "reference price")?;
+            book.peg_references = (
+                fields.parse_price(4, "peg reference")?,
+                fields.parse_price(5, "peg reference")?,
+            );
+        }
+        "schedule" => {
+            let time = fields.parse(0, "time")?;
+            book.schedule.push((time, fields.parse(1, "session")?));
+        }
+        "indicative" => {
+            let side = match fields.get(2, "side")? {
+                "-" => None,
+                side => Some(parse_side_from_csv(side).map_err(|e| fields.error(e))?),
+            };
+
+            book.indicative = Some(Clearing {
+                price: fields.parse(0, "price")?,
+                volume: fields.parse(1, "volume")?,
+                imbalance: fields.parse(3, "imbalance")?,
+                side,
+            });
+        }
+        "pegged" => book.pegged.push(fields.parse(0, "order")?),
+        "index" => book.orders.push(fields.parse_order(0)?),
+        "ask" => book.asks.push(fields.parse_order(0)?),
+        "bid" => book.bids.push(fields.parse_order(0)?),
+        "buy-stop" | "sell-stop" => {
+            let entry = (fields.parse(0, "stop")?, fields.parse_order(1)?);
+
+            match key {
+                "buy-stop" => book.buy_stops.push(entry),
+                _ => book.sell_stops.push(entry),
+            }
+        }
+        _ => return Err(SnapshotError::Malformed { line: fields.line }),
+    }
+
+    Ok(())
+}
+
+/// Format an order with every field, its attributes as they are.
+fn format_order(order: &Order) -> String {
+    let Order { id, user_id, side, price, quantity, hidden, .. } = order;
+    let side = parse_side_to_csv(*side);
+    let mut fields = vec![format!(
+        "{id}, {user_id}, {side}, {price}, {quantity}, {hidden}"
+    )];
+
+    fields.extend(format_attributes(order));
+    fields.join(", ")
+}
+
+/// Format an optional price, `-` when it's unavailable.
+fn format_price(price: Option<u32>) -> String {
+    price.map_or(String::from("-"), |price| price.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::order::{PegReference, PostOnly, Side, Trail};
+    use crate::order_book::MatchingMode;
+
+    use super::*;
+
+    fn new(order: Order) -> Command {
+        Command::New { symbol: String::from("IBM"), order }
+    }
+
+    // An engine with some of every piece of state
+    fn engine() -> MatchingEngine {
+        let mut engine = MatchingEngine::new().with_mode(MatchingMode::Trade);
+        let commands = vec![
+            new(Order::new(1, 1, Side::Ask, 10, 100).with_display(10)),
+            new(Order::new(2, 2, Side::Ask, 10, 50)),
+            new(Order::new(3, 1, Side::Bid, 10, 15)),
+            new(Order::new(4, 2, Side::Bid, 8, 20).with_post_only(PostOnly::Slide)),
+            new(Order::new(5, 2, Side::Bid, 0, 20).with_peg(PegReference::Primary, -1)),
+            new(Order::new(6, 1, Side::Ask, 0, 30)
+                .with_market()
+                .with_trail(Trail::Percent(10))),
+            new(Order::new(7, 1, Side::Bid, 12, 30)
+                .with_stop(11)
+                .with_min_quantity(5)),
+            Command::Session {
+                symbol: String::from("VAL"),
+                session: Session::ClosingAuction,
+                time: Some(1600),
+            },
+            Command::Clock { time: 930 },
+        ];
+
+        for command in commands {
+            engine.process(command);
+        }
+
+        engine
+    }
+
+    #[test]
+    fn test_write_read() {
+        let snapshot = engine().snapshot(9);
+        let mut text = Vec::new();
+        snapshot.write(&mut text).unwrap();
+
+        let text = String::from_utf8(text).unwrap();
+        let read = Snapshot::read(text.as_bytes()).unwrap();
+
+        assert_eq!(read, snapshot);
+        assert!(text.contains("\nask, 2, 2, S, 10, 45, 0\nask, 1, 1, S, 10, 10, 80, display=10\n"));
+        assert!(text.contains("\nsell-stop, 9, 6, 1, S, 0, 30, 0, market, stop=9, trail=10%\n"));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let mut text = Vec::new();
+        engine().snapshot(9).write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        let altered = text.replace("bid, 4, 2, B, 8", "bid, 4, 2, B, 9");
+        assert!(matches!(
+            Snapshot::read(altered.as_bytes()),
+            Err(SnapshotError::Checksum)
+        ));
+
+        let torn: String = text
+            .lines()
+            .take(5)
+            .map(|line| format!("{line}\n"))
+            .collect();
+        assert!(matches!(
+            Snapshot::read(torn.as_bytes()),
+            Err(SnapshotError::Truncated)
+        ));
+
+        let error = Snapshot::read("snapshot, 1\nbook, IBM\nask, 1\nend, 0\n".as_bytes());
+        assert_eq!(error.unwrap_err().to_string(), "3: Missing user");
+    }
+
+    #[test]
+    fn test_write_read_quoted_symbol() {
+        let mut engine = MatchingEngine::new();
+        let symbol = String::from("A, \"B\"");
+        let order = Order::new(1, 1, Side::Bid, 10, 100);
+        engine.process(Command::New { symbol: symbol.clone(), order });
+
+        let snapshot = engine.snapshot(1);
+        let mut text = Vec::new();
+        snapshot.write(&mut text).unwrap();
+
+        let text = String::from_utf8(text).unwrap();
+        let read = Snapshot::read(text.as_bytes()).unwrap();
+
+        assert!(text.contains("\nbook,\"A, \"\"B\"\"\"\n"));
+        assert_eq!(read.books[0].0, symbol);
+        assert_eq!(read, snapshot);
+    }
+}
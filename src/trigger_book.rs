@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use rb_tree::RBMap;
+
+use crate::order::Order;
+
+/// The pending conditional orders of one side of an order book. Orders are
+/// kept out of the book until a trade triggers them, they're stored in queues
+/// keyed by stop price in a map-indexable red-black tree.
+pub struct TriggerBook {
+    stops: RBMap<u32, VecDeque<Order>>,
+}
+
+impl TriggerBook {
+    #[must_use]
+    pub fn new() -> Self {
+        Self { stops: RBMap::new() }
+    }
+
+    /// Append an order to the queue of the provided stop price. The complexity
+    /// for this operation is *O*(log *n*), where *n* is the size of the tree.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::trigger_book::TriggerBook;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut trigger_book = TriggerBook::new();
+    /// trigger_book.append(12, Order::new(1, 1, Side::Bid, 0, 100));
+    ///
+    /// assert_eq!(trigger_book.len(), 1);
+    /// ```
+    pub fn append(&mut self, stop: u32, order: Order) {
+        if let Some(queue) = self.stops.get_mut(&stop) {
+            queue.push_back(order);
+            return;
+        }
+
+        self.stops.insert(stop, VecDeque::from(vec![order]));
+    }
+
+    /// Remove an order from the queue of the provided stop price, and returns
+    /// it. The complexity for this operation is *O*(log *n* + *m*), where *n*
+    /// is the size of the tree and *m* is the length of the queue.
+    ///
+    /// # Example
+    /// ```
+    /// use orderbook::trigger_book::TriggerBook;
+    /// use orderbook::order::{Order, Side};
+    ///
+    /// let mut trigger_book = TriggerBook::new();
+    /// trigger_book.append(12, Order::new(1, 1, Side::Bid, 0, 100));
+    /// trigger_book.remove(12, 1);
+    ///
+    /// assert!(trigger_book.is_empty());
+    /// ```
+    pub fn remove(&mut self, stop: u32, order_id: usize) -> Option<Order> {
+        // Searching a red-black tree is O(log n)
+        let queue = self.stops.get_mut(&stop)?;
+        // Removing from a queue is O(m)
+        let pos = queue.iter().position(|o| o.id == order_id)?;
+        let removed = queue.remove(pos);
+
+        if queue.is_empty() {
+            self.stops.remove(&stop);
+        }
+
+        removed
+    }
+
+    /// Remove and return every order whose stop price is lower than or equal
+    /// to the provided price, sorted by ascending stop price and then by time
+    /// priority. This is what a rising trade price triggers on the buy side.
+    /// The complexity for this operation is *O*(*n* + *k*), where *n* is the
+    /// size of the tree and *k* is the number of triggered orders.
+    pub fn drain_up_to(&mut self, price: u32) -> Vec<Order> {
+        let stops = self
+            .stops
+            .keys()
+            .copied()
+            .take_while(|&stop| stop <= price)
+            .collect();
+        self.drain(stops)
+    }
+
+    /// Remove and return every order whose stop price is greater than or equal
+    /// to the provided price, sorted by descending stop price and then by time
+    /// priority. This is what a falling trade price triggers on the sell side.
+    /// The complexity for this operation is *O*(*n* + *k*), where *n* is the
+    /// size of the tree and *k* is the number of triggered orders.
+    pub fn drain_down_to(&mut self, price: u32) -> Vec<Order> {
+        let mut stops: Vec<u32> = self
+            .stops
+            .keys()
+            .copied()
+            .filter(|&stop| stop >= price)
+            .collect();
+        stops.reverse();
+        self.drain(stops)
+    }
+
+    /// Remove the queues for the provided stop prices, and return their orders
+    /// in the same sequence.
+    fn drain(&mut self, stops: Vec<u32>) -> Vec<Order> {
+        stops
+            .iter()
+            .filter_map(|stop| self.stops.remove(stop))
+            .flatten()
+            .collect()
+    }
+
+    /// Return every pending order sorted by ascending stop price and then by
+    /// time priority. The complexity for this operation is *O*(*n* + *k*),
+    /// where *n* is the size of the tree and *k* is the number of orders.
+    #[must_use]
+    pub fn orders(&self) -> Vec<Order> {
+        self.stops.values().flatten().copied().collect()
+    }
+
+    /// Return every pending order along with the stop price it's queued at,
+    /// sorted the same way as `orders`. Appending them to an empty book in
+    /// this sequence rebuilds the same queues. The complexity for this
+    /// operation is *O*(*n* + *k*), where *n* is the size of the tree and *k*
+    /// is the number of orders.
+    #[must_use]
+    pub fn entries(&self) -> Vec<(u32, Order)> {
+        self.stops
+            .iter()
+            .flat_map(|(&stop, queue)| queue.iter().map(move |&order| (stop, order)))
+            .collect()
+    }
+
+    /// The number of pending orders, computed in *O*(*n*) where *n* is the
+    /// size of the tree.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.stops.values().map(VecDeque::len).sum()
+    }
+
+    /// Whether there are no pending orders.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+}
+
+impl Default for TriggerBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::order::Side;
+
+    use super::*;
+
+    #[test]
+    fn test_append_remove() {
+        let mut trigger_book = TriggerBook::new();
+
+        trigger_book.append(10, Order::new(1, 1, Side::Bid, 0, 1));
+        trigger_book.append(10, Order::new(2, 1, Side::Bid, 0, 1));
+        trigger_book.append(11, Order::new(3, 1, Side::Bid, 0, 1));
+
+        assert_eq!(trigger_book.len(), 3);
+        assert_eq!(trigger_book.remove(10, 2).unwrap().id, 2);
+        assert_eq!(trigger_book.remove(10, 2), None);
+        assert_eq!(trigger_book.remove(12, 3), None);
+        assert_eq!(trigger_book.len(), 2);
+    }
+
+    #[test]
+    fn test_drain_up_to() {
+        let mut trigger_book = TriggerBook::new();
+
+        trigger_book.append(12, Order::new(1, 1, Side::Bid, 0, 1));
+        trigger_book.append(10, Order::new(2, 1, Side::Bid, 0, 1));
+        trigger_book.append(10, Order::new(3, 1, Side::Bid, 0, 1));
+        trigger_book.append(11, Order::new(4, 1, Side::Bid, 0, 1));
+
+        let ids: Vec<_> = trigger_book.drain_up_to(11).iter().map(|o| o.id).collect();
+
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert_eq!(trigger_book.len(), 1);
+    }
+
+    #[test]
+    fn test_drain_down_to() {
+        let mut trigger_book = TriggerBook::new();
+
+        trigger_book.append(8, Order::new(1, 1, Side::Ask, 0, 1));
+        trigger_book.append(10, Order::new(2, 1, Side::Ask, 0, 1));
+        trigger_book.append(9, Order::new(3, 1, Side::Ask, 0, 1));
+        trigger_book.append(10, Order::new(4, 1, Side::Ask, 0, 1));
+
+        let ids: Vec<_> = trigger_book.drain_down_to(9).iter().map(|o| o.id).collect();
+
+        assert_eq!(ids, vec![2, 4, 3]);
+        assert!(!trigger_book.is_empty());
+    }
+
+    #[test]
+    fn test_orders() {
+        let mut trigger_book = TriggerBook::new();
+
+        trigger_book.append(9, Order::new(1, 1, Side::Ask, 0, 1));
+        trigger_book.append(8, Order::new(2, 1, Side::Ask, 0, 1));
+        trigger_book.append(9, Order::new(3, 1, Side::Ask, 0, 1));
+
+        let ids: Vec<_> = trigger_book.orders().iter().map(|o| o.id).collect();
+
+        assert_eq!(ids, vec![2, 1, 3]);
+
+        let stops: Vec<_> = trigger_book
+            .entries()
+            .iter()
+            .map(|&(stop, o)| (stop, o.id))
+            .collect();
+
+        assert_eq!(stops, vec![(8, 2), (9, 1), (9, 3)]);
+    }
+}
@@ -0,0 +1,111 @@
+use orderbook::backtest::{Actions, Backtest, Execution, Latency, Report, Strategy};
+use orderbook::generator::Generator;
+use orderbook::order::Side;
+use orderbook::order_book::{OrderBook, OrderOutcome};
+
+// Join the best bid and ask with one lot each, keep the quotes there as the
+// book moves, and flatten the position with a market order past a limit
+#[derive(Default)]
+struct MarketMaker {
+    quotes: [Option<usize>; 2],
+    position: i64,
+}
+
+impl Strategy for MarketMaker {
+    fn on_market(&mut self, order_book: &OrderBook, _: &OrderOutcome, actions: &mut Actions) {
+        let (bid, ask) = match (order_book.best_bid_price(), order_book.best_ask_price()) {
+            (Some(bid), Some(ask)) => (bid, ask),
+            _ => return,
+        };
+
+        for (quote, &(side, price)) in self
+            .quotes
+            .iter_mut()
+            .zip([(Side::Bid, bid), (Side::Ask, ask)].iter())
+        {
+            // Filled quotes are replaced
+            match quote.and_then(|order_id| order_book.order_status(order_id)) {
+                Some(order) if order.price == price => {}
+                Some(order) => actions.amend(order.id, price, 10),
+                None => *quote = Some(actions.submit(side, price, 10)),
+            }
+        }
+    }
+
+    fn on_fill(&mut self, _: &OrderBook, fill: &Execution, actions: &mut Actions) {
+        self.position += match fill.side {
+            Side::Bid => i64::from(fill.quantity),
+            Side::Ask => -i64::from(fill.quantity),
+        };
+
+        if self.position.abs() >= 50 {
+            let side = if self.position > 0 {
+                Side::Ask
+            } else {
+                Side::Bid
+            };
+            actions.market(side, self.position.unsigned_abs() as u32);
+        }
+    }
+}
+
+fn run(seed: u64, latency: Latency) -> (Report, i64) {
+    let flow = Generator::new()
+        .with_seed(seed)
+        .with_users(5)
+        .with_rate(10.0)
+        .with_volatility(2)
+        .take(5000);
+    let mut backtest = Backtest::new("SYM1", MarketMaker::default()).with_user_latency(0, latency);
+
+    backtest.run(flow);
+    assert_eq!(backtest.order_book().check_invariants(), Ok(()));
+
+    (backtest.report().clone(), backtest.strategy().position)
+}
+
+#[test]
+fn test_market_maker() {
+    let (report, position) = run(11, Latency::default());
+
+    // The position and the cash are the sums of the executions
+    let signed = |fill: &Execution, value: i64| match fill.side {
+        Side::Bid => value,
+        Side::Ask => -value,
+    };
+    let quantity = |fill: &Execution| i64::from(fill.quantity);
+
+    assert!(!report.fills.is_empty());
+    assert_eq!(report.position, position);
+    assert_eq!(
+        report.position,
+        report
+            .fills
+            .iter()
+            .map(|fill| signed(fill, quantity(fill)))
+            .sum::<i64>()
+    );
+    assert_eq!(
+        report.cash,
+        -report
+            .fills
+            .iter()
+            .map(|fill| signed(fill, quantity(fill) * i64::from(fill.price)))
+            .sum::<i64>()
+    );
+
+    // Quotes mostly rest, flattening takes liquidity
+    assert!(report.fills.iter().any(|fill| fill.passive));
+    assert!(report.fills.iter().any(|fill| !fill.passive));
+    assert!(report.queue.rested >= report.queue.filled);
+    assert!(report.queue.filled > 0);
+}
+
+#[test]
+fn test_deterministic() {
+    let latency = Latency::new(1, 1);
+
+    assert_eq!(run(5, latency), run(5, latency));
+    assert_ne!(run(5, latency).0, run(6, latency).0);
+    assert_ne!(run(5, latency).0, run(5, Latency::default()).0);
+}
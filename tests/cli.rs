@@ -0,0 +1,81 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Run the binary with the provided arguments, and return the lines it wrote
+fn run(args: &[&str]) -> Vec<String> {
+    run_with_input(args, "")
+}
+
+// Run the binary with the provided arguments and standard input, and return
+// the lines it wrote
+fn run_with_input(args: &[&str], input: &str) -> Vec<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_orderbook"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+// The default settings, alone or with the instruments as in the Dockerfile,
+// reproduce the expected output but for its comments and blank lines
+#[test]
+fn test_default_output() {
+    let expected: Vec<String> = fs::read_to_string("files/output_file.csv")
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(String::from)
+        .collect();
+
+    assert_eq!(run(&["--input", "files/input_file.csv"]), expected);
+    assert_eq!(
+        run(&[
+            "--input",
+            "files/input_file.csv",
+            "--instruments",
+            "files/instruments.csv"
+        ]),
+        expected
+    );
+}
+
+// Remainders the book cancels on its own aren't acknowledged, the order they
+// belong to already was
+#[test]
+fn test_quiet_stats() {
+    let input = "N, 1, IBM, 10, 100, S, 1, min=100
+N, 1, IBM, 9, 10, S, 2
+N, 2, IBM, 10, 30, B, 101
+";
+
+    assert_eq!(
+        run_with_input(&["--mode", "trade"], input).last().unwrap(),
+        "C, 2, 101, 20"
+    );
+    assert_eq!(
+        run_with_input(&["--mode", "trade", "--quiet"], input),
+        vec!["Q, 3, 0, 1, 10"]
+    );
+}
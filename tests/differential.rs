@@ -0,0 +1,51 @@
+use std::fs::File;
+
+use orderbook::book_side::Backend;
+use orderbook::differential::compare;
+use orderbook::engine::MatchingEngine;
+use orderbook::instrument::Registry;
+use orderbook::parser::CommandReader;
+use orderbook::MatchingMode;
+
+// Run an input file through engines with either backend, failing with the
+// first outcome they disagree on
+fn check(input: &str, mode: MatchingMode, registry: Option<&Registry>) {
+    let new_engine = |backend| {
+        let engine = MatchingEngine::new().with_mode(mode).with_backend(backend);
+
+        match registry {
+            Some(registry) => engine.with_registry(registry.clone()),
+            None => engine,
+        }
+    };
+
+    let reader = CommandReader::new(input, File::open(input).unwrap());
+    let comparison = compare(
+        reader,
+        &mut new_engine(Backend::RedBlackTree),
+        &mut new_engine(Backend::BTree),
+    )
+    .unwrap();
+
+    assert!(comparison.commands > 0);
+    if let Some(divergence) = &comparison.divergence {
+        panic!("\n{}:{}", input, divergence);
+    }
+    assert!(comparison.same_books);
+}
+
+#[test]
+fn test_backends() {
+    for input in ["files/input_file.csv", "files/input_file_extra.csv"].iter() {
+        for &mode in [MatchingMode::Trade, MatchingMode::Reject].iter() {
+            check(input, mode, None);
+        }
+    }
+}
+
+#[test]
+fn test_backends_registry() {
+    let registry = Registry::from_reader(File::open("files/instruments.csv").unwrap()).unwrap();
+
+    check("files/input_file.csv", MatchingMode::Trade, Some(&registry));
+}
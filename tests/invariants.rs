@@ -0,0 +1,144 @@
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+use orderbook::command::{Command, FlushPolicy};
+use orderbook::engine::MatchingEngine;
+use orderbook::order::{Order, PegReference, PostOnly, Side, Trail};
+use orderbook::session::Session;
+use orderbook::MatchingMode;
+
+// The kind of a new order, on top of its limit price and quantity
+#[derive(Clone, Debug)]
+enum Kind {
+    Limit,
+    Market,
+    Iceberg(u32),
+    PostOnly(PostOnly),
+    Stop(u32),
+    Trailing(u32),
+    Pegged(PegReference, i32),
+    MinQuantity(u32),
+    AllOrNone,
+}
+
+// A command with the order IDs left out, they're numbered as the orders come
+#[derive(Clone, Debug)]
+enum Step {
+    New(bool, usize, Side, u32, u32, Kind),
+    Cancel(usize),
+    Session(bool, Session, Option<u64>),
+    Clock(u64),
+    Flush(FlushPolicy),
+}
+
+fn kind() -> impl Strategy<Value = Kind> {
+    prop_oneof![
+        8 => Just(Kind::Limit),
+        1 => Just(Kind::Market),
+        1 => (1..=20u32).prop_map(Kind::Iceberg),
+        1 => prop_oneof![Just(PostOnly::Reject), Just(PostOnly::Slide)].prop_map(Kind::PostOnly),
+        1 => (1..=10u32).prop_map(Kind::Stop),
+        1 => (1..=3u32).prop_map(Kind::Trailing),
+        1 => (
+            prop_oneof![
+                Just(PegReference::Primary),
+                Just(PegReference::Market),
+                Just(PegReference::Midpoint),
+            ],
+            -2..=2i32,
+        )
+            .prop_map(|(reference, offset)| Kind::Pegged(reference, offset)),
+        1 => (1..=50u32).prop_map(Kind::MinQuantity),
+        1 => Just(Kind::AllOrNone),
+    ]
+}
+
+fn session() -> impl Strategy<Value = Session> {
+    prop_oneof![
+        Just(Session::PreOpen),
+        Just(Session::OpeningAuction),
+        Just(Session::Continuous),
+        Just(Session::VolatilityAuction),
+        Just(Session::Halted),
+        Just(Session::ClosingAuction),
+        Just(Session::Closed),
+    ]
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    let side = prop_oneof![Just(Side::Bid), Just(Side::Ask)];
+
+    prop_oneof![
+        12 => (any::<bool>(), 1..=3usize, side, 1..=10u32, 1..=50u32, kind()).prop_map(
+            |(symbol, user_id, side, price, quantity, kind)| {
+                Step::New(symbol, user_id, side, price, quantity, kind)
+            }
+        ),
+        4 => (0..64usize).prop_map(Step::Cancel),
+        2 => (any::<bool>(), session(), option::of(0..100u64))
+            .prop_map(|(symbol, session, time)| Step::Session(symbol, session, time)),
+        1 => (0..100u64).prop_map(Step::Clock),
+        1 => prop_oneof![Just(FlushPolicy::Reset), Just(FlushPolicy::Close)].prop_map(Step::Flush),
+    ]
+}
+
+// Build the command of a step, new orders get the next ID
+fn command(step: &Step, next_id: &mut usize) -> Command {
+    let symbol = |other: bool| String::from(if other { "AAPL" } else { "IBM" });
+
+    match step.clone() {
+        Step::New(other, user_id, side, price, quantity, kind) => {
+            let order = Order::new(*next_id, user_id, side, price, quantity);
+            *next_id += 1;
+
+            let order = match kind {
+                Kind::Limit => order,
+                Kind::Market => order.with_market(),
+                Kind::Iceberg(display) => order.with_display(display),
+                Kind::PostOnly(post_only) => order.with_post_only(post_only),
+                Kind::Stop(stop) => order.with_stop(stop),
+                Kind::Trailing(offset) => order.with_trail(Trail::Offset(offset)),
+                Kind::Pegged(reference, offset) => order.with_peg(reference, offset),
+                Kind::MinQuantity(min) => order.with_min_quantity(min),
+                Kind::AllOrNone => order.with_all_or_none(),
+            };
+
+            Command::New { symbol: symbol(other), order }
+        }
+        Step::Cancel(order_id) => Command::Cancel { user_id: 1, order_id },
+        Step::Session(other, session, time) => {
+            Command::Session { symbol: symbol(other), session, time }
+        }
+        Step::Clock(time) => Command::Clock { time },
+        Step::Flush(policy) => Command::Flush { policy },
+    }
+}
+
+// Run the steps through an engine, checking its invariants after each of them
+fn check(steps: &[Step], mode: MatchingMode) -> Result<(), TestCaseError> {
+    let mut engine = MatchingEngine::new().with_mode(mode);
+    let mut next_id = 1;
+
+    for step in steps {
+        engine.process(command(step, &mut next_id));
+
+        if let Err(error) = engine.check_invariants() {
+            return Err(TestCaseError::fail(format!("{error} after {step:?}")));
+        }
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_invariants_trade_mode(steps in vec(step(), 1..200)) {
+        check(&steps, MatchingMode::Trade)?;
+    }
+
+    #[test]
+    fn test_invariants_reject_mode(steps in vec(step(), 1..200)) {
+        check(&steps, MatchingMode::Reject)?;
+    }
+}
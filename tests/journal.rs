@@ -0,0 +1,54 @@
+use std::fs::File;
+
+use orderbook::engine::{Event, MatchingEngine};
+use orderbook::journal::{replay, JournalWriter};
+use orderbook::parser::{format_outcome, CommandReader};
+use orderbook::MatchingMode;
+
+// Format events as the lines of the output file
+fn output(events: &[Event]) -> String {
+    let mut output = String::new();
+
+    for line in events
+        .iter()
+        .flat_map(|event| format_outcome(&event.outcome))
+    {
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    output
+}
+
+// Process the commands of an input file while journaling them, then check
+// that replaying the journal into a new engine writes the same output
+fn check(input: &str, mode: MatchingMode) {
+    let mut engine = MatchingEngine::new().with_mode(mode);
+    let mut journal = JournalWriter::new(Vec::new());
+    let mut events = Vec::new();
+
+    for command in CommandReader::new(input, File::open(input).unwrap()) {
+        let command = command.unwrap();
+
+        journal.append(&command).unwrap();
+        events.extend(engine.process(command));
+    }
+
+    let journal = journal.into_inner();
+    let mut engine = MatchingEngine::new().with_mode(mode);
+    let replayed = replay(journal.as_slice(), &mut engine).unwrap();
+
+    assert!(!events.is_empty());
+    assert_eq!(output(&replayed), output(&events));
+}
+
+#[test]
+fn test_replay() {
+    check("files/input_file.csv", MatchingMode::Reject);
+    check("files/input_file.csv", MatchingMode::Trade);
+}
+
+#[test]
+fn test_replay_extra() {
+    check("files/input_file_extra.csv", MatchingMode::Trade);
+}
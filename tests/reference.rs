@@ -0,0 +1,102 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use orderbook::book_side::Backend;
+use orderbook::order::{Order, Side};
+use orderbook::reference::ReferenceBook;
+use orderbook::{MatchingMode, OrderBook};
+
+// A command fed to both books, cancels pick one of the resting orders
+#[derive(Clone, Debug)]
+enum Action {
+    Limit(Side, u32, u32),
+    Market(Side, u32),
+    Cancel(usize),
+}
+
+fn side() -> impl Strategy<Value = Side> {
+    prop_oneof![Just(Side::Bid), Just(Side::Ask)]
+}
+
+// Prices are drawn from a narrow range, so that orders often share a level
+// and cross the book
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        6 => (side(), 1..=10u32, 1..=50u32).prop_map(|(side, price, quantity)| {
+            Action::Limit(side, price, quantity)
+        }),
+        1 => (side(), 1..=100u32).prop_map(|(side, quantity)| Action::Market(side, quantity)),
+        3 => any::<usize>().prop_map(Action::Cancel),
+    ]
+}
+
+// Run the actions through both books, and check that every outcome and the
+// resting orders after each action are the same
+fn check(actions: &[Action], mode: MatchingMode, backend: Backend) -> Result<(), TestCaseError> {
+    let mut reference = ReferenceBook::new().with_mode(mode);
+    let mut order_book = OrderBook::new().with_mode(mode).with_backend(backend);
+
+    for (id, action) in (1..).zip(actions) {
+        let user_id = id % 3 + 1;
+
+        let (expected, actual) = match *action {
+            Action::Limit(side, price, quantity) => {
+                let order = Order::new(id, user_id, side, price, quantity);
+                (reference.submit(order), order_book.submit(order))
+            }
+            Action::Market(side, quantity) => {
+                let order = Order::new(id, user_id, side, 0, quantity).with_market();
+                (reference.submit(order), order_book.submit(order))
+            }
+            Action::Cancel(index) => {
+                let mut resting = reference.orders(Side::Bid);
+                resting.extend(reference.orders(Side::Ask));
+
+                if resting.is_empty() {
+                    continue;
+                }
+
+                let order = resting[index % resting.len()];
+                (
+                    reference.cancel_order(order.id),
+                    order_book.cancel_order(order.user_id, order.id),
+                )
+            }
+        };
+
+        prop_assert_eq!(expected, actual, "outcomes of {:?}", action);
+
+        let snapshot = order_book.snapshot();
+        prop_assert_eq!(
+            reference.orders(Side::Ask),
+            snapshot.asks,
+            "asks after {:?}",
+            action
+        );
+        prop_assert_eq!(
+            reference.orders(Side::Bid),
+            snapshot.bids,
+            "bids after {:?}",
+            action
+        );
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_trade_mode(actions in vec(action(), 1..200)) {
+        check(&actions, MatchingMode::Trade, Backend::RedBlackTree)?;
+    }
+
+    #[test]
+    fn test_trade_mode_b_tree(actions in vec(action(), 1..200)) {
+        check(&actions, MatchingMode::Trade, Backend::BTree)?;
+    }
+
+    #[test]
+    fn test_reject_mode(actions in vec(action(), 1..200)) {
+        check(&actions, MatchingMode::Reject, Backend::RedBlackTree)?;
+    }
+}
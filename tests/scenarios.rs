@@ -0,0 +1,45 @@
+use std::fs;
+
+use orderbook::engine::MatchingEngine;
+use orderbook::scenario::run_scenarios;
+use orderbook::MatchingMode;
+
+// Run the scenarios of an input file against its expected output, failing
+// with the report of every scenario when any of them doesn't pass
+fn check(input: &str, expected: &str, mode: MatchingMode) {
+    let text = fs::read_to_string(input).unwrap();
+    let expected = fs::read_to_string(expected).unwrap();
+    let reports = run_scenarios(input, &text, &expected, || {
+        MatchingEngine::new().with_mode(mode)
+    })
+    .unwrap();
+
+    assert!(!reports.is_empty());
+    assert!(
+        reports.iter().all(|report| report.passed()),
+        "\n{}",
+        reports
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
+
+#[test]
+fn test_scenarios() {
+    check(
+        "files/input_file.csv",
+        "files/output_file.csv",
+        MatchingMode::Reject,
+    );
+}
+
+#[test]
+fn test_scenarios_extra() {
+    check(
+        "files/input_file_extra.csv",
+        "files/output_file_extra.csv",
+        MatchingMode::Trade,
+    );
+}
@@ -0,0 +1,138 @@
+use std::fs;
+
+use orderbook::command::Command;
+use orderbook::engine::{Event, MatchingEngine};
+use orderbook::journal::{JournalReader, JournalWriter};
+use orderbook::parser::CommandReader;
+use orderbook::snapshot::{recover, Snapshot};
+use orderbook::MatchingMode;
+
+// Every kind of order and session change, across two books
+const INPUT: &str = "
+N, 1, IBM, 10, 100, S, 1, display=10
+N, 2, IBM, 10, 50, S, 2
+N, 1, IBM, 10, 15, B, 3
+N, 2, IBM, 8, 20, B, 4, post=slide
+N, 2, IBM, 0, 20, B, 5, peg=primary-1
+N, 1, IBM, 0, 30, S, 6, trail=10%
+N, 1, IBM, 12, 30, B, 7, stop=11, min=5
+N, 3, VAL, 20, 10, B, 8, aon
+X, VAL, closing, 1600
+X, VAL, closed, 1700
+K, 930
+N, 3, IBM, 11, 60, B, 9
+N, 4, IBM, 9, 40, S, 10
+C, 2, 4
+K, 1600
+N, 4, VAL, 19, 10, S, 11
+N, 4, VAL, 21, 5, S, 12
+X, IBM, opening
+N, 5, IBM, 12, 20, B, 13
+N, 5, IBM, 9, 25, S, 14
+X, IBM, continuous
+K, 1700
+N, 1, IBM, 12, 100, S, 15
+N, 3, IBM, 30, 5, S, 17
+N, 4, IBM, 30, 200, B, 18
+F, close
+N, 1, IBM, 10, 10, B, 16
+";
+
+// Read the commands of an input file
+fn commands(input: &str) -> Vec<Command> {
+    CommandReader::new("input.csv", input.as_bytes())
+        .map(Result::unwrap)
+        .collect()
+}
+
+// Journal the commands while processing them, taking a snapshot after the
+// first ones, then recover a new engine from the snapshot and the journal:
+// it must end up in the same state as the original engine, and replaying the
+// tail of the journal must give the same events
+fn check(commands: &[Command], mode: MatchingMode) {
+    for split in 0..=commands.len() {
+        let mut engine = MatchingEngine::new().with_mode(mode);
+        let mut journal = JournalWriter::new(Vec::new());
+        let mut snapshot = Vec::new();
+        let mut tail: Vec<Event> = Vec::new();
+
+        for (index, command) in commands.iter().enumerate() {
+            if index == split {
+                engine
+                    .snapshot(journal.seq() - 1)
+                    .write(&mut snapshot)
+                    .unwrap();
+            }
+
+            journal.append(command).unwrap();
+            let events = engine.process(command.clone());
+
+            if index >= split {
+                tail.extend(events);
+            }
+        }
+
+        if split == commands.len() {
+            engine
+                .snapshot(journal.seq() - 1)
+                .write(&mut snapshot)
+                .unwrap();
+        }
+
+        let snapshot = Snapshot::read(snapshot.as_slice()).unwrap();
+        let journal = journal.into_inner();
+        let mut reader = JournalReader::new(journal.as_slice());
+        let mut recovered = MatchingEngine::new().with_mode(mode);
+        let events = recover(&snapshot, &mut reader, &mut recovered).unwrap();
+
+        let seq = commands.len() as u64;
+        assert_eq!(events, tail, "split at {split}");
+        assert_eq!(
+            recovered.snapshot(seq),
+            engine.snapshot(seq),
+            "split at {split}"
+        );
+        assert_eq!(
+            recovered.open_orders(),
+            engine.open_orders(),
+            "split at {split}"
+        );
+    }
+}
+
+#[test]
+fn test_recover() {
+    let commands = commands(INPUT);
+
+    check(&commands, MatchingMode::Trade);
+    check(&commands, MatchingMode::Reject);
+}
+
+#[test]
+fn test_recover_files() {
+    let input = fs::read_to_string("files/input_file.csv").unwrap();
+    check(&commands(&input), MatchingMode::Reject);
+
+    let input = fs::read_to_string("files/input_file_extra.csv").unwrap();
+    check(&commands(&input), MatchingMode::Trade);
+}
+
+#[test]
+fn test_recover_truncated() {
+    let commands = commands(INPUT);
+    let mut engine = MatchingEngine::new();
+    let mut journal = JournalWriter::new(Vec::new());
+
+    for command in &commands[..3] {
+        journal.append(command).unwrap();
+        engine.process(command.clone());
+    }
+
+    // The snapshot is ahead of the journal
+    let snapshot = engine.snapshot(5);
+    let journal = journal.into_inner();
+    let mut reader = JournalReader::new(journal.as_slice());
+    let error = recover(&snapshot, &mut reader, &mut MatchingEngine::new()).unwrap_err();
+
+    assert_eq!(error.to_string(), "Missing entry 4");
+}